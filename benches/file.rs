@@ -4,9 +4,11 @@ use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion};
 
 use criterion::measurement::Measurement;
 use raid::file::FileHandler;
-use raid::raid::distributed::Checkpoint;
+use raid::raid::distributed::{Aggregation, Checkpoint, Repair};
 use raid::raid::controller::Controller;
-use raid::raid::RAID;
+#[cfg(target_os = "linux")]
+use raid::raid::uring::UringController;
+use raid::raid::{Config, RAID};
 use rand::seq::SliceRandom;
 use rand::{RngCore, SeedableRng};
 use seq_macro::seq;
@@ -60,6 +62,10 @@ fn criterion_benches(c: &mut Criterion) {
         criterion_write_single::<N, 2, X, _>(c.benchmark_group(format!("dwrite_{}_2", N)));
     });
 
+    criterion_aggregation::<6, 2, X, _>(c.benchmark_group("aggregation62"));
+    criterion_aggregation::<6, 4, X, _>(c.benchmark_group("aggregation64"));
+    criterion_aggregation::<12, 4, X, _>(c.benchmark_group("aggregation124"));
+
     criterion_read_single::<6, 0, X, _>(c.benchmark_group("cread60"));
     criterion_read_single::<6, 1, X, _>(c.benchmark_group("cread61"));
     criterion_read_single::<6, 2, X, _>(c.benchmark_group("cread62"));
@@ -110,6 +116,17 @@ fn criterion_read<const D: usize, const C: usize, const X: usize, M: Measurement
         });
     }
     file_handler.shutdown();
+
+    #[cfg(target_os = "linux")]
+    {
+        let file_handler = prepare_read::<UringController<D, C, X>, D, C, X>();
+        for length in &lengths {
+            group.bench_function(format!("uring_{length}"), |b| {
+                b.iter(|| file_handler.read_file(&format!("{length}")))
+            });
+        }
+        file_handler.shutdown();
+    }
     group.finish();
 }
 fn criterion_read_single<const D: usize, const C: usize, const X: usize, M: Measurement + 'static>(
@@ -138,6 +155,15 @@ fn criterion_read_single<const D: usize, const C: usize, const X: usize, M: Meas
         b.iter(|| file_handler.read_file(&format!("{length}")))
     });
     file_handler.shutdown();
+
+    #[cfg(target_os = "linux")]
+    {
+        let file_handler = prepare_read::<UringController<D, C, X>, D, C, X>();
+        group.bench_function(format!("uring_{length}"), |b| {
+            b.iter(|| file_handler.read_file(&format!("{length}")))
+        });
+        file_handler.shutdown();
+    }
     group.finish();
 }
 
@@ -187,6 +213,20 @@ fn criterion_write<const D: usize, const C: usize, const X: usize, M: Measuremen
         });
     }
     file_handler.shutdown();
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut file_handler = prepare_read::<UringController<D, C, X>, D, C, X>();
+        for file in &files {
+            group.bench_function(format!("uring_{}", file.len()), |b| {
+                b.iter(|| {
+                    file_handler.add_file("s".to_string(), file);
+                    file_handler.ping();
+                })
+            });
+        }
+        file_handler.shutdown();
+    }
     group.finish();
 }
 
@@ -232,6 +272,59 @@ fn criterion_write_single<
         })
     });
     file_handler.shutdown();
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut file_handler = prepare_read::<UringController<D, C, X>, D, C, X>();
+        group.bench_function(format!("uring_{}", file.len()), |b| {
+            b.iter(|| {
+                file_handler.add_file("s".to_string(), &file);
+                file_handler.ping();
+            })
+        });
+        file_handler.shutdown();
+    }
+    group.finish();
+}
+
+fn criterion_aggregation<const D: usize, const C: usize, const X: usize, M: Measurement + 'static>(
+    mut group: BenchmarkGroup<M>,
+) where
+    [(); X * D]:,
+    [(); D * X]:,
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    group
+        .sample_size(100)
+        .measurement_time(Duration::from_nanos(1));
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+
+    let length = ((100 * 6 - 1) * X / 2 + X) / (10);
+    let mut file = vec![0u8; length];
+    rng.fill_bytes(&mut file);
+
+    for (name, aggregation) in [
+        ("direct", Aggregation::Direct),
+        ("chain", Aggregation::Chain),
+        ("tree", Aggregation::Tree),
+    ] {
+        let config = Config {
+            aggregation,
+            ..Config::default()
+        };
+        let mut file_handler = prepare_read_with::<Checkpoint<D, C, X>, D, C, X>(config);
+        group.bench_function(format!("{name}_{}", file.len()), |b| {
+            b.iter(|| {
+                file_handler.add_file("s".to_string(), &file);
+                file_handler.ping();
+            })
+        });
+        file_handler.shutdown();
+    }
     group.finish();
 }
 
@@ -267,10 +360,37 @@ fn criterion_recover<const D: usize, const C: usize, const X: usize, M: Measurem
         })
     });
     file_handler.shutdown();
+    let config = Config {
+        repair: Repair::Pipelined,
+        ..Config::default()
+    };
+    let file_handler = prepare_read_with::<Checkpoint<D, C, X>, D, C, X>(config);
+    group.bench_function("pipelined recover", |b| {
+        b.iter(|| {
+            file_handler.destroy_devices(&failures);
+            file_handler.ping();
+        })
+    });
+    file_handler.shutdown();
     group.finish()
 }
 
 fn prepare_read<R, const D: usize, const C: usize, const X: usize>() -> FileHandler<R, D, C, X>
+where
+    R: RAID<D, C, X>,
+    [(); X * D]:,
+    [(); D * X]:,
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    prepare_read_with(Config::default())
+}
+
+fn prepare_read_with<R, const D: usize, const C: usize, const X: usize>(
+    config: Config,
+) -> FileHandler<R, D, C, X>
 where
     R: RAID<D, C, X>,
     [(); X * D]:,
@@ -282,7 +402,7 @@ where
 {
     let mut rng = rand::rngs::StdRng::seed_from_u64(1);
     let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("nodes");
-    let mut file_handler: FileHandler<R, D, C, X> = FileHandler::new(path);
+    let mut file_handler: FileHandler<R, D, C, X> = FileHandler::with_config(path, config);
     let mut lengths: [usize; SAMPLE_POINTS] = core::array::from_fn(|i| {
         ((100 * 6 - 1) * X * i + X * SAMPLE_POINTS) / (10 * SAMPLE_POINTS)
     });
@@ -3,7 +3,7 @@ use std::convert::TryInto;
 use std::path::PathBuf;
 
 use crate::galois;
-use crate::raid::RAID;
+use crate::raid::{Config, RAID};
 
 #[derive(Debug, Clone)]
 struct FileLocation {
@@ -55,6 +55,15 @@ where
         }
     }
 
+    pub fn with_config(path: PathBuf, config: Config) -> Self {
+        Self {
+            raid: R::with_config(path, config),
+            file_locations: HashMap::new(),
+            current_slice: 0,
+            current_data_idx: 0,
+        }
+    }
+
     pub fn number_of_data_chunks_used(&self) -> usize {
         self.current_slice * D + self.current_data_idx
     }
@@ -63,6 +72,10 @@ where
         self.raid.destroy_devices(dev_idxs)
     }
 
+    pub fn replace_device(&self, dev_idx: usize, path: PathBuf) {
+        self.raid.replace_device(dev_idx, path)
+    }
+
     pub fn shutdown(self) {
         self.raid.shutdown()
     }
@@ -102,6 +102,11 @@ pub fn as_bytes_ref<const X: usize>(galois_slice: &[Galois; X]) -> &[u8; X] {
     unsafe { core::mem::transmute(galois_slice) }
 }
 
+/// transmute from Galois to u8, for slices of any length
+pub fn as_bytes_slice(galois_slice: &[Galois]) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(galois_slice.as_ptr().cast(), galois_slice.len()) }
+}
+
 macro_rules! add_impl {
     ($($t:ty)*) => ($(
         impl Add for $t {
@@ -1,6 +1,5 @@
 #![feature(generic_const_exprs)]
 #![feature(slice_as_chunks)]
-#![feature(array_chunks)]
 #![feature(new_uninit)]
 #![feature(const_mut_refs)]
 
@@ -0,0 +1,121 @@
+#![feature(generic_const_exprs)]
+
+//! Runs the node of one slot of a distributed array.
+//!
+//! ```text
+//! raid-node [--config FILE] --slot N --storage DIR [--open] [--KEY VALUE]...
+//! ```
+//!
+//! The settings come from the cluster file, see `raid::raid::cluster`, and `--KEY VALUE`
+//! overrides the setting `KEY` of the file. The node listens on its address in `nodes`
+//! until the head node shuts it down. Without `--open` the storage folder is emptied.
+
+use std::path::PathBuf;
+use std::process::exit;
+
+use raid::raid::cluster::Cluster;
+use raid::raid::distributed::Node;
+
+struct Args {
+    cluster: Cluster,
+    slot: usize,
+    storage: PathBuf,
+    open: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: raid-node [--config FILE] --slot N --storage DIR [--open] [--KEY VALUE]...");
+    exit(2)
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("raid-node: {msg}");
+    exit(1)
+}
+
+fn parse_args() -> Args {
+    let mut config = None;
+    let mut slot = None;
+    let mut storage = None;
+    let mut open = false;
+    let mut settings = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--open" {
+            open = true;
+            continue;
+        }
+        let Some(key) = arg.strip_prefix("--") else {
+            usage()
+        };
+        let Some(value) = args.next() else { usage() };
+        match key {
+            "config" => config = Some(PathBuf::from(value)),
+            "slot" => slot = Some(value.parse().unwrap_or_else(|_| usage())),
+            "storage" => storage = Some(PathBuf::from(value)),
+            _ => settings.push((key.to_string(), value)),
+        }
+    }
+    // flags win over the file
+    let mut cluster = config.map_or_else(Cluster::default, |path| Cluster::load(&path));
+    for (key, value) in settings {
+        cluster.set(&key, &value);
+    }
+    let (Some(slot), Some(storage)) = (slot, storage) else {
+        usage()
+    };
+    Args {
+        cluster,
+        slot,
+        storage,
+        open,
+    }
+}
+
+fn serve<const D: usize, const C: usize, const X: usize>(args: Args)
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    let config = args.cluster.config();
+    let Some(peers) = config.peers.as_ref() else {
+        fail("the cluster has no node addresses")
+    };
+    let Some(addr) = peers.nodes.get(args.slot) else {
+        fail(&format!(
+            "slot {} is not one of the {} nodes",
+            args.slot,
+            peers.nodes.len()
+        ))
+    };
+    println!("node {} of {D}+{C} listens on {addr}", args.slot);
+    if let Err(err) = Node::<D, C, X>::serve(args.storage, args.slot, &config, args.open) {
+        fail(&err.to_string())
+    }
+}
+
+// the geometries the binary is built for, every one with chunks of 4 KiB, 64 KiB and 1 MiB
+macro_rules! dispatch {
+    ($args:expr; $(($d:literal, $c:literal)),*) => {{
+        let args = $args;
+        let cluster = &args.cluster;
+        match (cluster.data_devices, cluster.checksum_devices, cluster.chunk_size) {
+            $(
+                ($d, $c, 4096) => serve::<$d, $c, 4096>(args),
+                ($d, $c, 65536) => serve::<$d, $c, 65536>(args),
+                ($d, $c, 1048576) => serve::<$d, $c, 1048576>(args),
+            )*
+            (d, c, x) => fail(&format!(
+                "not built for {d}+{c} devices with chunks of {x} bytes, only for {} \
+                 devices with chunks of 4096, 65536 or 1048576 bytes",
+                [$(concat!($d, "+", $c)),*].join(", ")
+            )),
+        }
+    }};
+}
+
+fn main() {
+    dispatch!(parse_args(); (2, 1), (3, 2), (4, 2), (6, 2), (6, 3), (8, 2), (8, 3), (10, 4), (30, 3));
+}
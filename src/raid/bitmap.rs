@@ -0,0 +1,96 @@
+//! Persistent bitmap with one bit per slice.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::raid::disk::Durability;
+
+pub struct Bitmap {
+    file: File,
+    bits: Mutex<Vec<u8>>,
+    durability: Durability,
+}
+
+impl Bitmap {
+    /// Empty bitmap, an existing file is truncated.
+    pub fn create(path: &Path, durability: Durability) -> Self {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        Self {
+            file,
+            bits: Mutex::new(vec![]),
+            durability,
+        }
+    }
+
+    /// Load the bitmap from disk, a missing file is an empty bitmap.
+    pub fn open(path: &Path, durability: Durability) -> Self {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .unwrap();
+        let mut bits = vec![];
+        file.read_to_end(&mut bits).unwrap();
+        Self {
+            file,
+            bits: Mutex::new(bits),
+            durability,
+        }
+    }
+
+    fn store(&self, data_slice: usize, value: bool, sync: bool) {
+        let mut bits = self.bits.lock().unwrap();
+        let byte_idx = data_slice / 8;
+        if byte_idx >= bits.len() {
+            bits.resize(byte_idx + 1, 0);
+        }
+        let old = bits[byte_idx];
+        if value {
+            bits[byte_idx] |= 1 << (data_slice % 8);
+        } else {
+            bits[byte_idx] &= !(1 << (data_slice % 8));
+        }
+        if old == bits[byte_idx] {
+            return;
+        }
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(byte_idx as u64)).unwrap();
+        file.write_all(&bits[byte_idx..byte_idx + 1]).unwrap();
+        if sync && self.durability != Durability::NoSync {
+            self.file.sync_data().unwrap();
+        }
+    }
+
+    /// Set the bit. It is on disk before this returns.
+    pub fn set(&self, data_slice: usize) {
+        self.store(data_slice, true, true)
+    }
+
+    /// Clear the bit. Not synced, losing a clear only costs a needless resync.
+    pub fn clear(&self, data_slice: usize) {
+        self.store(data_slice, false, false)
+    }
+
+    pub fn is_set(&self, data_slice: usize) -> bool {
+        let bits = self.bits.lock().unwrap();
+        bits.get(data_slice / 8)
+            .is_some_and(|byte| byte & (1 << (data_slice % 8)) != 0)
+    }
+
+    pub fn set_slices(&self) -> Vec<usize> {
+        let bits = self.bits.lock().unwrap();
+        (0..bits.len() * 8)
+            .filter(|i| bits[i / 8] & (1 << (i % 8)) != 0)
+            .collect()
+    }
+}
@@ -0,0 +1,178 @@
+//! Settings of a deployment where the nodes of a `Checkpoint` run in their own processes,
+//! shared by the `raid-node` servers and the head node. The file has `key=value` lines like
+//! the meta file of an array, empty lines and lines starting with `#` are skipped:
+//!
+//! ```text
+//! data_devices=3
+//! checksum_devices=2
+//! chunk_size=4096
+//! head=10.0.0.1:7000
+//! nodes=10.0.0.2:7000,10.0.0.3:7000,10.0.0.4:7000,10.0.0.5:7000,10.0.0.6:7000
+//! ```
+//!
+//! `durability` is one of `no-sync`, `per-write` and `group-commit`. `layout`, `domains` and
+//! `weights` are written as in the meta file. `heartbeat_interval` and `heartbeat_timeout`
+//! are in milliseconds, `suspicion` is the number of missed heartbeats after which a node
+//! is down. `inflight_bytes` limits the chunks queued at the nodes, `none` does not limit
+//! them. `read_timeout` is in milliseconds and `hedge` is a percentile like `0.95` or `none`.
+//! `aggregation` is one of `direct`, `chain` and `tree`, `repair` is `direct` or `pipelined`.
+//! The head node opens the array with `Checkpoint::open(root_path, cluster.config())`.
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::raid::disk::Durability;
+use crate::raid::distributed::{Aggregation, Heartbeat, Repair};
+use crate::raid::layout;
+use crate::raid::layout::{Layout, Rotation};
+use crate::raid::transport::Peers;
+use crate::raid::Config;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub data_devices: usize,
+    pub checksum_devices: usize,
+    pub chunk_size: usize,
+    pub durability: Durability,
+    pub layout: String,
+    pub domains: Vec<String>,
+    pub weights: Vec<u64>,
+    pub head: Option<SocketAddr>,
+    /// Address of the node of every slot.
+    pub nodes: Vec<SocketAddr>,
+    pub heartbeat: Heartbeat,
+    pub inflight_bytes: Option<u64>,
+    pub read_timeout: Duration,
+    pub hedge: Option<f64>,
+    pub aggregation: Aggregation,
+    pub repair: Repair,
+}
+
+impl Default for Cluster {
+    fn default() -> Self {
+        Self {
+            data_devices: 0,
+            checksum_devices: 0,
+            chunk_size: 0,
+            durability: Durability::default(),
+            layout: Rotation.name(),
+            domains: vec![],
+            weights: vec![],
+            head: None,
+            nodes: vec![],
+            heartbeat: Heartbeat::default(),
+            inflight_bytes: Config::default().inflight_bytes,
+            read_timeout: Config::default().read_timeout,
+            hedge: None,
+            aggregation: Aggregation::default(),
+            repair: Repair::default(),
+        }
+    }
+}
+
+impl Cluster {
+    pub fn load(path: &Path) -> Self {
+        let content = fs::read_to_string(path).unwrap();
+        let mut cluster = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').unwrap();
+            cluster.set(key.trim(), value.trim());
+        }
+        cluster
+    }
+
+    /// Change one setting, `key` is named as in the file.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let list = || value.split(',').filter(|item| !item.is_empty());
+        let millis = || Duration::from_millis(value.parse().unwrap());
+        match key {
+            "data_devices" => self.data_devices = value.parse().unwrap(),
+            "checksum_devices" => self.checksum_devices = value.parse().unwrap(),
+            "chunk_size" => self.chunk_size = value.parse().unwrap(),
+            "durability" => {
+                self.durability = match value {
+                    "no-sync" | "none" => Durability::NoSync,
+                    "per-write" => Durability::PerWrite,
+                    "group-commit" => Durability::GroupCommit,
+                    _ => panic!("unknown durability {value}"),
+                }
+            }
+            "layout" => self.layout = value.to_string(),
+            "domains" => self.domains = list().map(str::to_string).collect(),
+            "weights" => self.weights = list().map(|weight| weight.parse().unwrap()).collect(),
+            "head" => self.head = Some(value.parse().unwrap()),
+            "nodes" => self.nodes = list().map(|addr| addr.parse().unwrap()).collect(),
+            "heartbeat_interval" => self.heartbeat.interval = millis(),
+            "heartbeat_timeout" => self.heartbeat.timeout = millis(),
+            "suspicion" => self.heartbeat.suspicion = value.parse().unwrap(),
+            "inflight_bytes" => {
+                self.inflight_bytes = match value {
+                    "none" => None,
+                    _ => Some(value.parse().unwrap()),
+                }
+            }
+            "read_timeout" => self.read_timeout = millis(),
+            "hedge" => {
+                self.hedge = match value {
+                    "none" => None,
+                    _ => Some(value.parse().unwrap()),
+                }
+            }
+            "aggregation" => {
+                self.aggregation = match value {
+                    "direct" => Aggregation::Direct,
+                    "chain" => Aggregation::Chain,
+                    "tree" => Aggregation::Tree,
+                    _ => panic!("unknown aggregation {value}"),
+                }
+            }
+            "repair" => {
+                self.repair = match value {
+                    "direct" => Repair::Direct,
+                    "pipelined" => Repair::Pipelined,
+                    _ => panic!("unknown repair {value}"),
+                }
+            }
+            _ => panic!("unknown setting {key}"),
+        }
+    }
+
+    /// The config of the head node and of every node.
+    pub fn config(&self) -> Config {
+        let Some(head) = self.head else {
+            panic!("the cluster has no head node address")
+        };
+        let devices = self.data_devices + self.checksum_devices;
+        if self.nodes.len() != devices {
+            panic!(
+                "the cluster has {} node addresses for {devices} slots",
+                self.nodes.len()
+            )
+        }
+        let Some(layout) = layout::by_name(&self.layout) else {
+            panic!("unknown layout {}", self.layout)
+        };
+        Config {
+            durability: self.durability,
+            layout,
+            domains: self.domains.clone(),
+            weights: self.weights.clone(),
+            peers: Some(Peers {
+                head,
+                nodes: self.nodes.clone(),
+            }),
+            heartbeat: self.heartbeat,
+            inflight_bytes: self.inflight_bytes,
+            read_timeout: self.read_timeout,
+            hedge: self.hedge,
+            aggregation: self.aggregation,
+            repair: self.repair,
+            ..Config::default()
+        }
+    }
+}
@@ -1,35 +1,87 @@
+use std::collections::BTreeSet;
 use std::fs;
 use std::fs::create_dir;
-use std::io;
 use std::path::PathBuf;
 use std::prelude::rust_2021::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use crate::galois;
 use crate::galois::Galois;
 use crate::matrix::Matrix;
-use crate::raid::RAID;
+use crate::raid::bitmap::Bitmap;
+use crate::raid::device;
+use crate::raid::device::Device;
+use crate::raid::disk;
+use crate::raid::disk::{Batch, Durability};
+use crate::raid::layout::Layout;
+use crate::raid::meta;
+use crate::raid::meta::Meta;
+use crate::raid::rebuild;
+use crate::raid::rebuild::{Job, Progress, Running};
+use crate::raid::{Config, RAID};
 
-pub struct Controller<const D: usize, const C: usize, const X: usize>
+// number of slice locks, slices with different locks are written and rebuilt in parallel
+const LOCKS: usize = 64;
+
+/// The devices of a `Controller`, shared with the background rebuilds.
+pub(crate) struct Array<const D: usize, const C: usize, const X: usize>
 where
     [(); C + D]:,
     [(); D + C]:,
     [(); C + C]:,
     [(); D + D]:,
 {
-    max_data_slices: usize,
-    reed: Matrix<C, D>,
-    paths: [PathBuf; C + D],
+    pub(crate) reed: Matrix<C, D>,
+    pub(crate) durability: Durability,
+    layout: Arc<dyn Layout>,
+    // `layout.devices(D, C)` devices, at least D + C
+    devices: Vec<Device>,
+    // held while a slice is written or rebuilt, slice n uses lock n % LOCKS
+    locks: [Mutex<()>; LOCKS],
+    // devices that returned an I/O error, a spare takes over on the next operation
+    failed: Mutex<Vec<usize>>,
 }
 
-impl<const D: usize, const C: usize, const X: usize> Controller<D, C, X>
+impl<const D: usize, const C: usize, const X: usize> Array<D, C, X>
 where
     [(); C + D]:,
     [(); D + C]:,
     [(); C + C]:,
     [(); D + D]:,
 {
-    fn folder_id(data_slice: usize, data_idx: usize) -> usize {
-        (data_idx + data_slice) % (D + C)
+    pub(crate) fn new(
+        durability: Durability,
+        layout: Arc<dyn Layout>,
+        devices: Vec<Device>,
+    ) -> Self {
+        assert_eq!(devices.len(), layout.devices(D, C));
+        Self {
+            reed: Matrix::<C, D>::reed_solomon(),
+            durability,
+            layout,
+            devices,
+            locks: core::array::from_fn(|_| Mutex::new(())),
+            failed: Mutex::new(vec![]),
+        }
+    }
+
+    pub(crate) fn lock(&self, data_slice: usize) -> MutexGuard<'_, ()> {
+        self.locks[data_slice % LOCKS].lock().unwrap()
+    }
+
+    // no slice is written while the guards are held
+    fn lock_all(&self) -> Vec<MutexGuard<'_, ()>> {
+        self.locks.iter().map(|lock| lock.lock().unwrap()).collect()
+    }
+
+    pub(crate) fn folder_id(&self, data_slice: usize, data_idx: usize) -> usize {
+        self.layout.device(D, C, data_slice, data_idx)
+    }
+
+    // inverse of folder_id, None if the folder has no chunk of the slice
+    fn chunk_idx(&self, data_slice: usize, folder_id: usize) -> Option<usize> {
+        self.layout.chunk(D, C, data_slice, folder_id)
     }
 
     fn data_name(data_slice: usize, data_idx: usize) -> String {
@@ -40,18 +92,356 @@ where
         format!("{}_{}c.bin", data_slice, check_idx)
     }
 
-    fn data_file(&self, data_slice: usize, data_idx: usize) -> PathBuf {
-        let folder_path = &self.paths[Self::folder_id(data_slice, data_idx)];
+    pub(crate) fn data_file(&self, data_slice: usize, data_idx: usize) -> PathBuf {
+        let folder_path = &self.devices[self.folder_id(data_slice, data_idx)].path;
         let name = Self::data_name(data_slice, data_idx);
         folder_path.join(name)
     }
 
-    fn checksum_file(&self, data_slice: usize, check_idx: usize) -> PathBuf {
-        let folder_path = &self.paths[Self::folder_id(data_slice, D + check_idx)];
+    pub(crate) fn checksum_file(&self, data_slice: usize, check_idx: usize) -> PathBuf {
+        let folder_path = &self.devices[self.folder_id(data_slice, D + check_idx)].path;
         let name = Self::checksum_name(data_slice, check_idx);
         folder_path.join(name)
     }
 
+    // data chunks are 0..D, checksum chunks D..D + C
+    fn chunk_file(&self, data_slice: usize, idx: usize) -> PathBuf {
+        if idx < D {
+            self.data_file(data_slice, idx)
+        } else {
+            self.checksum_file(data_slice, idx - D)
+        }
+    }
+
+    // chunks that were never written are zero. None if the chunk can not be read, a media
+    // error also fails the device
+    fn read_chunk(&self, data_slice: usize, idx: usize) -> Option<Box<[Galois; X]>> {
+        match disk::read_chunk(&self.chunk_file(data_slice, idx)) {
+            Ok(chunk) => Some(chunk.map_or_else(galois::zeros, galois::from_bytes)),
+            Err(err) => {
+                if disk::is_media_error(&err) {
+                    self.fail_device(self.folder_id(data_slice, idx));
+                }
+                None
+            }
+        }
+    }
+
+    // the chunk as it is before a write to the slice, decoded if it can not be read. A device
+    // without the chunk gets it from the rebuild or the resync anyway
+    fn old_chunk(&self, data_slice: usize, idx: usize) -> Box<[Galois; X]> {
+        let dev_idx = self.folder_id(data_slice, idx);
+        if !self.devices[dev_idx].has_chunk(data_slice) {
+            return galois::zeros();
+        }
+        self.read_chunk(data_slice, idx).unwrap_or_else(|| {
+            let mut sources = self.sources(data_slice);
+            sources[dev_idx] = false;
+            let data = self.decode(data_slice, &sources);
+            self.encode_chunk(&data, idx)
+        })
+    }
+
+    pub(crate) fn fail_device(&self, dev_idx: usize) {
+        self.devices[dev_idx].fail();
+        self.failed.lock().unwrap().push(dev_idx);
+    }
+
+    /// Write the chunk or remember the slice in the dirty log of a missing device.
+    pub(crate) fn write_chunk(
+        &self,
+        batch: &mut Batch,
+        data_slice: usize,
+        idx: usize,
+        chunk: &[Galois; X],
+    ) {
+        for file_path in self.write_paths(data_slice, idx) {
+            batch.write(file_path, galois::as_bytes_ref(chunk));
+        }
+    }
+
+    /// The files a write of the chunk goes to, a device that is replaced gets it in both
+    /// folders. A missing device remembers the slice in its dirty log instead.
+    pub(crate) fn write_paths(&self, data_slice: usize, idx: usize) -> Vec<PathBuf> {
+        let device = &self.devices[self.folder_id(data_slice, idx)];
+        let file_path = self.chunk_file(data_slice, idx);
+        let mut paths: Vec<_> = device.mirror(&file_path, data_slice).into_iter().collect();
+        if device.is_present() {
+            paths.push(file_path);
+        } else {
+            device.mark_dirty(data_slice);
+        }
+        paths
+    }
+
+    // devices with an up to date chunk of the slice
+    fn sources(&self, data_slice: usize) -> Vec<bool> {
+        self.devices
+            .iter()
+            .map(|device| device.has_chunk(data_slice))
+            .collect()
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|device| !device.is_present() || device.is_rebuilding())
+    }
+
+    /// Data chunk of a slice, decoded from the other devices if its device lacks it.
+    pub(crate) fn read_data_chunk(&self, data_slice: usize, data_idx: usize) -> Box<[Galois; X]> {
+        let dev_idx = self.folder_id(data_slice, data_idx);
+        if self.devices[dev_idx].has_chunk(data_slice) {
+            if let Some(chunk) = self.read_chunk(data_slice, data_idx) {
+                return chunk;
+            }
+        }
+        let data = self.decode(data_slice, &self.sources(data_slice));
+        galois::from_slice(&data[data_idx])
+    }
+
+    /// Write the data chunks of a slice together with their checksums.
+    pub(crate) fn write_slice(&self, data_slice: usize, data: &[Box<[Galois; X]>; D]) {
+        let mut batch = Batch::new(self.durability);
+        for (data_idx, chunk) in data.iter().enumerate() {
+            self.write_chunk(&mut batch, data_slice, data_idx, chunk);
+        }
+        for check_idx in 0..C {
+            let checksum = self.reed.mul_vec_at(data, check_idx);
+            self.write_chunk(&mut batch, data_slice, D + check_idx, &checksum);
+        }
+        batch.commit();
+    }
+
+    /// Write a data chunk and change the checksums of the slice by its difference to `old`.
+    pub(crate) fn update_chunk(
+        &self,
+        data_slice: usize,
+        data_idx: usize,
+        old: &[Galois; X],
+        data: &[Galois; X],
+    ) {
+        let mut batch = Batch::new(self.durability);
+        self.write_chunk(&mut batch, data_slice, data_idx, data);
+        for check_idx in 0..C {
+            let old_checksum = self.old_chunk(data_slice, D + check_idx);
+            let new_checksum: Box<[Galois; X]> = galois::from_fn(|i| {
+                old_checksum[i] + self.reed[check_idx][data_idx] * (data[i] - old[i])
+            });
+            self.write_chunk(&mut batch, data_slice, D + check_idx, &new_checksum);
+        }
+        batch.commit();
+    }
+
+    /// Compute the checksums of a slice again from its data chunks.
+    pub(crate) fn resync(&self, data_slice: usize) {
+        // a running rebuild must not write the slice between the read and the checksums
+        let _guard = self.lock(data_slice);
+        let data: [Box<[Galois; X]>; D] =
+            core::array::from_fn(|i| self.read_data_chunk(data_slice, i));
+        let mut batch = Batch::new(self.durability);
+        for check_idx in 0..C {
+            let checksum = self.reed.mul_vec_at(&data, check_idx);
+            self.write_chunk(&mut batch, data_slice, D + check_idx, &checksum);
+        }
+        batch.commit();
+    }
+
+    /// Remove every chunk of a slice.
+    pub(crate) fn remove_slice(&self, data_slice: usize) {
+        for idx in 0..D + C {
+            let _ = fs::remove_file(self.chunk_file(data_slice, idx));
+        }
+    }
+
+    /// Compute the data chunks of a slice from D chunks stored on the `sources` devices.
+    fn decode(&self, data_slice: usize, sources: &[bool]) -> [Box<[Galois; X]>; D] {
+        let mut r_data_check = vec![];
+        let mut r_data_idx = vec![];
+        let mut r_check_idx = vec![];
+        for idx in 0..D + C {
+            if r_data_check.len() == D {
+                break;
+            }
+            if !sources[self.folder_id(data_slice, idx)] {
+                continue;
+            }
+            // a failing device is skipped
+            if let Some(chunk) = self.read_chunk(data_slice, idx) {
+                r_data_check.push(chunk);
+                if idx < D {
+                    r_data_idx.push(idx);
+                } else {
+                    r_check_idx.push(idx - D);
+                }
+            }
+        }
+        if r_data_check.len() < D {
+            panic!("Too man devices lost")
+        }
+
+        let mut rec_matrix = self.reed.recovery_matrix(r_data_idx, r_check_idx);
+        let mut data: [Box<[Galois; X]>; D] = r_data_check.try_into().unwrap();
+        rec_matrix.gaussian_elimination(&mut data);
+        data
+    }
+
+    // data or checksum chunk `idx` of the decoded slice
+    fn encode_chunk(&self, data: &[Box<[Galois; X]>; D], idx: usize) -> Box<[Galois; X]> {
+        if idx < D {
+            galois::from_slice(&data[idx])
+        } else {
+            self.reed.mul_vec_at(data, idx - D)
+        }
+    }
+
+    // write the chunks of the slice the devices are missing
+    fn rebuild_slice(&self, data_slice: usize, dev_idxs: &[usize]) {
+        let mut sources = self.sources(data_slice);
+        for dev_idx in dev_idxs {
+            sources[*dev_idx] = false;
+        }
+        let idxs: Vec<_> = dev_idxs
+            .iter()
+            .filter_map(|dev_idx| self.chunk_idx(data_slice, *dev_idx))
+            .collect();
+        if idxs.is_empty() {
+            return;
+        }
+        let data = self.decode(data_slice, &sources);
+        let mut batch = Batch::new(self.durability);
+        for idx in idxs {
+            let chunk = self.encode_chunk(&data, idx);
+            batch.write(
+                self.chunk_file(data_slice, idx),
+                galois::as_bytes_ref(&chunk),
+            );
+        }
+        batch.commit();
+    }
+
+    // copy the chunk of the device to its replacement, reconstruct it if it can not be read
+    fn copy_slice(&self, data_slice: usize, dev_idx: usize) {
+        let device = &self.devices[dev_idx];
+        let Some(idx) = self.chunk_idx(data_slice, dev_idx) else {
+            return;
+        };
+        let file_path = self.chunk_file(data_slice, idx);
+        let chunk = if device.has_chunk(data_slice) {
+            match disk::read_chunk::<X>(&file_path) {
+                Ok(Some(chunk)) => Some(chunk),
+                // nothing to copy
+                Ok(None) => return,
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+        let chunk = chunk.unwrap_or_else(|| {
+            let mut sources = self.sources(data_slice);
+            sources[dev_idx] = false;
+            let data = self.decode(data_slice, &sources);
+            galois::as_bytes(self.encode_chunk(&data, idx))
+        });
+        let mut batch = Batch::new(self.durability);
+        batch.write(device.copy_path(&file_path), &*chunk);
+        batch.commit();
+    }
+
+    /// Rebuild devices while the array keeps serving requests. Every worker rebuilds one
+    /// slice at a time, so at most `workers` slices are decoded at once. A slice is decoded
+    /// once for all devices.
+    fn rebuild(&self, dev_idxs: &[usize], job: &Job, workers: usize) {
+        let data_slices = job.remaining();
+        let next = AtomicUsize::new(data_slices.start);
+        // slices finish out of order, the rebuild only counts up to the first missing one
+        let done = Mutex::new((data_slices.start, BTreeSet::new()));
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    if job.is_cancelled() {
+                        return;
+                    }
+                    let data_slice = next.fetch_add(1, Ordering::SeqCst);
+                    if data_slice >= data_slices.end {
+                        return;
+                    }
+                    // with a declustered layout most slices have no chunk on the devices
+                    let rebuilt = dev_idxs
+                        .iter()
+                        .filter(|dev_idx| self.chunk_idx(data_slice, **dev_idx).is_some())
+                        .count();
+                    if rebuilt > 0 {
+                        let _guard = self.lock(data_slice);
+                        self.rebuild_slice(data_slice, dev_idxs);
+                    }
+                    {
+                        let mut done = done.lock().unwrap();
+                        let (until, finished) = &mut *done;
+                        finished.insert(data_slice);
+                        while finished.remove(until) {
+                            *until += 1;
+                        }
+                        job.rebuilt_until(*until);
+                        for dev_idx in dev_idxs {
+                            self.devices[*dev_idx].rebuilt_until(*until);
+                        }
+                    }
+                    if rebuilt > 0 {
+                        // D chunks read, one written per device
+                        job.throttle(((D + rebuilt) * X) as u64);
+                    }
+                });
+            }
+        });
+        if job.is_cancelled() {
+            // stores the checkpoint
+            job.rebuilt_until(done.lock().unwrap().0);
+            return;
+        }
+        // the checkpoint may already be at the end
+        for dev_idx in dev_idxs {
+            self.devices[*dev_idx].rebuilt_until(data_slices.end);
+        }
+    }
+}
+
+pub struct Controller<const D: usize, const C: usize, const X: usize>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    pub(crate) max_data_slices: usize,
+    pub(crate) array: Arc<Array<D, C, X>>,
+    // slices with a write in progress
+    pub(crate) bitmap: Bitmap,
+    spares: Mutex<Vec<PathBuf>>,
+    rebuild_bandwidth: Option<u64>,
+    rebuild_workers: usize,
+    rebuilds: Mutex<Vec<Running>>,
+}
+
+impl<const D: usize, const C: usize, const X: usize> Controller<D, C, X>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    pub(crate) fn data_file(&self, data_slice: usize, data_idx: usize) -> PathBuf {
+        self.array.data_file(data_slice, data_idx)
+    }
+
+    pub(crate) fn checksum_file(&self, data_slice: usize, check_idx: usize) -> PathBuf {
+        self.array.checksum_file(data_slice, check_idx)
+    }
+
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.array.is_degraded()
+    }
+
     pub fn read_checksum_at(&self, data_slice: usize, check_idx: usize) -> Box<[u8; X]> {
         let file_path = self.checksum_file(data_slice, check_idx);
         fs::read(file_path)
@@ -65,88 +455,207 @@ where
         core::array::from_fn(|i| self.read_checksum_at(data_slice, i))
     }
 
+    /// Recompute the checksums of a slice from its data chunks.
+    fn resync(&self, data_slice: usize) {
+        self.array.resync(data_slice);
+        self.bitmap.clear(data_slice);
+    }
+
+    /// Register a standby device. It takes over the slot of the next device that fails.
+    pub fn add_spare(&self, path: PathBuf) {
+        self.spares.lock().unwrap().push(path);
+    }
+
+    // link a spare into the slot and rebuild it in the background. False without spares
+    fn promote_spare(&self, dev_idx: usize) -> bool {
+        let Some(spare) = self.spares.lock().unwrap().pop() else {
+            return false;
+        };
+        let device = &self.array.devices[dev_idx];
+        device.promote(&spare);
+        self.start_rebuild(vec![dev_idx], false);
+        true
+    }
+
+    // rebuild the devices in the background with one job, from their checkpoints if `resume`
+    fn start_rebuild(&self, dev_idxs: Vec<usize>, resume: bool) {
+        if dev_idxs.is_empty() {
+            return;
+        }
+        let paths: Vec<_> = dev_idxs
+            .iter()
+            .map(|dev_idx| self.array.devices[*dev_idx].path.as_path())
+            .collect();
+        let end = self.max_data_slices + 1;
+        let job = if resume {
+            Job::resume(&paths, end, self.array.durability, self.rebuild_bandwidth)
+        } else {
+            Job::new(&paths, end, self.array.durability, self.rebuild_bandwidth)
+        };
+        for dev_idx in &dev_idxs {
+            self.array.devices[*dev_idx].start_rebuild(job.remaining());
+        }
+        let job = Arc::new(job);
+        let array = self.array.clone();
+        let workers = self.rebuild_workers;
+        let handle = {
+            let job = job.clone();
+            let dev_idxs = dev_idxs.clone();
+            std::thread::Builder::new()
+                .name(format!("rebuild{}", dev_idxs[0]))
+                .spawn(move || array.rebuild(&dev_idxs, &job, workers))
+                .unwrap()
+        };
+        self.rebuilds.lock().unwrap().push(Running {
+            dev_idxs,
+            job,
+            handle,
+        });
+    }
+
+    // devices with an unfinished rebuild that no job works on
+    fn stopped_rebuilds(&self) -> Vec<usize> {
+        let running: Vec<_> = {
+            let rebuilds = self.rebuilds.lock().unwrap();
+            rebuilds.iter().flat_map(|r| r.dev_idxs.clone()).collect()
+        };
+        (0..self.array.devices.len())
+            .filter(|dev_idx| !running.contains(dev_idx))
+            .filter(|dev_idx| {
+                let device = &self.array.devices[*dev_idx];
+                device.is_present() && (device.is_rebuilding() || !device.is_current())
+            })
+            .collect()
+    }
+
+    /// Replace the devices that returned I/O errors.
+    pub(crate) fn handle_failures(&self) {
+        let failed: Vec<_> = self.array.failed.lock().unwrap().drain(..).collect();
+        for dev_idx in failed {
+            self.promote_spare(dev_idx);
+        }
+    }
+
+    /// Stop the running rebuilds, they continue from their checkpoint with `resume_rebuild`
+    /// or on the next open. Without it `shutdown` waits for them.
+    pub fn cancel_rebuilds(&self) {
+        rebuild::cancel(&self.rebuilds, None)
+    }
+
+    /// Block until the running background rebuilds are done.
+    pub fn wait_for_rebuild(&self) {
+        rebuild::join(&self.rebuilds, None)
+    }
+
+    /// Progress of the running rebuilds by device.
+    pub fn rebuild_progress(&self) -> Vec<(usize, Progress)> {
+        rebuild::progress(&self.rebuilds)
+    }
+
+    /// Stop the rebuild of the device, and of the devices rebuilt by the same job. The
+    /// devices stay degraded, the rebuild continues from its checkpoint with
+    /// `resume_rebuild` or when the array is opened again.
+    pub fn cancel_rebuild(&self, dev_idx: usize) {
+        rebuild::cancel(&self.rebuilds, Some(dev_idx))
+    }
+
+    /// Continue a cancelled rebuild from its checkpoint, together with the other cancelled
+    /// rebuilds.
+    pub fn resume_rebuild(&self, dev_idx: usize) {
+        if self.array.devices[dev_idx].is_rebuilding() {
+            self.cancel_rebuild(dev_idx);
+            self.start_rebuild(self.stopped_rebuilds(), true);
+        }
+    }
+
     pub fn remove_device(&self, idx: usize) {
-        let device_path = &self.paths[idx];
+        let device_path = &self.array.devices[idx].path;
         let _ = std::fs::remove_dir_all(device_path);
     }
 
     pub fn construct_missing_devices(&self) {
-        let mut online_devices: [bool; D + C] = [false; D + C];
-        let mut count = 0;
         // check which devices are online
-        for i in 0..D + C {
-            if self.paths[i].exists() {
-                online_devices[i] = true;
-                count += 1;
-            } else {
-                create_dir(&self.paths[i]).unwrap()
-            }
+        let missing: Vec<_> = (0..self.array.devices.len())
+            .filter(|i| !self.array.devices[*i].is_present())
+            .collect();
+        // a slice of a declustered array can lose more devices, as long as at most C of
+        // them hold one of its chunks. The rebuild panics on a slice with too few left
+        if missing.len() > C && self.array.devices.len() == D + C {
+            panic!("Too man devices lost")
         }
+        for i in &missing {
+            // a folder that came back is stale
+            let _ = std::fs::remove_dir_all(&self.array.devices[*i].path);
+            create_dir(&self.array.devices[*i].path).unwrap();
+        }
+        let first = missing.first().copied();
+        self.start_rebuild(missing, false);
+        if let Some(first) = first {
+            rebuild::join(&self.rebuilds, Some(first));
+        }
+    }
 
-        if count < D {
-            panic!("Too man devices lost")
+    /// Bring back a device that was missing for a while. Only the slices written in the
+    /// meantime are rebuilt. Returns the number of rebuilt slices.
+    pub fn reattach_device(&self, dev_idx: usize) -> Result<usize, device::Error> {
+        let device = &self.array.devices[dev_idx];
+        let dirty = device.reattach()?;
+
+        for &data_slice in &dirty {
+            let _guard = self.array.lock(data_slice);
+            self.array.rebuild_slice(data_slice, &[dev_idx]);
         }
+        device.resynced();
+        Ok(dirty.len())
+    }
 
-        // use only D devices
-        let mut recover_devices = online_devices;
-        let mut x = D + C - 1;
-        while count > D {
-            if recover_devices[x] {
-                recover_devices[x] = false;
-                count -= 1;
-            }
-            x -= 1;
-        }
-
-        assert_eq!(count, D);
-
-        for data_slice in 0..self.max_data_slices + 1 {
-            // collect data we need
-            let mut r_data_check = vec![];
-            let mut r_data_idx = vec![];
-            for data_idx in 0..D {
-                let folder_id_i = Self::folder_id(data_slice, data_idx);
-                if recover_devices[folder_id_i] {
-                    let bytes = self.read_data_at(data_slice, data_idx);
-                    let g_data = galois::from_bytes(bytes);
-                    r_data_check.push(g_data);
-                    r_data_idx.push(data_idx);
-                }
-            }
-            let mut r_check_idx = vec![];
-            for check_idx in 0..C {
-                let folder_id_i = Self::folder_id(data_slice, check_idx + D);
-                if recover_devices[folder_id_i] {
-                    let bytes = self.read_checksum_at(data_slice, check_idx);
-                    let g_data = galois::from_bytes(bytes);
-                    r_data_check.push(g_data);
-                    r_check_idx.push(check_idx);
-                }
-            }
+    /// Open an existing array like `RAID::open`, but return an error if it can not be used.
+    pub fn try_open(root_path: PathBuf, config: Config) -> Result<Self, meta::Error> {
+        Meta::check::<D, C, X>(&root_path, &config)?;
+        let layout = config.layout(D, C).map_err(meta::Error::Layout)?;
+        let rebuild_workers = config.rebuild_workers();
+        let paths: Vec<_> = (0..layout.devices(D, C))
+            .map(|i| root_path.join(format!("device{i}")))
+            .collect();
+        disk::remove_tmp_files(&root_path);
+        for path in &paths {
+            disk::remove_tmp_files(path);
+        }
+        let devices: Vec<_> = paths
+            .iter()
+            .map(|path| Device::new(path.clone(), config.durability))
+            .collect();
+        // spares that already took over a slot
+        let in_use: Vec<_> = devices.iter().filter_map(|device| device.spare()).collect();
+        let spares = config
+            .spares
+            .into_iter()
+            .filter(|spare| !in_use.contains(spare))
+            .collect();
 
-            // consturct matrix
-            let mut rec_matrix = self.reed.recovery_matrix(r_data_idx, r_check_idx);
-            let mut data: [Box<[Galois; X]>; D] = r_data_check.try_into().unwrap();
-            rec_matrix.gaussian_elimination(&mut data);
-
-            // save data
-            for data_idx in 0..D {
-                let folder_id_i = Self::folder_id(data_slice, data_idx);
-                if !online_devices[folder_id_i] {
-                    let file_path = self.data_file(data_slice, data_idx);
-                    fs::write(file_path, galois::as_bytes_ref(&data[data_idx])).unwrap();
-                }
-            }
-            // coompute checksum and save
-            for check_idx in 0..C {
-                let folder_id_i = Self::folder_id(data_slice, check_idx + D);
-                if !online_devices[folder_id_i] {
-                    let file_path = self.checksum_file(data_slice, check_idx);
-                    let checksum = self.reed.mul_vec_at(&data, check_idx);
-                    fs::write(file_path, galois::as_bytes_ref(&checksum)).unwrap();
-                }
-            }
+        let controller = Self {
+            max_data_slices: meta::max_data_slice(&paths),
+            array: Arc::new(Array::new(config.durability, layout, devices)),
+            bitmap: Bitmap::open(&root_path.join("bitmap"), config.durability),
+            spares: Mutex::new(spares),
+            rebuild_bandwidth: config.rebuild_bandwidth,
+            rebuild_workers,
+            rebuilds: Mutex::new(vec![]),
+        };
+
+        // rebuilds were interrupted, continue them
+        controller.start_rebuild(controller.stopped_rebuilds(), true);
+
+        // missing devices stay missing until they are reattached or rebuilt
+        let dirty = controller.bitmap.set_slices();
+        if controller.is_degraded() && !dirty.is_empty() {
+            controller.cancel_rebuilds();
+            return Err(meta::Error::DegradedDirty { dirty: dirty.len() });
+        }
+        for data_slice in dirty {
+            controller.resync(data_slice);
         }
+        Ok(controller)
     }
 }
 
@@ -157,103 +666,155 @@ where
     [(); C + C]:,
     [(); D + D]:,
 {
-    fn new(root_path: PathBuf) -> Self {
-        let paths = core::array::from_fn(|i| root_path.join(format!("device{i}")));
-        for path in &paths {
-            let _ = std::fs::remove_dir_all(path);
-            create_dir(path).unwrap()
+    fn with_config(root_path: PathBuf, config: Config) -> Self {
+        let layout = config.layout(D, C).unwrap();
+        let rebuild_workers = config.rebuild_workers();
+        let devices: Vec<_> = (0..layout.devices(D, C))
+            .map(|i| Device::new(root_path.join(format!("device{i}")), config.durability))
+            .collect();
+        for device in &devices {
+            let _ = std::fs::remove_dir_all(&device.path);
+            create_dir(&device.path).unwrap();
+            device.create();
         }
 
+        Meta::new::<D, C, X>(&config).store(&root_path, config.durability);
+
         Self {
             max_data_slices: 0,
-            reed: Matrix::<C, D>::reed_solomon(),
-            paths,
+            array: Arc::new(Array::new(config.durability, layout, devices)),
+            bitmap: Bitmap::create(&root_path.join("bitmap"), config.durability),
+            spares: Mutex::new(config.spares),
+            rebuild_bandwidth: config.rebuild_bandwidth,
+            rebuild_workers,
+            rebuilds: Mutex::new(vec![]),
         }
     }
 
+    fn open(root_path: PathBuf, config: Config) -> Self {
+        Self::try_open(root_path, config).unwrap()
+    }
+
     fn add_data(&mut self, data: &[&[u8; X]; D], data_slice: usize) {
+        self.handle_failures();
         self.max_data_slices = self.max_data_slices.max(data_slice);
         let data: &[&[Galois; X]; D] = unsafe { core::mem::transmute(data) };
-        let checksum = self.reed.mul_vec(data);
+        let checksum = self.array.reed.mul_vec(data);
+        let _guard = self.array.lock(data_slice);
+        self.bitmap.set(data_slice);
+        let mut batch = Batch::new(self.array.durability);
         for d_idx in 0..D {
-            let file_path = self.data_file(data_slice, d_idx);
-            fs::write(file_path, galois::as_bytes_ref(&data[d_idx])).unwrap();
+            self.array
+                .write_chunk(&mut batch, data_slice, d_idx, data[d_idx]);
         }
 
         for c_idx in 0..C {
-            let file_path = self.checksum_file(data_slice, c_idx);
-            fs::write(file_path, galois::as_bytes_ref(&checksum[c_idx])).unwrap();
+            self.array
+                .write_chunk(&mut batch, data_slice, D + c_idx, &checksum[c_idx]);
         }
+        batch.commit();
+        self.bitmap.clear(data_slice);
     }
 
     fn add_data_at(&mut self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        self.handle_failures();
         self.max_data_slices = self.max_data_slices.max(data_slice);
         let data = galois::from_bytes_ref(data);
-        let dfile_path = self.data_file(data_slice, data_idx);
-        fs::write(&dfile_path, galois::as_bytes_ref(&data)).unwrap();
+        let _guard = self.array.lock(data_slice);
+        self.bitmap.set(data_slice);
+        let mut batch = Batch::new(self.array.durability);
+        self.array
+            .write_chunk(&mut batch, data_slice, data_idx, data);
 
         for check_idx in 0..C {
-            let checksum_path = self.checksum_file(data_slice, check_idx);
-            let new_checksum: Box<[Galois; X]> = match fs::read(&checksum_path) {
-                Ok(file) => {
-                    let old_checksum: Box<[Galois; X]> =
-                        galois::from_bytes(file.into_boxed_slice().try_into().unwrap());
-                    galois::from_fn(|i| old_checksum[i] + self.reed[check_idx][data_idx] * data[i])
-                }
-                Err(err) => {
-                    let io::ErrorKind::NotFound = err.kind() else {
-                        panic!("{:?}", err)
-                    };
-                    galois::from_fn(|i| self.reed[check_idx][data_idx] * data[i])
-                }
-            };
-            fs::write(&checksum_path, galois::as_bytes_ref(&new_checksum)).unwrap();
+            let old_checksum = self.array.old_chunk(data_slice, D + check_idx);
+            let new_checksum: Box<[Galois; X]> = galois::from_fn(|i| {
+                old_checksum[i] + self.array.reed[check_idx][data_idx] * data[i]
+            });
+            self.array
+                .write_chunk(&mut batch, data_slice, D + check_idx, &new_checksum);
         }
+        batch.commit();
+        self.bitmap.clear(data_slice);
     }
 
     fn read_data_at(&self, data_slice: usize, data_idx: usize) -> Box<[u8; X]> {
-        let file_path = self.data_file(data_slice, data_idx);
-        match fs::read(&file_path) {
-            Ok(file) => file.into_boxed_slice().try_into().unwrap(),
-            Err(err) => {
-                let io::ErrorKind::NotFound = err.kind() else {
-                    panic!("{:?}", err)
-                };
-                if data_slice > self.max_data_slices {
-                    panic!("not allowed")
+        self.handle_failures();
+        let dev_idx = self.array.folder_id(data_slice, data_idx);
+        if self.array.devices[dev_idx].has_chunk(data_slice) {
+            let file_path = self.data_file(data_slice, data_idx);
+            match disk::read_chunk(&file_path) {
+                Ok(Some(chunk)) => return chunk,
+                Ok(None) => {
+                    if data_slice > self.max_data_slices {
+                        panic!("not allowed")
+                    }
+                    return galois::zeros_raw();
                 }
-                galois::zeros_raw()
+                // serve the read degraded, a spare takes over on the next operation
+                Err(err) if disk::is_media_error(&err) => self.array.fail_device(dev_idx),
+                Err(_) => {}
             }
         }
+        // degraded read
+        let data = self
+            .array
+            .decode(data_slice, &self.array.sources(data_slice));
+        galois::as_bytes(galois::from_slice(&data[data_idx]))
     }
 
     fn read_data(&self, data_slice: usize) -> [Box<[u8; X]>; D] {
-        core::array::from_fn(|i| self.read_data_at(data_slice, i))
+        let sources = self.array.sources(data_slice);
+        if (0..D).all(|i| sources[self.array.folder_id(data_slice, i)]) {
+            core::array::from_fn(|i| self.read_data_at(data_slice, i))
+        } else {
+            // decode the slice only once
+            self.array
+                .decode(data_slice, &sources)
+                .map(galois::as_bytes)
+        }
     }
 
     fn destroy_devices(&self, dev_idxs: &[usize]) {
         for dev_idx in dev_idxs {
-            let device_path = &self.paths[*dev_idx];
-            let _ = std::fs::remove_dir_all(device_path);
+            self.remove_device(*dev_idx);
         }
-        self.construct_missing_devices()
+        // without a spare the device is rebuilt in place
+        let mut in_place = false;
+        for dev_idx in dev_idxs {
+            in_place |= !self.promote_spare(*dev_idx);
+        }
+        if in_place {
+            self.construct_missing_devices()
+        }
+    }
+
+    fn replace_device(&self, dev_idx: usize, path: PathBuf) {
+        self.handle_failures();
+        let device = &self.array.devices[dev_idx];
+        let first = device.start_replace(&path);
+        for data_slice in first..self.max_data_slices + 1 {
+            let _guard = self.array.lock(data_slice);
+            self.array.copy_slice(data_slice, dev_idx);
+            device.copied_until(data_slice + 1);
+        }
+        let _guards = self.array.lock_all();
+        device.replaced();
     }
 
     fn update_data(&self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        self.handle_failures();
         let data = galois::from_bytes_ref(data);
+        // a write of the slice in between would change the checksums the delta goes into
+        let _guard = self.array.lock(data_slice);
         let old_data = galois::from_bytes(self.read_data_at(data_slice, data_idx));
-        let dfile_path = self.data_file(data_slice, data_idx);
-        fs::remove_file(&dfile_path).unwrap();
-        fs::write(&dfile_path, galois::as_bytes_ref(&data)).unwrap();
+        self.bitmap.set(data_slice);
+        self.array
+            .update_chunk(data_slice, data_idx, &old_data, data);
+        self.bitmap.clear(data_slice);
+    }
 
-        for check_idx in 0..C {
-            let old_checksum = galois::from_bytes(self.read_checksum_at(data_slice, check_idx));
-            let new_checksum: Box<[Galois; X]> = galois::from_fn(|i| {
-                old_checksum[i] + self.reed[check_idx][data_idx] * (data[i] - old_data[i])
-            });
-            let file_path = self.checksum_file(data_slice, check_idx);
-            fs::remove_file(&file_path).unwrap();
-            fs::write(&file_path, galois::as_bytes_ref(&new_checksum)).unwrap();
-        }
+    fn shutdown(self) {
+        self.wait_for_rebuild();
     }
 }
@@ -0,0 +1,409 @@
+//! Bookkeeping for devices that are temporarily unavailable.
+//!
+//! Next to every device folder `deviceN` the array keeps `deviceN.generation`, the generation
+//! the folder must have to be in sync, and `deviceN.dirty`, a bitmap of the slices written while
+//! the folder was missing. The folder itself stores its generation in `deviceN/generation`.
+//! Only a full rebuild increments the generation, so a folder that comes back after its slot
+//! was rebuilt is older than the array expects and has to be refused.
+//!
+//! A folder that went missing stays out of the array even if it shows up again, its chunks
+//! are stale until `reattach` checks its generation and the missed slices are written again.
+//!
+//! A hot spare takes over a slot by linking `deviceN` to the spare folder. The spare has no
+//! generation until its rebuild is done, so an interrupted rebuild is noticed on open.
+//! A replaced device is switched over the same way once it is fully copied: the link is
+//! made as `deviceN.link` and renamed over the slot, the old folder is kept as
+//! `deviceN.replaced`. The target and the number of copied slices are stored in
+//! `deviceN.copy`, so a crash while copying continues where it stopped and a crash while
+//! switching finishes the switch on open.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::raid::bitmap::Bitmap;
+use crate::raid::disk;
+use crate::raid::disk::Durability;
+use crate::raid::rebuild::CHECKPOINT_INTERVAL;
+
+#[derive(Debug)]
+pub enum Error {
+    /// the device folder is still missing
+    Absent,
+    /// the device missed writes that are no longer tracked and needs a full rebuild
+    Stale {
+        generation: Option<u64>,
+        expected: u64,
+    },
+}
+
+pub struct Device {
+    pub path: PathBuf,
+    durability: Durability,
+    dirty: Mutex<Option<Bitmap>>,
+    // the folder was found missing, it is not used until it is reattached
+    absent: AtomicBool,
+    // slices a reattached folder missed and that are not written again yet
+    stale: Mutex<BTreeSet<usize>>,
+    // slices a running rebuild has not written yet
+    rebuild: Mutex<Option<Range<usize>>>,
+    replacement: Mutex<Option<Replacement>>,
+}
+
+// folder the device is copied to, the number of slices copied so far and the number in
+// `deviceN.copy`
+struct Replacement {
+    target: PathBuf,
+    copied: usize,
+    stored: usize,
+}
+
+// the slot of a spare or replacement links to its folder
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+fn read_generation(path: &Path) -> Option<u64> {
+    match fs::read_to_string(path) {
+        Ok(content) => Some(content.trim().parse().unwrap()),
+        Err(err) => {
+            let io::ErrorKind::NotFound = err.kind() else {
+                panic!("{:?}", err)
+            };
+            None
+        }
+    }
+}
+
+impl Device {
+    pub fn new(path: PathBuf, durability: Durability) -> Self {
+        // a dirty log is only kept while the folder is missing
+        let absent = path.with_extension("dirty").exists();
+        let device = Self {
+            path,
+            durability,
+            dirty: Mutex::new(None),
+            absent: AtomicBool::new(absent),
+            stale: Mutex::new(BTreeSet::new()),
+            rebuild: Mutex::new(None),
+            replacement: Mutex::new(None),
+        };
+        device.load_replacement();
+        device
+    }
+
+    fn progress_path(&self) -> PathBuf {
+        self.path.with_extension("copy")
+    }
+
+    fn store_replacement(&self, replacement: &mut Replacement) {
+        let content = format!("{}\n{}\n", replacement.copied, replacement.target.display());
+        disk::write(self.progress_path(), content.as_bytes(), self.durability);
+        replacement.stored = replacement.copied;
+    }
+
+    // continue an interrupted replace, or finish it if the copy was complete
+    fn load_replacement(&self) {
+        let Ok(content) = fs::read_to_string(self.progress_path()) else {
+            return;
+        };
+        let Some((copied, target)) = content.trim_end().split_once('\n') else {
+            // torn before `disk::write` made it atomic, the copy starts again
+            let _ = fs::remove_file(self.progress_path());
+            return;
+        };
+        let target = PathBuf::from(target);
+        let link = self.path.with_extension("link");
+        if fs::symlink_metadata(&link).is_ok() {
+            self.switch_slot(&link);
+        }
+        if fs::read_link(&self.path).is_ok_and(|linked| linked == target) {
+            // switched before the crash, only the generation is missing
+            self.switched();
+            return;
+        }
+        let copied = copied.parse().unwrap_or(0);
+        *self.replacement.lock().unwrap() = Some(Replacement {
+            target,
+            copied,
+            stored: copied,
+        });
+    }
+
+    fn expected_path(&self) -> PathBuf {
+        self.path.with_extension("generation")
+    }
+
+    fn dirty_path(&self) -> PathBuf {
+        self.path.with_extension("dirty")
+    }
+
+    fn stamp(&self, generation: u64) {
+        let content = format!("{generation}\n");
+        disk::write(self.expected_path(), content.as_bytes(), self.durability);
+        disk::write(
+            self.path.join("generation"),
+            content.as_bytes(),
+            self.durability,
+        );
+    }
+
+    fn remove_dirty_log(&self) {
+        *self.dirty.lock().unwrap() = None;
+        let _ = fs::remove_file(self.dirty_path());
+    }
+
+    // the slot holds a folder the array can use from now on
+    fn in_use(&self) {
+        self.absent.store(false, Ordering::SeqCst);
+        self.stale.lock().unwrap().clear();
+    }
+
+    /// Start bookkeeping for a new and empty device folder.
+    pub fn create(&self) {
+        self.remove_dirty_log();
+        self.in_use();
+        self.stamp(0);
+    }
+
+    /// The folder is there and was not missing since it was last reattached or rebuilt.
+    pub fn is_present(&self) -> bool {
+        if self.absent.load(Ordering::SeqCst) {
+            return false;
+        }
+        if !self.path.exists() {
+            self.absent.store(true, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    /// The device holds an up to date chunk of the slice.
+    pub fn has_chunk(&self, data_slice: usize) -> bool {
+        let rebuild = self.rebuild.lock().unwrap();
+        self.is_present()
+            && !rebuild.as_ref().is_some_and(|r| r.contains(&data_slice))
+            && !self.stale.lock().unwrap().contains(&data_slice)
+    }
+
+    pub fn is_rebuilding(&self) -> bool {
+        self.rebuild.lock().unwrap().is_some()
+    }
+
+    /// The device folder has the generation the array expects.
+    pub fn is_current(&self) -> bool {
+        let generation = read_generation(&self.path.join("generation"));
+        generation == Some(read_generation(&self.expected_path()).unwrap_or(0))
+    }
+
+    /// The spare folder the slot is linked to, if any.
+    pub fn spare(&self) -> Option<PathBuf> {
+        fs::read_link(&self.path).ok()
+    }
+
+    /// Take the device out of the array after an I/O error. The folder is kept as
+    /// `deviceN.failed` for inspection.
+    pub fn fail(&self) {
+        let failed_path = self.path.with_extension("failed");
+        let _ = fs::remove_dir_all(&failed_path);
+        let _ = fs::remove_file(&failed_path);
+        if fs::rename(&self.path, &failed_path).is_err() {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    /// Link the slot to an empty spare folder. The spare is rebuilt with `start_rebuild`.
+    pub fn promote(&self, spare: &Path) {
+        let _ = fs::remove_dir_all(spare);
+        fs::create_dir_all(spare).unwrap();
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                fs::remove_file(&self.path).unwrap()
+            }
+            Ok(_) => fs::remove_dir_all(&self.path).unwrap(),
+            Err(_) => {}
+        }
+        symlink(spare, &self.path).unwrap();
+        self.remove_dirty_log();
+        self.in_use();
+    }
+
+    /// Until `rebuilt` the slices in the range are treated as missing.
+    pub fn start_rebuild(&self, data_slices: Range<usize>) {
+        *self.rebuild.lock().unwrap() = Some(data_slices);
+        self.in_use();
+    }
+
+    /// All slices before `data_slice` are rebuilt. The device is `rebuilt` once the whole
+    /// range is done.
+    pub fn rebuilt_until(&self, data_slice: usize) {
+        let done = match self.rebuild.lock().unwrap().as_mut() {
+            Some(rebuild) => {
+                rebuild.start = rebuild.start.max(data_slice);
+                rebuild.start >= rebuild.end
+            }
+            None => false,
+        };
+        if done {
+            self.rebuilt();
+        }
+    }
+
+    /// Start copying the device to an empty folder, or continue an interrupted copy to the
+    /// same folder. Until `replaced` writes to the slices already copied go to both
+    /// folders, see `mirror`. Returns the first slice that is not copied yet.
+    pub fn start_replace(&self, target: &Path) -> usize {
+        let mut replacement = self.replacement.lock().unwrap();
+        if let Some(replacement) = replacement.as_ref().filter(|r| r.target == target) {
+            return replacement.copied;
+        }
+        let _ = fs::remove_dir_all(target);
+        fs::create_dir_all(target).unwrap();
+        let mut new = Replacement {
+            target: target.to_path_buf(),
+            copied: 0,
+            stored: 0,
+        };
+        self.store_replacement(&mut new);
+        *replacement = Some(new);
+        0
+    }
+
+    pub fn is_replacing(&self) -> bool {
+        self.replacement.lock().unwrap().is_some()
+    }
+
+    /// Number of slices already copied to the new folder.
+    pub fn copied(&self) -> usize {
+        let replacement = self.replacement.lock().unwrap();
+        replacement.as_ref().map_or(0, |r| r.copied)
+    }
+
+    /// Where the chunk of a copied slice is written too, `None` if it is not copied yet.
+    pub fn mirror(&self, file_path: &Path, data_slice: usize) -> Option<PathBuf> {
+        let replacement = self.replacement.lock().unwrap();
+        let replacement = replacement.as_ref()?;
+        (data_slice < replacement.copied)
+            .then(|| replacement.target.join(file_path.file_name().unwrap()))
+    }
+
+    /// The copy of a chunk in the new folder.
+    pub fn copy_path(&self, file_path: &Path) -> PathBuf {
+        let replacement = self.replacement.lock().unwrap();
+        let replacement = replacement.as_ref().unwrap();
+        replacement.target.join(file_path.file_name().unwrap())
+    }
+
+    /// All slices before `data_slice` are copied. Stored from time to time, a copy that
+    /// is continued after a crash starts at the last stored slice.
+    pub fn copied_until(&self, data_slice: usize) {
+        if let Some(replacement) = self.replacement.lock().unwrap().as_mut() {
+            replacement.copied = data_slice;
+            if data_slice >= replacement.stored + CHECKPOINT_INTERVAL {
+                self.store_replacement(replacement);
+            }
+        }
+    }
+
+    // rename the link over the slot, a folder can not be replaced by a link in one step so
+    // it is moved to `deviceN.replaced` first
+    fn switch_slot(&self, link: &Path) {
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => {
+                let old = self.path.with_extension("replaced");
+                let _ = fs::remove_dir_all(&old);
+                fs::rename(&self.path, &old).unwrap();
+            }
+            _ => {}
+        }
+        fs::rename(link, &self.path).unwrap();
+    }
+
+    // the slot links to the fully copied folder
+    fn switched(&self) {
+        self.in_use();
+        // the old folder is stale from now on
+        self.rebuilt();
+        let _ = fs::remove_file(self.progress_path());
+    }
+
+    /// Every slice is copied, link the slot to the new folder.
+    pub fn replaced(&self) {
+        let replacement = self.replacement.lock().unwrap().take().unwrap();
+        let link = self.path.with_extension("link");
+        let _ = fs::remove_file(&link);
+        symlink(&replacement.target, &link).unwrap();
+        self.switch_slot(&link);
+        self.switched();
+    }
+
+    /// A write to the slice was not applied. A missing device remembers the slice,
+    /// a rebuilding one writes it later anyway.
+    pub fn missed_write(&self, data_slice: usize) {
+        if !self.is_present() {
+            self.mark_dirty(data_slice);
+        }
+    }
+
+    /// The chunk of the slice can not be read. It is not used until the device is resynced or
+    /// rebuilt.
+    pub fn lost_chunk(&self, data_slice: usize) {
+        self.stale.lock().unwrap().insert(data_slice);
+    }
+
+    /// Remember that a slice was written while the device was missing.
+    pub fn mark_dirty(&self, data_slice: usize) {
+        let mut dirty = self.dirty.lock().unwrap();
+        dirty
+            .get_or_insert_with(|| Bitmap::open(&self.dirty_path(), self.durability))
+            .set(data_slice);
+    }
+
+    /// Check if a returned device can be resynced. Returns the slices it missed. The device
+    /// takes writes again, but its chunks of these slices are not read until `resynced`.
+    pub fn reattach(&self) -> Result<Vec<usize>, Error> {
+        if !self.path.exists() {
+            return Err(Error::Absent);
+        }
+        let generation = read_generation(&self.path.join("generation"));
+        let expected = read_generation(&self.expected_path()).unwrap_or(0);
+        if generation != Some(expected) {
+            return Err(Error::Stale {
+                generation,
+                expected,
+            });
+        }
+        let dirty = match self.dirty_path().exists() {
+            true => Bitmap::open(&self.dirty_path(), self.durability).set_slices(),
+            false => vec![],
+        };
+        *self.stale.lock().unwrap() = dirty.iter().copied().collect();
+        self.absent.store(false, Ordering::SeqCst);
+        Ok(dirty)
+    }
+
+    /// The missed slices of a reattached device are written again.
+    pub fn resynced(&self) {
+        self.remove_dirty_log();
+        self.stale.lock().unwrap().clear();
+    }
+
+    /// The device folder was rebuilt from scratch. Older copies of it are now stale.
+    pub fn rebuilt(&self) {
+        let generation = read_generation(&self.expected_path()).unwrap_or(0) + 1;
+        self.remove_dirty_log();
+        self.stamp(generation);
+        *self.rebuild.lock().unwrap() = None;
+        self.stale.lock().unwrap().clear();
+    }
+}
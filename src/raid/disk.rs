@@ -0,0 +1,121 @@
+//! Atomic chunk writes: write to a temp file, fsync, rename, fsync the directory.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// When chunk writes are forced to stable storage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Only temp file and rename. A process crash never leaves a torn chunk,
+    /// but a power loss can lose recent writes.
+    #[default]
+    NoSync,
+    /// Every chunk is fsynced, renamed and its directory fsynced on its own.
+    PerWrite,
+    /// All chunks of one operation (e.g. a whole slice) are fsynced together,
+    /// then renamed, then every touched directory is fsynced once.
+    GroupCommit,
+}
+
+pub fn tmp_path(path: &Path) -> PathBuf {
+    path.with_extension("tmp")
+}
+
+/// Read a whole chunk. None if the file does not exist, a file of another size is an
+/// `UnexpectedEof` error like a torn write.
+pub fn read_chunk<const X: usize>(path: &Path) -> io::Result<Option<Box<[u8; X]>>> {
+    match fs::read(path) {
+        Ok(bytes) => match bytes.into_boxed_slice().try_into() {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// The error comes from a failing device. Others, like a missing permission or a short
+/// file, only make the chunk unreadable, it is decoded from the other devices.
+pub fn is_media_error(err: &io::Error) -> bool {
+    // EIO, ENXIO and ENODEV
+    matches!(err.raw_os_error(), Some(5 | 6 | 19))
+}
+
+/// Remove the temp files a crash left in the folder. They were never renamed into place,
+/// so nothing refers to them.
+pub fn remove_tmp_files(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "tmp") && path.is_file() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn sync_dir(dir: &Path) {
+    File::open(dir).unwrap().sync_all().unwrap();
+}
+
+/// Chunk writes that become visible together on `commit`.
+pub struct Batch {
+    durability: Durability,
+    pending: Vec<(PathBuf, File)>,
+}
+
+impl Batch {
+    pub fn new(durability: Durability) -> Self {
+        Self {
+            durability,
+            pending: vec![],
+        }
+    }
+
+    pub fn write(&mut self, path: PathBuf, bytes: &[u8]) {
+        let mut file = File::create(tmp_path(&path)).unwrap();
+        file.write_all(bytes).unwrap();
+        self.written(path, file);
+    }
+
+    /// Add a temp file (see `tmp_path`) that was already written by the caller.
+    pub fn written(&mut self, path: PathBuf, file: File) {
+        match self.durability {
+            Durability::NoSync => fs::rename(tmp_path(&path), &path).unwrap(),
+            Durability::PerWrite => {
+                file.sync_data().unwrap();
+                fs::rename(tmp_path(&path), &path).unwrap();
+                sync_dir(path.parent().unwrap());
+            }
+            Durability::GroupCommit => self.pending.push((path, file)),
+        }
+    }
+
+    pub fn commit(self) {
+        for (_, file) in &self.pending {
+            file.sync_data().unwrap();
+        }
+        let mut dirs = vec![];
+        for (path, _) in &self.pending {
+            fs::rename(tmp_path(path), path).unwrap();
+            let dir = path.parent().unwrap();
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        for dir in dirs {
+            sync_dir(dir);
+        }
+    }
+}
+
+/// Write a single chunk atomically.
+pub fn write(path: PathBuf, bytes: &[u8], durability: Durability) {
+    let mut batch = Batch::new(durability);
+    batch.write(path, bytes);
+    batch.commit();
+}
@@ -1,21 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::fs;
 use std::fs::create_dir;
 use std::io;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{unbounded, Receiver, SendError, Sender};
+use crossbeam_channel::{unbounded, Receiver, RecvError, RecvTimeoutError, SendError, Sender};
 
 use crate::galois;
 use crate::galois::Galois;
 use crate::matrix::Matrix;
-use crate::raid::RAID;
+use crate::raid::bitmap::Bitmap;
+use crate::raid::device;
+use crate::raid::device::Device;
+use crate::raid::disk;
+use crate::raid::disk::Durability;
+use crate::raid::layout;
+use crate::raid::layout::Layout;
+use crate::raid::meta;
+use crate::raid::meta::Meta;
+use crate::raid::rebuild;
+use crate::raid::rebuild::{Job, Progress, Running, CHECKPOINT_INTERVAL};
+use crate::raid::transport::{Budget, Channels, Listener, Packet, Requests, Tcp, Transport};
+use crate::raid::{Config, RAID};
 
 #[derive(Debug)]
 pub enum Error {
     Shutdown,
+    // the in-flight budget of the head node is used up
+    Busy,
+    // fewer than D nodes sent their chunk of the slice
+    Lost { data_slice: usize },
 }
 
 impl<T> From<SendError<T>> for Error {
@@ -24,13 +45,21 @@ impl<T> From<SendError<T>> for Error {
     }
 }
 
+impl From<RecvError> for Error {
+    fn from(_: RecvError) -> Self {
+        Self::Shutdown
+    }
+}
+
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug)]
-pub struct CheckpointMsg<const X: usize> {
-    data_slice: usize,
-    data: Box<[Galois; X]>,
-}
+/// Matches a reply to the request of the head node.
+pub type RequestId = u64;
+
+/// Orders the writes of a slice. The head node hands out increasing versions, a write of the
+/// whole slice starts an epoch and the writes of single chunks build on the last epoch. A
+/// node drops writes it has seen or that a newer epoch replaced, 0 is older than every write.
+pub type Version = u64;
 
 #[derive(Debug)]
 pub enum Msg<const X: usize> {
@@ -38,70 +67,510 @@ pub enum Msg<const X: usize> {
     NewData {
         data_slice: usize,
         data: Box<[Galois; X]>,
+        version: Version,
     },
     // New chunk only for this device
     NewDataAt {
         data_slice: usize,
         data: Box<[Galois; X]>,
+        version: Version,
+        epoch: Version,
     },
     // New checksum for the whole slice
     NewDataChecksum {
         data_slice: usize,
         data: Box<[Galois; X]>,
         dev_idx: usize,
+        version: Version,
     },
     // New checksum only for this device
     NewDataChecksumAt {
         data_slice: usize,
         data: Box<[Galois; X]>,
         dev_idx: usize,
+        version: Version,
+        epoch: Version,
     },
     // update chunk
     UpdateData {
         data_slice: usize,
         data: Box<[Galois; X]>,
+        version: Version,
+        epoch: Version,
     },
     // request to update checksum
     UpdateDataChecksum {
         data_slice: usize,
         diff: Box<[Galois; X]>,
         dev_idx: usize,
+        version: Version,
+        epoch: Version,
+    },
+    // New chunk for the whole slice, the checksums are combined along the data nodes, see
+    // `Aggregation`
+    NewDataAggregated {
+        data_slice: usize,
+        data: Box<[Galois; X]>,
+        version: Version,
+        fan_in: usize,
+    },
+    // sum of the chunks of a data node and the data nodes below it for one checksum
+    PartialChecksum {
+        data_slice: usize,
+        check_idx: usize,
+        partial: Box<[Galois; X]>,
+        version: Version,
+        fan_in: usize,
     },
-    // request for chunk for data recovery
+    // the checksum of an aggregated write of the whole slice
+    NewChecksum {
+        data_slice: usize,
+        checksum: Box<[Galois; X]>,
+        version: Version,
+    },
+    // request for chunk for data recovery, the reply carries the `round`
     NeedRecover {
         data_slice: usize,
         dev_idx: usize,
+        round: u64,
     },
     // head node request chunk. For the read operation
     HeadNodeDataRequest {
         data_slice: usize,
-        oneshot_send: oneshot::Sender<CheckpointMsg<X>>,
+        request: RequestId,
+    },
+    // head node request data or checksum chunk. For degraded reads
+    HeadNodeChunkRequest {
+        data_slice: usize,
+        request: RequestId,
     },
-    // simulate the loss of a device
+    // storage is back after an outage, resync the slices written in the meantime
+    Reattach {
+        request: RequestId,
+    },
+    // simulate the loss of a device, the slices are missing until they are recovered
     DestroyStorage {
-        max_data_slice: usize,
+        data_slices: Range<usize>,
+    },
+    // the storage is lost and a spare takes over the slot, stop the thread
+    Fail,
+    // the slices are missing until they are recovered
+    Rebuild {
+        data_slices: Range<usize>,
+    },
+    // recover the slices, part of a running rebuild
+    Recover {
+        data_slices: Range<usize>,
+        request: RequestId,
+        repair: Repair,
+    },
+    // start the running sum that repairs the chunk of `dev_idx`, see `Repair`. The sum is
+    // passed along `helpers`, this node first, every helper adds its chunk times its
+    // coefficient
+    Repair {
+        data_slice: usize,
+        dev_idx: usize,
+        round: u64,
+        helpers: Vec<usize>,
+        coefficients: Vec<Galois>,
+    },
+    // a piece of the running sum that starts at `offset` of the chunk, for the helpers left
+    RepairPiece {
+        data_slice: usize,
+        dev_idx: usize,
+        round: u64,
+        helpers: Vec<usize>,
+        coefficients: Vec<Galois>,
+        offset: usize,
+        sum: Vec<Galois>,
+    },
+    // start copying the storage to a new folder
+    Replace {
+        path: PathBuf,
+    },
+    // copy the chunks of the slices to the new folder
+    Copy {
+        data_slices: Range<usize>,
+        request: RequestId,
     },
-    // used to see when the thread has finished
+    // everything is copied, switch over to the new folder
+    Replaced {
+        request: RequestId,
+    },
+    // used to see when the thread has finished, the messages the node sent before are
+    // queued at their receivers
     Ping {
-        oneshot_send: oneshot::Sender<()>,
+        request: RequestId,
+    },
+    // state of the storage, when the array is opened
+    Status {
+        request: RequestId,
+    },
+    // the head node changed the state of a node, see `Health`
+    State {
+        dev_idx: usize,
+        state: NodeState,
+    },
+    // a beat of the head node, answered by the loop of the node, see `Heartbeat`
+    Heartbeat {
+        request: RequestId,
     },
     // Shutdown
     Shutdown,
 }
 
+/// Answer to a recovering node. `round` is the one of the request, the node drops answers
+/// to its earlier recoveries.
 #[derive(Debug)]
 pub enum RecoverMsg<const X: usize> {
     RequestedData {
         data_slice: usize,
         data: Box<[Galois; X]>,
         dev_idx: usize,
+        round: u64,
+    },
+    // a piece of the repaired chunk, from the last helper
+    RepairedPiece {
+        data_slice: usize,
+        round: u64,
+        offset: usize,
+        data: Vec<Galois>,
+    },
+    // a helper can not add its chunk to the running sum
+    RepairFailed {
+        data_slice: usize,
+        round: u64,
+    },
+}
+
+/// Answer of a node to a request of the head node.
+#[derive(Debug)]
+pub enum Reply<const X: usize> {
+    // chunk for a read, None if the storage of the node is missing
+    Chunk {
+        request: RequestId,
+        data: Option<Box<[Galois; X]>>,
+    },
+    Reattach {
+        request: RequestId,
+        result: std::result::Result<usize, device::Error>,
+    },
+    Status {
+        request: RequestId,
+        status: Status,
+    },
+    Done {
+        request: RequestId,
     },
 }
 
+impl<const X: usize> Msg<X> {
+    // bytes the head node counts against its `Budget` while the message is queued
+    fn bytes(&self) -> u64 {
+        match self {
+            Self::NewData { .. }
+            | Self::NewDataAt { .. }
+            | Self::NewDataChecksum { .. }
+            | Self::NewDataChecksumAt { .. }
+            | Self::UpdateData { .. }
+            | Self::UpdateDataChecksum { .. }
+            | Self::NewDataAggregated { .. }
+            | Self::PartialChecksum { .. }
+            | Self::NewChecksum { .. } => X as u64,
+            _ => 0,
+        }
+    }
+}
+
+impl<const X: usize> Reply<X> {
+    pub fn request(&self) -> RequestId {
+        match self {
+            Self::Chunk { request, .. }
+            | Self::Reattach { request, .. }
+            | Self::Status { request, .. }
+            | Self::Done { request } => *request,
+        }
+    }
+
+    fn chunk(self) -> Option<Box<[Galois; X]>> {
+        let Self::Chunk { data, .. } = self else {
+            panic!("expected a chunk")
+        };
+        data
+    }
+
+    fn reattach(self) -> std::result::Result<usize, device::Error> {
+        let Self::Reattach { result, .. } = self else {
+            panic!("expected the result of a reattach")
+        };
+        result
+    }
+
+    fn status(self) -> Status {
+        let Self::Status { status, .. } = self else {
+            panic!("expected a status")
+        };
+        status
+    }
+}
+
+/// State of the storage of a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub present: bool,
+    pub current: bool,
+    pub max_data_slice: usize,
+    /// Highest version of a write the node has seen.
+    pub version: Version,
+}
+
+impl Status {
+    fn state(&self) -> NodeState {
+        match (self.present, self.current) {
+            (true, true) => NodeState::Healthy,
+            (true, false) => NodeState::Rebuilding,
+            (false, _) => NodeState::Failed,
+        }
+    }
+}
+
+// a spare thread takes over the slot `dev_idx`
+struct Promotion<const X: usize> {
+    dev_idx: usize,
+    // slices the spare has to recover
+    data_slices: Range<usize>,
+    rec: Receiver<Msg<X>>,
+    recover_rec: Receiver<RecoverMsg<X>>,
+}
+
+// idle thread that becomes a `Node` once a device fails
+struct Spare<const X: usize> {
+    promote: Sender<Promotion<X>>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Healthy,
+    // the storage is recovered, its chunks must not be used to decode
+    Rebuilding,
+    // the storage is missing or returned an I/O error
+    Failed,
+    // the node does not answer heartbeats, the head node reads and writes around it
+    Down,
+}
+
+/// State of every node, shared by the head node and the nodes. A node only asks healthy
+/// nodes for chunks, so concurrent rebuilds never decode from each other. Nodes in other
+/// processes keep their own copy, the head node sends them every change.
+pub struct Health {
+    states: Mutex<Vec<NodeState>>,
+    // nodes report I/O errors to the head node
+    failures: Sender<usize>,
+    // tells the nodes in other processes about a changed state
+    changed: Option<Box<dyn Fn(usize, NodeState) + Send + Sync>>,
+}
+
+impl Health {
+    fn new(states: Vec<NodeState>, failures: Sender<usize>) -> Self {
+        Self {
+            states: Mutex::new(states),
+            failures,
+            changed: None,
+        }
+    }
+
+    // the head node of nodes in other processes, every change is sent to all nodes
+    fn broadcast<const X: usize>(mut self, transport: Arc<dyn Transport<X>>) -> Self {
+        self.changed = Some(Box::new(move |dev_idx, state| {
+            for i in 0..transport.nodes() {
+                // a node that is not reachable gets all states when it is back
+                let _ = transport.send(i, Msg::State { dev_idx, state });
+            }
+        }));
+        self
+    }
+
+    // send all states to the nodes again, they may have started with all nodes healthy
+    fn announce(&self) {
+        let states = self.states.lock().unwrap();
+        if let Some(changed) = &self.changed {
+            for (dev_idx, state) in states.iter().enumerate() {
+                changed(dev_idx, *state);
+            }
+        }
+    }
+
+    pub fn state(&self, dev_idx: usize) -> NodeState {
+        self.states.lock().unwrap()[dev_idx]
+    }
+
+    pub fn states(&self) -> Vec<NodeState> {
+        self.states.lock().unwrap().clone()
+    }
+
+    fn set(&self, dev_idx: usize, state: NodeState) {
+        let mut states = self.states.lock().unwrap();
+        if states[dev_idx] == state {
+            return;
+        }
+        states[dev_idx] = state;
+        // under the lock, so the nodes get the changes in order
+        if let Some(changed) = &self.changed {
+            changed(dev_idx, state);
+        }
+    }
+
+    fn is_healthy(&self, dev_idx: usize) -> bool {
+        self.state(dev_idx) == NodeState::Healthy
+    }
+
+    // the storage of the node failed, the head node lets a spare take over
+    fn fail(&self, dev_idx: usize) {
+        self.set(dev_idx, NodeState::Failed);
+        let _ = self.failures.send(dev_idx);
+    }
+
+    // the node stopped answering heartbeats, the head node lets a spare take over
+    fn down(&self, dev_idx: usize) {
+        self.set(dev_idx, NodeState::Down);
+        let _ = self.failures.send(dev_idx);
+    }
+}
+
+/// Heartbeats of the head node of a `Checkpoint`, answered by the loop of every node. A
+/// node that misses `suspicion` heartbeats in a row is marked down and a spare takes over
+/// if there is one. Once it answers again, for example a new process on its address, its
+/// storage is rebuilt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Time between two heartbeats.
+    pub interval: Duration,
+    /// A heartbeat without an answer in this time is missed.
+    pub timeout: Duration,
+    pub suspicion: u32,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(1),
+            suspicion: 3,
+        }
+    }
+}
+
+/// How the checksums of a whole slice written to a `Checkpoint` reach the checksum nodes.
+/// The head node falls back to `Direct` for slices with a node that is not healthy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Every data node sends its chunk to every checksum node, D * C chunks that the
+    /// checksum nodes add up.
+    #[default]
+    Direct,
+    /// Every data node multiplies its chunk for every checksum, adds the partial checksums
+    /// of the data node after it and passes them on. The first data node sends every
+    /// checksum node its checksum.
+    Chain,
+    /// Like `Chain` along a binary tree, fewer hops for wide slices.
+    Tree,
+}
+
+impl Aggregation {
+    // data nodes that send their partial checksums to one data node, None for `Direct`
+    fn fan_in(self) -> Option<usize> {
+        match self {
+            Self::Direct => None,
+            Self::Chain => Some(1),
+            Self::Tree => Some(2),
+        }
+    }
+
+    // None for the first data node, the root
+    fn parent(data_idx: usize, fan_in: usize) -> Option<usize> {
+        (data_idx > 0).then(|| (data_idx - 1) / fan_in)
+    }
+
+    fn children(data_idx: usize, fan_in: usize, data: usize) -> Range<usize> {
+        (fan_in * data_idx + 1).min(data)..(fan_in * data_idx + fan_in + 1).min(data)
+    }
+
+    // messages a write of a whole slice passes through from the head node to the
+    // checksum nodes, one ping round each
+    fn hops(self, data: usize) -> usize {
+        let Some(fan_in) = self.fan_in() else {
+            return 2;
+        };
+        let mut depth = 0;
+        let mut data_idx = data - 1;
+        while let Some(parent) = Self::parent(data_idx, fan_in) {
+            data_idx = parent;
+            depth += 1;
+        }
+        depth + 2
+    }
+}
+
+/// How a node of a `Checkpoint` that is rebuilt gets the chunks it lost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Repair {
+    /// The other nodes send their chunks, the rebuilding node decodes from D of them.
+    #[default]
+    Direct,
+    /// D other nodes multiply their chunk by its decode coefficient and add it to a running
+    /// sum passed from one to the next in pieces of the chunk. The rebuilding node receives
+    /// one chunk per slice and the pieces overlap on the way.
+    Pipelined,
+}
+
+// the head node checks on the nodes until it is shut down
+struct Monitor {
+    // dropped to stop the thread
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
 struct CurrentChecksumStatus<const X: usize> {
     count: usize,
     current_checksum: Box<[Galois; X]>,
-    missed_recover_dev_idx: Vec<usize>,
+    // nodes that asked for the checksum and the round they asked in
+    missed_recover_dev_idx: Vec<(usize, u64)>,
+}
+
+// change of a data chunk that a checksum node adds to its checksum
+struct Delta<const X: usize> {
+    data_idx: usize,
+    diff: Box<[Galois; X]>,
+    version: Version,
+    epoch: Version,
+}
+
+// the writes of a slice a node has handled
+struct Versions<const X: usize> {
+    epoch: Version,
+    // last version of every data chunk
+    chunks: Vec<Version>,
+    // deltas of an epoch that has not arrived yet
+    early: Vec<Delta<X>>,
+}
+
+// partial checksums of a slice a data node combines, see `Aggregation`
+struct Aggregate<const X: usize> {
+    version: Version,
+    partials: Vec<Box<[Galois; X]>>,
+    // the own chunk and the partials of the children that did not arrive yet, 0 once the
+    // partials are sent
+    remaining: usize,
+}
+
+// a slice a rebuilding node waits for
+enum Pending<const X: usize> {
+    // chunks of other nodes by their index in the slice
+    Chunks(Vec<(usize, Box<[Galois; X]>)>),
+    // the chunk the helpers sum up and the offsets of the pieces that arrived, a piece can
+    // arrive twice
+    Pieces(Box<[Galois; X]>, HashSet<usize>),
 }
 
 pub struct Node<const D: usize, const C: usize, const X: usize>
@@ -113,10 +582,20 @@ where
 {
     dev_idx: usize,
     vandermonde: Matrix<C, D>,
-    path: PathBuf,
-    coms: [Sender<Msg<X>>; D + C],
-    recover_coms: [Sender<RecoverMsg<X>>; D + C],
+    device: Device,
+    durability: Durability,
+    layout: Arc<dyn Layout>,
+    transport: Arc<dyn Transport<X>>,
+    health: Arc<Health>,
     current_checksum: HashMap<usize, CurrentChecksumStatus<X>>,
+    versions: HashMap<usize, Versions<X>>,
+    aggregates: HashMap<usize, Aggregate<X>>,
+    // slices whose unfinished checksum changed since it was stored and the chunks added
+    // since then, see `store_partials`
+    unsaved: HashSet<usize>,
+    since_stored: usize,
+    // last recovery of this node, see `RecoverMsg`
+    recover_round: AtomicU64,
 }
 
 impl<const D: usize, const C: usize, const X: usize> Node<D, C, X>
@@ -130,27 +609,109 @@ where
         path: PathBuf,
         dev_idx: usize,
         vandermonde: Matrix<C, D>,
-        coms: [Sender<Msg<X>>; D + C],
-        recover_coms: [Sender<RecoverMsg<X>>; D + C],
+        durability: Durability,
+        layout: Arc<dyn Layout>,
+        transport: Arc<dyn Transport<X>>,
+        health: Arc<Health>,
     ) -> Self {
         let _ = std::fs::remove_dir_all(&path);
         create_dir(&path).unwrap();
-        Self {
+        let node = Self::open(
             path,
             dev_idx,
             vandermonde,
-            coms,
-            recover_coms,
+            durability,
+            layout,
+            transport,
+            health,
+        );
+        node.device.create();
+        node
+    }
+
+    /// Node that keeps the chunks already stored in `path`, and the checksums it was still
+    /// adding up when it stopped, as far as they were stored
+    pub fn open(
+        path: PathBuf,
+        dev_idx: usize,
+        vandermonde: Matrix<C, D>,
+        durability: Durability,
+        layout: Arc<dyn Layout>,
+        transport: Arc<dyn Transport<X>>,
+        health: Arc<Health>,
+    ) -> Self {
+        disk::remove_tmp_files(&path);
+        let mut node = Self {
+            device: Device::new(path, durability),
+            durability,
+            layout,
+            dev_idx,
+            vandermonde,
+            transport,
+            health,
             current_checksum: HashMap::new(),
-        }
+            versions: HashMap::new(),
+            aggregates: HashMap::new(),
+            unsaved: HashSet::new(),
+            since_stored: 0,
+            recover_round: AtomicU64::new(0),
+        };
+        node.load_partials();
+        node
+    }
+
+    /// Run the node of slot `dev_idx` in this process until the head node shuts it down. The
+    /// head node and the other nodes are reached at the addresses of `config.peers`. Keeps
+    /// the chunks already stored in `path` if `open`.
+    pub fn serve(path: PathBuf, dev_idx: usize, config: &Config, open: bool) -> io::Result<()> {
+        let peers = config
+            .peers
+            .as_ref()
+            .expect("the node needs the addresses of its peers");
+        Checkpoint::<D, C, X>::check_layout(config)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("{err:?}")))?;
+        let (send, rec) = unbounded();
+        let (recover_send, recover_rec) = unbounded();
+        let _listener = Listener::bind::<X>(peers.nodes[dev_idx], move |packet| match packet {
+            Packet::Msg(msg) => {
+                let _ = send.send(msg);
+            }
+            Packet::Recover(msg) => {
+                let _ = recover_send.send(msg);
+            }
+            // replies and credits only go to the head node
+            Packet::Reply(_) | Packet::Credit { .. } => {}
+        })?;
+        let transport = Arc::new(Tcp::new(peers));
+        // I/O errors are not reported, the head node notices the missing chunks
+        let health = Arc::new(Health::new(vec![NodeState::Healthy; D + C], unbounded().0));
+        let v = Matrix::<C, D>::reed_solomon();
+        let durability = config.durability;
+        let layout = config.layout(D, C).unwrap();
+        let t = transport.clone();
+        let node = if open {
+            Self::open(path, dev_idx, v, durability, layout, t, health)
+        } else {
+            Self::new(path, dev_idx, v, durability, layout, t, health)
+        };
+        node.health.set(dev_idx, node.status().state());
+        let _ = node.start(rec, recover_rec);
+        // the replies to the last requests
+        Transport::<X>::sync(&*transport);
+        Ok(())
+    }
+
+    // every node has a chunk of every slice, see `check_layout`
+    fn data_check_idx(&self, dev_idx: usize, data_slice: usize) -> usize {
+        self.layout.chunk(D, C, data_slice, dev_idx).unwrap()
     }
 
-    fn data_check_idx(dev_idx: usize, data_slice: usize) -> usize {
-        ((dev_idx as isize - data_slice as isize).rem_euclid((D + C) as isize)) as usize
+    fn dev_idx(&self, data_slice: usize, data_check_idx: usize) -> usize {
+        self.layout.device(D, C, data_slice, data_check_idx)
     }
 
     fn data_idx(&self, data_slice: usize) -> usize {
-        let idx = Self::data_check_idx(self.dev_idx, data_slice);
+        let idx = self.data_check_idx(self.dev_idx, data_slice);
         if idx >= D {
             panic!("not good");
         }
@@ -158,7 +719,7 @@ where
     }
 
     fn check_idx(&self, data_slice: usize) -> usize {
-        let idx = Self::data_check_idx(self.dev_idx, data_slice);
+        let idx = self.data_check_idx(self.dev_idx, data_slice);
         if idx < D || idx >= C + D {
             panic!("not good {} {} {}", idx, self.dev_idx, data_slice);
         }
@@ -175,47 +736,398 @@ where
         format!("{}_{}c.bin", data_slice, idx)
     }
 
+    // the checksum of a slice while its data chunks arrive, see `store_partial`
+    fn partial_file(&self, data_slice: usize) -> PathBuf {
+        let idx = self.check_idx(data_slice);
+        self.device
+            .path
+            .join(format!("{}_{}c.part", data_slice, idx))
+    }
+
     fn data_file(&self, data_slice: usize) -> PathBuf {
         let name = self.data_name(data_slice);
-        self.path.join(name)
+        self.device.path.join(name)
     }
     fn checksum_file(&self, data_slice: usize) -> PathBuf {
         let name = self.checksum_name(data_slice);
-        self.path.join(name)
+        self.device.path.join(name)
     }
 
-    fn read_data(&self, data_slice: usize) -> Box<[Galois; X]> {
-        let file_path = self.data_file(data_slice);
-        match fs::read(&file_path) {
-            Ok(file) => galois::from_bytes(file.into_boxed_slice().try_into().unwrap()),
+    // chunks that were never written are zero. None if the storage failed
+    fn try_read(&self, file_path: &Path) -> Option<Box<[Galois; X]>> {
+        match disk::read_chunk(file_path) {
+            Ok(chunk) => Some(chunk.map_or_else(galois::zeros, galois::from_bytes)),
             Err(err) => {
-                let io::ErrorKind::NotFound = err.kind() else {
-                    panic!("{:?}", err)
-                };
-                galois::zeros()
+                self.failed_read(&err);
+                None
             }
         }
     }
 
-    fn read_checksum(&self, data_slice: usize) -> Box<[Galois; X]> {
-        let file_path = self.checksum_file(data_slice);
-        galois::from_bytes(
-            fs::read(file_path)
-                .unwrap()
-                .into_boxed_slice()
-                .try_into()
-                .unwrap(),
-        )
+    // a media error takes the storage out, other errors only make the chunk unreadable
+    fn failed_read(&self, err: &io::Error) {
+        if disk::is_media_error(err) {
+            self.device.fail();
+            self.health.fail(self.dev_idx);
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            present: self.device.is_present(),
+            current: self.device.is_current(),
+            max_data_slice: meta::max_data_slice(std::slice::from_ref(&self.device.path)),
+            version: self
+                .versions
+                .values()
+                .flat_map(|versions| versions.chunks.iter().chain([&versions.epoch]))
+                .copied()
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    fn versions(&mut self, data_slice: usize) -> &mut Versions<X> {
+        self.versions.entry(data_slice).or_insert_with(|| Versions {
+            epoch: 0,
+            chunks: vec![0; D],
+            early: vec![],
+        })
+    }
+
+    // true the first time the write of the data chunk is seen, false if it is sent again or
+    // a newer epoch replaced it
+    fn is_new(&mut self, data_slice: usize, data_idx: usize, version: Version) -> bool {
+        let versions = self.versions(data_slice);
+        if version < versions.epoch || version <= versions.chunks[data_idx] {
+            return false;
+        }
+        versions.chunks[data_idx] = version;
+        true
+    }
+
+    // add the change of a data chunk to the checksum. A delta that builds on an epoch the
+    // node has not seen yet waits for it
+    fn apply_delta(&mut self, data_slice: usize, delta: Delta<X>) {
+        let versions = self.versions(data_slice);
+        if delta.epoch > versions.epoch {
+            versions.early.push(delta);
+            return;
+        }
+        if !self.is_new(data_slice, delta.data_idx, delta.version) {
+            return;
+        }
+        let coefficient = self.vandermonde[self.check_idx(data_slice)][delta.data_idx];
+        if let Some(current_status) = self.current_checksum.get_mut(&data_slice) {
+            // still waiting for data chunks
+            let new_checksum = galois::from_fn(|i| {
+                current_status.current_checksum[i] + coefficient * delta.diff[i]
+            });
+            current_status.current_checksum = new_checksum;
+            self.partial_changed(data_slice);
+        } else if !self.device.has_chunk(data_slice) {
+            // the checksum is recomputed when the storage is back or rebuilt
+            self.device.missed_write(data_slice);
+        } else if let Some(current_checksum) = self.try_read(&self.checksum_file(data_slice)) {
+            // not waiting for additional data chunks. A replacement node gets the updates it
+            // missed before it is rebuilt, on top of a zero checksum
+            let new_checksum =
+                galois::from_fn(|i| current_checksum[i] + coefficient * delta.diff[i]);
+            self.write_checksum(data_slice, &new_checksum);
+        } else {
+            // the checksum can not be read, reads decode around it until the node is rebuilt
+            self.device.lost_chunk(data_slice);
+        }
+    }
+
+    // add `chunks`, by check idx, to the partial checksums of the write. Once the own chunk
+    // and the partials of the children are added, the partials go to the parent, or from the
+    // root to the checksum nodes
+    fn aggregate(
+        &mut self,
+        data_slice: usize,
+        version: Version,
+        fan_in: usize,
+        chunks: impl IntoIterator<Item = (usize, Box<[Galois; X]>)>,
+    ) -> Result<()> {
+        let data_idx = self.data_idx(data_slice);
+        let children = Aggregation::children(data_idx, fan_in, D).len();
+        let aggregate = self.aggregates.entry(data_slice).or_insert(Aggregate {
+            version: 0,
+            partials: vec![],
+            remaining: 0,
+        });
+        if version < aggregate.version || (version == aggregate.version && aggregate.remaining == 0)
+        {
+            // a newer write replaced it, or it is sent again
+            return Ok(());
+        }
+        if version > aggregate.version {
+            *aggregate = Aggregate {
+                version,
+                partials: (0..C).map(|_| galois::zeros()).collect(),
+                remaining: 1 + children * C,
+            };
+        }
+        for (check_idx, chunk) in chunks {
+            let partial = &mut aggregate.partials[check_idx];
+            *partial = galois::from_fn(|i| partial[i] + chunk[i]);
+        }
+        aggregate.remaining -= 1;
+        if aggregate.remaining > 0 {
+            return Ok(());
+        }
+        let partials = std::mem::take(&mut aggregate.partials);
+        for (check_idx, partial) in partials.into_iter().enumerate() {
+            let (dev_idx, msg) = match Aggregation::parent(data_idx, fan_in) {
+                Some(parent) => (
+                    self.dev_idx(data_slice, parent),
+                    Msg::PartialChecksum {
+                        data_slice,
+                        check_idx,
+                        partial,
+                        version,
+                        fan_in,
+                    },
+                ),
+                None => (
+                    self.dev_idx(data_slice, check_idx + D),
+                    Msg::NewChecksum {
+                        data_slice,
+                        checksum: partial,
+                        version,
+                    },
+                ),
+            };
+            self.transport.send(dev_idx, msg)?;
+        }
+        Ok(())
+    }
+
+    // like `try_read_chunk` for the part `range` of the chunk
+    fn try_read_piece(&self, data_slice: usize, range: Range<usize>) -> Option<Vec<Galois>> {
+        if !self.device.has_chunk(data_slice) {
+            return None;
+        }
+        let file_path = if self.data_check_idx(self.dev_idx, data_slice) < D {
+            self.data_file(data_slice)
+        } else {
+            self.checksum_file(data_slice)
+        };
+        let mut piece = vec![0; range.len()];
+        let read = fs::File::open(file_path).and_then(|mut file| {
+            // a short file is a torn chunk, see `disk::read_chunk`
+            if file.metadata()?.len() != X as u64 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            file.seek(SeekFrom::Start(range.start as u64))?;
+            file.read_exact(&mut piece)
+        });
+        match read {
+            Ok(()) => Some(piece.into_iter().map(Galois::new).collect()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Some(vec![Galois::zero(); range.len()])
+            }
+            Err(err) => {
+                self.failed_read(&err);
+                None
+            }
+        }
+    }
+
+    // add the part `range` of the chunk times `coefficient` to the piece of a running sum,
+    // None if this node can not help
+    fn add_to_repair(
+        &self,
+        data_slice: usize,
+        range: Range<usize>,
+        coefficient: Galois,
+        sum: Option<&[Galois]>,
+    ) -> Option<Vec<Galois>> {
+        // a checksum that is still added up is not ready
+        if !self.health.is_healthy(self.dev_idx) || self.current_checksum.contains_key(&data_slice)
+        {
+            return None;
+        }
+        let piece = self.try_read_piece(data_slice, range)?;
+        let sum = piece
+            .iter()
+            .enumerate()
+            .map(|(i, value)| sum.map_or(Galois::zero(), |sum| sum[i]) + coefficient * *value);
+        Some(sum.collect())
+    }
+
+    // send the piece of the running sum to the next helper, or the last helper sends it to
+    // the rebuilding node
+    #[allow(clippy::too_many_arguments)]
+    fn pass_on(
+        &self,
+        data_slice: usize,
+        dev_idx: usize,
+        round: u64,
+        helpers: &[usize],
+        coefficients: &[Galois],
+        offset: usize,
+        sum: Vec<Galois>,
+    ) -> Result<()> {
+        match helpers.get(1) {
+            Some(next) => self.transport.send(
+                *next,
+                Msg::RepairPiece {
+                    data_slice,
+                    dev_idx,
+                    round,
+                    helpers: helpers[1..].to_vec(),
+                    coefficients: coefficients[1..].to_vec(),
+                    offset,
+                    sum,
+                },
+            ),
+            None => self.transport.send_recover(
+                dev_idx,
+                RecoverMsg::RepairedPiece {
+                    data_slice,
+                    round,
+                    offset,
+                    data: sum,
+                },
+            ),
+        }
+    }
+
+    // chunk of this node if it is up to date and readable
+    fn try_read_chunk(&self, data_slice: usize) -> Option<Box<[Galois; X]>> {
+        if !self.device.has_chunk(data_slice) {
+            return None;
+        }
+        if self.data_check_idx(self.dev_idx, data_slice) < D {
+            self.try_read(&self.data_file(data_slice))
+        } else {
+            self.try_read(&self.checksum_file(data_slice))
+        }
     }
 
     fn write_data(&self, data_slice: usize, data: &[Galois; X]) {
-        let file_path = self.data_file(data_slice);
-        fs::write(file_path, galois::as_bytes_ref(data)).unwrap();
+        self.write_chunk(self.data_file(data_slice), data_slice, data);
     }
 
     fn write_checksum(&self, data_slice: usize, check: &[Galois; X]) {
-        let file_path = self.checksum_file(data_slice);
-        fs::write(file_path, galois::as_bytes_ref(check)).unwrap();
+        self.write_chunk(self.checksum_file(data_slice), data_slice, check);
+    }
+
+    // the unfinished checksum of the slice changed, the changed ones are stored every
+    // `CHECKPOINT_INTERVAL` chunks
+    fn partial_changed(&mut self, data_slice: usize) {
+        self.unsaved.insert(data_slice);
+        self.since_stored += 1;
+        if self.since_stored >= CHECKPOINT_INTERVAL {
+            self.store_partials();
+        }
+    }
+
+    // keep the changed unfinished checksums on disk, a restarted node continues with them.
+    // A crash loses the chunks added since, like a node that stopped before they arrived
+    fn store_partials(&mut self) {
+        for data_slice in std::mem::take(&mut self.unsaved) {
+            self.store_partial(data_slice);
+        }
+        self.since_stored = 0;
+    }
+
+    // the file has the epoch, the number of data chunks added, the version of every data
+    // chunk and the checksum so far
+    fn store_partial(&self, data_slice: usize) {
+        if !self.device.is_present() {
+            return;
+        }
+        let Some(status) = self.current_checksum.get(&data_slice) else {
+            return;
+        };
+        let versions = &self.versions[&data_slice];
+        let mut bytes = vec![];
+        bytes.extend(versions.epoch.to_le_bytes());
+        bytes.extend((status.count as u64).to_le_bytes());
+        for version in &versions.chunks {
+            bytes.extend(version.to_le_bytes());
+        }
+        bytes.extend(galois::as_bytes_ref(&status.current_checksum));
+        disk::write(self.partial_file(data_slice), &bytes, self.durability);
+    }
+
+    // the checksum of the slice is complete
+    fn remove_partial(&mut self, data_slice: usize) {
+        self.unsaved.remove(&data_slice);
+        let _ = fs::remove_file(self.partial_file(data_slice));
+    }
+
+    // the unfinished checksums stored by `store_partial`. A file that can not be read is
+    // removed, the node continues without it
+    fn load_partials(&mut self) {
+        let Ok(entries) = fs::read_dir(&self.device.path) else {
+            return;
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if !path.extension().is_some_and(|ext| ext == "part") {
+                continue;
+            }
+            let partial = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split_once('_'))
+                .and_then(|(data_slice, _)| data_slice.parse().ok())
+                .and_then(|data_slice| {
+                    let bytes = fs::read(&path).ok()?;
+                    Some((data_slice, Self::parse_partial(&bytes)?))
+                });
+            match partial {
+                Some((data_slice, (versions, status))) => {
+                    self.versions.insert(data_slice, versions);
+                    self.current_checksum.insert(data_slice, status);
+                }
+                None => {
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+    }
+
+    // None if the file is short or does not belong to an unfinished checksum
+    fn parse_partial(bytes: &[u8]) -> Option<(Versions<X>, CurrentChecksumStatus<X>)> {
+        if bytes.len() != 8 * (D + 2) + X {
+            return None;
+        }
+        let (header, checksum) = bytes.split_at(8 * (D + 2));
+        let mut numbers = header
+            .chunks(8)
+            .map(|number| u64::from_le_bytes(number.try_into().unwrap()));
+        let epoch = numbers.next().unwrap();
+        let count = numbers.next().unwrap() as usize;
+        if count >= D {
+            return None;
+        }
+        let versions = Versions {
+            epoch,
+            chunks: numbers.collect(),
+            early: vec![],
+        };
+        let status = CurrentChecksumStatus {
+            count,
+            current_checksum: galois::from_bytes(checksum.to_vec().try_into().unwrap()),
+            missed_recover_dev_idx: vec![],
+        };
+        Some((versions, status))
+    }
+
+    fn write_chunk(&self, file_path: PathBuf, data_slice: usize, chunk: &[Galois; X]) {
+        // a device that is replaced gets the write in both folders
+        if let Some(mirror) = self.device.mirror(&file_path, data_slice) {
+            disk::write(mirror, galois::as_bytes_ref(chunk), self.durability);
+        }
+        if !self.device.is_present() {
+            return self.device.mark_dirty(data_slice);
+        }
+        disk::write(file_path, galois::as_bytes_ref(chunk), self.durability);
     }
 
     pub fn start(
@@ -224,267 +1136,713 @@ where
         recover_rec: Receiver<RecoverMsg<X>>,
     ) -> Result<()> {
         while let Ok(msg) = rec.recv() {
+            if msg.bytes() > 0 {
+                self.transport.credit(self.dev_idx, msg.bytes())?;
+            }
             match msg {
-                Msg::NewData { data_slice, data } => {
+                Msg::NewData {
+                    data_slice,
+                    data,
+                    version,
+                } => {
+                    if !self.is_new(data_slice, self.data_idx(data_slice), version) {
+                        continue;
+                    }
                     // inform checksum devices
                     for check_idx in 0..C {
-                        let check_dev = Checkpoint::<D, C, X>::dev_idx(data_slice, check_idx + D);
-                        self.coms[check_dev].send(Msg::NewDataChecksum {
-                            data_slice,
-                            data: data.clone(),
-                            dev_idx: self.dev_idx,
-                        })?;
+                        let check_dev = self.dev_idx(data_slice, check_idx + D);
+                        self.transport.send(
+                            check_dev,
+                            Msg::NewDataChecksum {
+                                data_slice,
+                                data: data.clone(),
+                                dev_idx: self.dev_idx,
+                                version,
+                            },
+                        )?;
                     }
                     // write data
                     self.write_data(data_slice, &data);
                 }
-                Msg::NewDataAt { data_slice, data } => {
+                Msg::NewDataAt {
+                    data_slice,
+                    data,
+                    version,
+                    epoch,
+                } => {
+                    if !self.is_new(data_slice, self.data_idx(data_slice), version) {
+                        continue;
+                    }
                     // inform checksum devices
                     for check_idx in 0..C {
-                        let check_dev = Checkpoint::<D, C, X>::dev_idx(data_slice, check_idx + D);
-                        self.coms[check_dev].send(Msg::NewDataChecksumAt {
-                            data_slice,
-                            data: data.clone(),
-                            dev_idx: self.dev_idx,
-                        })?;
+                        let check_dev = self.dev_idx(data_slice, check_idx + D);
+                        self.transport.send(
+                            check_dev,
+                            Msg::NewDataChecksumAt {
+                                data_slice,
+                                data: data.clone(),
+                                dev_idx: self.dev_idx,
+                                version,
+                                epoch,
+                            },
+                        )?;
                     }
                     // write data
                     self.write_data(data_slice, &data);
                 }
-                Msg::UpdateData { data_slice, data } => {
-                    let old_data = self.read_data(data_slice);
-                    let diff_data = galois::from_fn(|i| data[i] - old_data[i]);
-                    // inform checksum devices
-                    for check_idx in 0..C {
-                        let check_dev = Checkpoint::<D, C, X>::dev_idx(data_slice, check_idx + D);
-                        self.coms[check_dev].send(Msg::UpdateDataChecksum {
-                            data_slice,
-                            diff: diff_data.clone(),
-                            dev_idx: self.dev_idx,
-                        })?;
+                Msg::UpdateData {
+                    data_slice,
+                    data,
+                    version,
+                    epoch,
+                } => {
+                    if !self.is_new(data_slice, self.data_idx(data_slice), version) {
+                        continue;
+                    }
+                    let old_data = match self.try_read_chunk(data_slice) {
+                        Some(old_data) => old_data,
+                        // storage is missing or the chunk can not be read, get the old data
+                        // from the other nodes
+                        None => self.reconstruct(&recover_rec, data_slice)?,
+                    };
+                    let diff_data = galois::from_fn(|i| data[i] - old_data[i]);
+                    // inform checksum devices
+                    for check_idx in 0..C {
+                        let check_dev = self.dev_idx(data_slice, check_idx + D);
+                        self.transport.send(
+                            check_dev,
+                            Msg::UpdateDataChecksum {
+                                data_slice,
+                                diff: diff_data.clone(),
+                                dev_idx: self.dev_idx,
+                                version,
+                                epoch,
+                            },
+                        )?;
                     }
                     // write data
                     self.write_data(data_slice, &data);
                 }
+                Msg::NewDataAggregated {
+                    data_slice,
+                    data,
+                    version,
+                    fan_in,
+                } => {
+                    let data_idx = self.data_idx(data_slice);
+                    if !self.is_new(data_slice, data_idx, version) {
+                        continue;
+                    }
+                    let chunks: Vec<_> = (0..C)
+                        .map(|check_idx| {
+                            let coefficient = self.vandermonde[check_idx][data_idx];
+                            (check_idx, galois::from_fn(|i| coefficient * data[i]))
+                        })
+                        .collect();
+                    self.aggregate(data_slice, version, fan_in, chunks)?;
+                    // write data
+                    self.write_data(data_slice, &data);
+                }
+                Msg::PartialChecksum {
+                    data_slice,
+                    check_idx,
+                    partial,
+                    version,
+                    fan_in,
+                } => {
+                    self.aggregate(data_slice, version, fan_in, [(check_idx, partial)])?;
+                }
                 Msg::NewDataChecksum {
                     data_slice,
                     data,
                     dev_idx,
+                    version,
                 } => {
-                    let data_idx = Self::data_check_idx(dev_idx, data_slice);
-
-                    let current_status = self.current_checksum.get(&data_slice);
-
-                    // check if we already received data chunks
-                    let new_status = if let Some(status) = current_status {
-                        // update checksum
-                        let new_checksum = galois::from_fn(|i| {
-                            status.current_checksum[i]
-                                + self.vandermonde[self.check_idx(data_slice)][data_idx] * data[i]
-                        });
-                        CurrentChecksumStatus {
-                            count: status.count + 1,
-                            current_checksum: new_checksum,
-                            missed_recover_dev_idx: status.missed_recover_dev_idx.clone(),
-                        }
-                    } else {
-                        // first data chunk
-                        let new_checksum = galois::from_fn(|i| {
-                            self.vandermonde[self.check_idx(data_slice)][data_idx] * data[i]
-                        });
-                        CurrentChecksumStatus {
-                            count: 1,
-                            current_checksum: new_checksum,
-                            missed_recover_dev_idx: vec![],
+                    let data_idx = self.data_check_idx(dev_idx, data_slice);
+                    let versions = self.versions(data_slice);
+                    if version > versions.epoch {
+                        // a new write of the whole slice, an unfinished older one is dropped
+                        versions.epoch = version;
+                        let early = std::mem::take(&mut versions.early);
+                        let missed_recover_dev_idx = self
+                            .current_checksum
+                            .remove(&data_slice)
+                            .map_or(vec![], |status| status.missed_recover_dev_idx);
+                        self.current_checksum.insert(
+                            data_slice,
+                            CurrentChecksumStatus {
+                                count: 0,
+                                current_checksum: galois::zeros(),
+                                missed_recover_dev_idx,
+                            },
+                        );
+                        for delta in early {
+                            self.apply_delta(data_slice, delta);
                         }
-                    };
-                    if new_status.count == D {
+                    }
+                    if !self.is_new(data_slice, data_idx, version) {
+                        continue;
+                    }
+
+                    // update checksum
+                    let coefficient = self.vandermonde[self.check_idx(data_slice)][data_idx];
+                    let status = self.current_checksum.get_mut(&data_slice).unwrap();
+                    let new_checksum =
+                        galois::from_fn(|i| status.current_checksum[i] + coefficient * data[i]);
+                    status.current_checksum = new_checksum;
+                    status.count += 1;
+                    if status.count == D {
                         // all data chunks received
-                        self.current_checksum.remove(&data_slice);
+                        let new_status = self.current_checksum.remove(&data_slice).unwrap();
                         self.write_checksum(data_slice, &new_status.current_checksum);
-                        for dev_idx in new_status.missed_recover_dev_idx {
-                            self.recover_coms[dev_idx].send(RecoverMsg::RequestedData {
-                                data_slice,
-                                data: new_status.current_checksum.clone(),
-                                dev_idx: self.dev_idx,
-                            })?;
+                        self.remove_partial(data_slice);
+                        for (dev_idx, round) in new_status.missed_recover_dev_idx {
+                            self.transport.send_recover(
+                                dev_idx,
+                                RecoverMsg::RequestedData {
+                                    data_slice,
+                                    data: new_status.current_checksum.clone(),
+                                    dev_idx: self.dev_idx,
+                                    round,
+                                },
+                            )?;
                         }
                     } else {
-                        self.current_checksum.insert(data_slice, new_status);
+                        self.partial_changed(data_slice);
                     }
                 }
-                Msg::UpdateDataChecksum {
+                // a new chunk changes the checksum like an update of a zero chunk
+                Msg::NewDataChecksumAt {
+                    data_slice,
+                    data: diff,
+                    dev_idx,
+                    version,
+                    epoch,
+                }
+                | Msg::UpdateDataChecksum {
                     data_slice,
                     diff,
                     dev_idx,
+                    version,
+                    epoch,
                 } => {
-                    let data_idx = Self::data_check_idx(dev_idx, data_slice);
-                    let self_check_idx = self.check_idx(data_slice);
-                    let current_status = self.current_checksum.get_mut(&data_slice);
-                    if let Some(current_status) = current_status {
-                        // still waiting for data chunks
-                        let new_checksum = galois::from_fn(|i| {
-                            current_status.current_checksum[i]
-                                + self.vandermonde[self_check_idx][data_idx] * diff[i]
-                        });
-                        current_status.current_checksum = new_checksum;
-                    } else {
-                        // not waiting for additional data chunks
-                        let current_checksum = self.read_checksum(data_slice);
-                        let new_checksum = galois::from_fn(|i| {
-                            current_checksum[i]
-                                + self.vandermonde[self.check_idx(data_slice)][data_idx] * diff[i]
-                        });
-                        self.write_checksum(data_slice, &new_checksum);
-                    }
+                    let data_idx = self.data_check_idx(dev_idx, data_slice);
+                    let delta = Delta {
+                        data_idx,
+                        diff,
+                        version,
+                        epoch,
+                    };
+                    self.apply_delta(data_slice, delta);
                 }
-                Msg::NewDataChecksumAt {
+                Msg::NewChecksum {
                     data_slice,
-                    data,
-                    dev_idx,
+                    checksum,
+                    version,
                 } => {
-                    assert!(self.current_checksum.get(&data_slice).is_none());
-                    let data_idx = Self::data_check_idx(dev_idx, data_slice);
-                    let self_check_idx = self.check_idx(data_slice);
-                    let checksum_path = self.checksum_file(data_slice);
-                    // update checksum
-                    let new_checksum: Box<[Galois; X]> = match fs::read(&checksum_path) {
-                        Ok(file) => {
-                            let old_checksum: Box<[Galois; X]> =
-                                galois::from_bytes(file.into_boxed_slice().try_into().unwrap());
-                            galois::from_fn(|i| {
-                                old_checksum[i]
-                                    + self.vandermonde[self_check_idx][data_idx] * data[i]
-                            })
-                        }
-                        Err(err) => {
-                            let io::ErrorKind::NotFound = err.kind() else {
-                                panic!("{:?}", err)
-                            };
-                            // file not found we assume the current checksum is zero
-                            galois::from_fn(|i| {
-                                self.vandermonde[self_check_idx][data_idx] * data[i]
-                            })
+                    let versions = self.versions(data_slice);
+                    if version <= versions.epoch {
+                        continue;
+                    }
+                    // the write covers every data chunk, an unfinished older one is dropped
+                    versions.epoch = version;
+                    versions.chunks.fill(version);
+                    let early = std::mem::take(&mut versions.early);
+                    let missed_recover_dev_idx = self
+                        .current_checksum
+                        .remove(&data_slice)
+                        .map_or(vec![], |status| status.missed_recover_dev_idx);
+                    self.write_checksum(data_slice, &checksum);
+                    self.remove_partial(data_slice);
+                    for (dev_idx, round) in missed_recover_dev_idx {
+                        self.transport.send_recover(
+                            dev_idx,
+                            RecoverMsg::RequestedData {
+                                data_slice,
+                                data: checksum.clone(),
+                                dev_idx: self.dev_idx,
+                                round,
+                            },
+                        )?;
+                    }
+                    for delta in early {
+                        self.apply_delta(data_slice, delta);
+                    }
+                }
+                Msg::DestroyStorage { data_slices } => {
+                    let _ = std::fs::remove_dir_all(&self.device.path);
+                    create_dir(&self.device.path).unwrap();
+                    self.device.start_rebuild(data_slices);
+                }
+                Msg::Fail => {
+                    self.health.set(self.dev_idx, NodeState::Failed);
+                    let _ = std::fs::remove_dir_all(&self.device.path);
+                    return Ok(());
+                }
+                Msg::Rebuild { data_slices } => {
+                    self.device.start_rebuild(data_slices);
+                }
+                Msg::Recover {
+                    data_slices,
+                    request,
+                    repair,
+                } => {
+                    self.recover(&recover_rec, data_slices.clone(), repair)?;
+                    self.device.rebuilt_until(data_slices.end);
+                    if !self.device.is_rebuilding() {
+                        self.health.set(self.dev_idx, NodeState::Healthy);
+                    }
+                    self.transport.reply(Reply::Done { request })?;
+                }
+                Msg::Replace { path } => {
+                    self.device.start_replace(&path);
+                }
+                Msg::Copy {
+                    data_slices,
+                    request,
+                } => {
+                    // slices copied before a restart are skipped
+                    for data_slice in data_slices.start.max(self.device.copied())..data_slices.end {
+                        self.copy_chunk(&recover_rec, data_slice)?;
+                    }
+                    self.device.copied_until(data_slices.end);
+                    self.transport.reply(Reply::Done { request })?;
+                }
+                Msg::Replaced { request } => {
+                    self.device.replaced();
+                    self.transport.reply(Reply::Done { request })?;
+                }
+                Msg::Reattach { request } => {
+                    let result = match self.device.reattach() {
+                        Ok(dirty) => {
+                            self.recover(&recover_rec, dirty.iter().copied(), Repair::Direct)?;
+                            self.device.resynced();
+                            self.health.set(self.dev_idx, NodeState::Healthy);
+                            Ok(dirty.len())
                         }
+                        Err(err) => Err(err),
                     };
-                    fs::write(&checksum_path, galois::as_bytes_ref(&new_checksum)).unwrap();
-                }
-                Msg::DestroyStorage { max_data_slice } => {
-                    let _ = std::fs::remove_dir_all(&self.path);
-                    create_dir(&self.path).unwrap();
-                    self.recover(&recover_rec, max_data_slice)?;
+                    self.transport.reply(Reply::Reattach { request, result })?;
                 }
                 Msg::NeedRecover {
                     data_slice,
                     dev_idx,
+                    round,
                 } => {
-                    if Self::data_check_idx(self.dev_idx, data_slice) < D {
-                        self.recover_coms[dev_idx]
-                            .send(RecoverMsg::RequestedData {
-                                data_slice,
-                                data: self.read_data(data_slice),
-                                dev_idx: self.dev_idx,
-                            })
-                            .unwrap();
-                    } else if let Some(checksum_status) = self.current_checksum.get_mut(&data_slice)
-                    {
+                    if !self.health.is_healthy(self.dev_idx) || !self.device.has_chunk(data_slice) {
+                        // nothing to offer, the node asks the others
+                        continue;
+                    }
+                    if let Some(checksum_status) = self.current_checksum.get_mut(&data_slice) {
                         // if we still expect data chunks wait until we receive it
-                        checksum_status.missed_recover_dev_idx.push(dev_idx);
-                    } else {
-                        self.recover_coms[dev_idx]
-                            .send(RecoverMsg::RequestedData {
+                        checksum_status
+                            .missed_recover_dev_idx
+                            .push((dev_idx, round));
+                    } else if let Some(data) = self.try_read_chunk(data_slice) {
+                        self.transport.send_recover(
+                            dev_idx,
+                            RecoverMsg::RequestedData {
                                 data_slice,
-                                data: self.read_checksum(data_slice),
+                                data,
                                 dev_idx: self.dev_idx,
-                            })
-                            .unwrap();
+                                round,
+                            },
+                        )?;
+                    }
+                }
+                Msg::Repair {
+                    data_slice,
+                    dev_idx,
+                    round,
+                    helpers,
+                    coefficients,
+                } => {
+                    let piece = piece_len(X);
+                    for offset in (0..X).step_by(piece) {
+                        let range = offset..X.min(offset + piece);
+                        let Some(sum) =
+                            self.add_to_repair(data_slice, range, coefficients[0], None)
+                        else {
+                            let failed = RecoverMsg::RepairFailed { data_slice, round };
+                            self.transport.send_recover(dev_idx, failed)?;
+                            break;
+                        };
+                        let (helpers, coefficients) = (&helpers, &coefficients);
+                        self.pass_on(
+                            data_slice,
+                            dev_idx,
+                            round,
+                            helpers,
+                            coefficients,
+                            offset,
+                            sum,
+                        )?;
+                    }
+                }
+                Msg::RepairPiece {
+                    data_slice,
+                    dev_idx,
+                    round,
+                    helpers,
+                    coefficients,
+                    offset,
+                    sum,
+                } => {
+                    let range = offset..offset + sum.len();
+                    match self.add_to_repair(data_slice, range, coefficients[0], Some(&sum)) {
+                        Some(sum) => {
+                            let (helpers, coefficients) = (&helpers, &coefficients);
+                            self.pass_on(
+                                data_slice,
+                                dev_idx,
+                                round,
+                                helpers,
+                                coefficients,
+                                offset,
+                                sum,
+                            )?
+                        }
+                        None => {
+                            let failed = RecoverMsg::RepairFailed { data_slice, round };
+                            self.transport.send_recover(dev_idx, failed)?
+                        }
                     }
                 }
                 Msg::HeadNodeDataRequest {
                     data_slice,
-                    oneshot_send: oneshot_rec,
+                    request,
+                }
+                | Msg::HeadNodeChunkRequest {
+                    data_slice,
+                    request,
                 } => {
-                    let data = self.read_data(data_slice);
-                    oneshot_rec.send(CheckpointMsg { data_slice, data }).unwrap();
+                    let data = self.try_read_chunk(data_slice);
+                    self.transport.reply(Reply::Chunk { request, data })?;
+                }
+                Msg::Ping { request } => {
+                    self.transport.sync();
+                    self.transport.reply(Reply::Done { request })?;
+                }
+                Msg::Status { request } => {
+                    let status = self.status();
+                    self.transport.reply(Reply::Status { request, status })?;
                 }
-                Msg::Ping { oneshot_send } => {
-                    oneshot_send.send(()).unwrap();
+                Msg::State { dev_idx, state } => {
+                    self.health.set(dev_idx, state);
+                }
+                Msg::Heartbeat { request } => {
+                    self.transport.reply(Reply::Done { request })?;
                 }
                 Msg::Shutdown => {
+                    self.store_partials();
                     return Ok(());
                 }
             }
         }
+        self.store_partials();
         Ok(())
     }
 
-    pub fn recover(
+    // the other nodes that can give their chunk
+    fn sources(&self) -> Vec<usize> {
+        let sources: Vec<_> = (0..C + D)
+            .filter(|i| *i != self.dev_idx && self.health.is_healthy(*i))
+            .collect();
+        if sources.len() < D {
+            panic!("Too man devices lost")
+        }
+        sources
+    }
+
+    // ask the healthy nodes for their chunk of the slice, but the ones whose index in the
+    // slice is in `answered`
+    fn request_chunks(&self, data_slice: usize, round: u64, answered: &[usize]) -> Result<()> {
+        for i in self.sources() {
+            if answered.contains(&self.data_check_idx(i, data_slice)) {
+                continue;
+            }
+            self.transport.send(
+                i,
+                Msg::NeedRecover {
+                    dev_idx: self.dev_idx,
+                    data_slice,
+                    round,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    // ask D healthy nodes to sum up the chunk of this node, see `Repair::Pipelined`. Data
+    // nodes are preferred, their coefficients are simpler
+    fn request_repair(&self, data_slice: usize, round: u64) -> Result<()> {
+        let mut helpers = self.sources();
+        helpers.sort_by_key(|helper| self.data_check_idx(*helper, data_slice));
+        helpers.truncate(D);
+        let coefficients = self.repair_coefficients(data_slice, &helpers);
+        self.transport.send(
+            helpers[0],
+            Msg::Repair {
+                data_slice,
+                dev_idx: self.dev_idx,
+                round,
+                helpers,
+                coefficients,
+            },
+        )
+    }
+
+    // the chunk of this node is the sum of the chunks of the helpers times these
+    // coefficients. The helpers are sorted by their index in the slice
+    fn repair_coefficients(&self, data_slice: usize, helpers: &[usize]) -> Vec<Galois> {
+        let idxs: Vec<_> = helpers
+            .iter()
+            .map(|helper| self.data_check_idx(*helper, data_slice))
+            .collect();
+        let data_idxs = idxs.iter().copied().filter(|idx| *idx < D).collect();
+        let check_idxs = idxs
+            .iter()
+            .filter(|idx| **idx >= D)
+            .map(|idx| idx - D)
+            .collect();
+        let mut rec_matrix = self.vandermonde.recovery_matrix(data_idxs, check_idxs);
+        // solving for the unit vectors gives the inverse, a row for every data chunk
+        let mut inverse: [Box<[Galois; D]>; D] = core::array::from_fn(|m| {
+            galois::from_fn(|n| {
+                if m == n {
+                    Galois::one()
+                } else {
+                    Galois::zero()
+                }
+            })
+        });
+        rec_matrix.gaussian_elimination(&mut inverse);
+        let data_check_idx = self.data_check_idx(self.dev_idx, data_slice);
+        if data_check_idx < D {
+            inverse[data_check_idx].to_vec()
+        } else {
+            self.vandermonde
+                .mul_vec_at(&inverse, data_check_idx - D)
+                .to_vec()
+        }
+    }
+
+    // compute the chunk of this node from D chunks of other nodes, given by their index
+    fn decode(
+        &self,
+        data_slice: usize,
+        chunks: Vec<(usize, Box<[Galois; X]>)>,
+    ) -> Box<[Galois; X]> {
+        let mut r_data = vec![];
+        let mut r_check = vec![];
+        let mut r_data_idx = vec![];
+        let mut r_check_idx = vec![];
+        for (data_check_idx, chunk) in chunks {
+            if data_check_idx < D {
+                r_data.push(chunk);
+                r_data_idx.push(data_check_idx);
+            } else {
+                r_check.push(chunk);
+                r_check_idx.push(data_check_idx - D)
+            }
+        }
+
+        // make matrix
+        r_data.append(&mut r_check);
+        let mut rec_matrix = self.vandermonde.recovery_matrix(r_data_idx, r_check_idx);
+
+        // compute data
+        let mut rec_data: [Box<[Galois; X]>; D] = r_data.try_into().unwrap();
+        rec_matrix.gaussian_elimination(&mut rec_data);
+        let data_check_idx = self.data_check_idx(self.dev_idx, data_slice);
+        if data_check_idx < D {
+            galois::from_slice(&rec_data[data_check_idx])
+        } else {
+            // compute checksum
+            self.vandermonde.mul_vec_at(&rec_data, data_check_idx - D)
+        }
+    }
+
+    // compute the chunks of this node for the slices from the other nodes. Up to
+    // `RECOVER_WINDOW` slices are requested at once and replies are matched by slice,
+    // so a slice is decoded while the chunks of the next ones are on their way. Replies
+    // to earlier rounds are dropped. When no reply arrives for `RECOVER_TIMEOUT` the nodes
+    // that did not answer are asked again, after `RECOVER_ATTEMPTS` the recovery fails
+    fn reconstruct_all(
         &self,
         recover_rec: &Receiver<RecoverMsg<X>>,
-        max_data_slice: usize,
+        data_slices: impl IntoIterator<Item = usize>,
+        repair: Repair,
+        mut reconstructed: impl FnMut(usize, Box<[Galois; X]>),
     ) -> Result<()> {
-        for current_data_slice in 0..max_data_slice + 1 {
-            // clear old recover responses
-            while !recover_rec.is_empty() {
-                recover_rec.recv().unwrap();
-            }
-            // ask for data or checksum chunks
-            for i in 0..C + D {
-                if i != self.dev_idx {
-                    self.coms[i].send(Msg::NeedRecover {
-                        dev_idx: self.dev_idx,
-                        data_slice: current_data_slice,
-                    })?;
-                }
-            }
-
-            // collect data/checksum chunks
-            let mut r_data = vec![];
-            let mut r_check = vec![];
-            let mut r_data_idx = vec![];
-            let mut r_check_idx = vec![];
-            while let Ok(msg) = recover_rec.recv() {
-                match msg {
-                    RecoverMsg::RequestedData {
-                        data_slice,
-                        data,
-                        dev_idx,
-                    } => {
-                        if data_slice != current_data_slice {
-                            continue;
-                        }
-                        let data_check_idx = Self::data_check_idx(dev_idx, data_slice);
-                        if data_check_idx < D {
-                            r_data.push(data);
-                            r_data_idx.push(data_check_idx);
-                        } else {
-                            r_check.push(data);
-                            r_check_idx.push(data_check_idx - D)
+        let round = self.recover_round.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut data_slices = data_slices.into_iter();
+        // the slices and how often they were asked for
+        let mut pending: HashMap<usize, (Pending<X>, usize)> = HashMap::new();
+        let mut request = |pending: &mut HashMap<_, _>| -> Result<()> {
+            while pending.len() < RECOVER_WINDOW {
+                let Some(data_slice) = data_slices.next() else {
+                    break;
+                };
+                let slice = match repair {
+                    Repair::Direct => {
+                        self.request_chunks(data_slice, round, &[])?;
+                        Pending::Chunks(vec![])
+                    }
+                    Repair::Pipelined => {
+                        self.request_repair(data_slice, round)?;
+                        Pending::Pieces(galois::zeros(), HashSet::new())
+                    }
+                };
+                pending.insert(data_slice, (slice, 1));
+            }
+            Ok(())
+        };
+
+        request(&mut pending)?;
+        while !pending.is_empty() {
+            let msg = match recover_rec.recv_timeout(RECOVER_TIMEOUT) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => {
+                    for (data_slice, (slice, attempts)) in pending.iter_mut() {
+                        if *attempts == RECOVER_ATTEMPTS {
+                            return Err(Error::Lost {
+                                data_slice: *data_slice,
+                            });
                         }
-                        if r_data_idx.len() + r_check_idx.len() == D {
-                            break;
+                        *attempts += 1;
+                        // a helper of the repair may be gone, ask the nodes directly
+                        if let Pending::Pieces(..) = slice {
+                            *slice = Pending::Chunks(vec![]);
                         }
+                        let Pending::Chunks(chunks) = slice else {
+                            unreachable!()
+                        };
+                        let answered: Vec<_> = chunks.iter().map(|(idx, _)| *idx).collect();
+                        self.request_chunks(*data_slice, round, &answered)?;
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::Shutdown),
+            };
+            match msg {
+                RecoverMsg::RequestedData { round: r, .. }
+                | RecoverMsg::RepairedPiece { round: r, .. }
+                | RecoverMsg::RepairFailed { round: r, .. }
+                    if r != round => {}
+                RecoverMsg::RequestedData {
+                    data_slice,
+                    data,
+                    dev_idx,
+                    ..
+                } => {
+                    // more than D nodes answered
+                    let Some((Pending::Chunks(chunks), _)) = pending.get_mut(&data_slice) else {
+                        continue;
+                    };
+                    // a node that was asked again answers twice
+                    let data_check_idx = self.data_check_idx(dev_idx, data_slice);
+                    if chunks.iter().any(|(idx, _)| *idx == data_check_idx) {
+                        continue;
+                    }
+                    chunks.push((data_check_idx, data));
+                    if chunks.len() == D {
+                        let Some((Pending::Chunks(chunks), _)) = pending.remove(&data_slice) else {
+                            unreachable!()
+                        };
+                        request(&mut pending)?;
+                        reconstructed(data_slice, self.decode(data_slice, chunks));
+                    }
+                }
+                RecoverMsg::RepairedPiece {
+                    data_slice,
+                    offset,
+                    data,
+                    ..
+                } => {
+                    // pieces of a repair that failed
+                    let Some((Pending::Pieces(chunk, received), _)) = pending.get_mut(&data_slice)
+                    else {
+                        continue;
+                    };
+                    chunk[offset..offset + data.len()].copy_from_slice(&data);
+                    received.insert(offset);
+                    if received.len() == X.div_ceil(piece_len(X)) {
+                        let Some((Pending::Pieces(chunk, _), _)) = pending.remove(&data_slice)
+                        else {
+                            unreachable!()
+                        };
+                        request(&mut pending)?;
+                        reconstructed(data_slice, chunk);
+                    }
+                }
+                RecoverMsg::RepairFailed { data_slice, .. } => {
+                    // decode the slice from the chunks of the other nodes instead
+                    if let Some((slice @ Pending::Pieces(..), _)) = pending.get_mut(&data_slice) {
+                        *slice = Pending::Chunks(vec![]);
+                        self.request_chunks(data_slice, round, &[])?;
                     }
                 }
             }
+        }
+        Ok(())
+    }
 
-            // make matrix
-            r_data.append(&mut r_check);
-            let mut rec_matrix = self.vandermonde.recovery_matrix(r_data_idx, r_check_idx);
+    /// Rebuild the chunks of this node for the given slices from the other nodes.
+    pub fn recover(
+        &self,
+        recover_rec: &Receiver<RecoverMsg<X>>,
+        data_slices: impl IntoIterator<Item = usize>,
+        repair: Repair,
+    ) -> Result<()> {
+        self.reconstruct_all(recover_rec, data_slices, repair, |data_slice, chunk| {
+            if self.data_check_idx(self.dev_idx, data_slice) < D {
+                self.write_data(data_slice, &chunk)
+            } else {
+                self.write_checksum(data_slice, &chunk);
+            }
+        })
+    }
 
-            // compute data
-            let mut rec_data: [Box<[Galois; X]>; D] = r_data.try_into().unwrap();
-            rec_matrix.gaussian_elimination(&mut rec_data);
+    // compute the chunk of this node from the other nodes
+    fn reconstruct(
+        &self,
+        recover_rec: &Receiver<RecoverMsg<X>>,
+        data_slice: usize,
+    ) -> Result<Box<[Galois; X]>> {
+        let mut result = None;
+        self.reconstruct_all(recover_rec, [data_slice], Repair::Direct, |_, chunk| {
+            result = Some(chunk)
+        })?;
+        Ok(result.unwrap())
+    }
 
-            let data_check_idx = Self::data_check_idx(self.dev_idx, current_data_slice);
-            if data_check_idx < D {
-                self.write_data(current_data_slice, &rec_data[data_check_idx])
-            } else {
-                // compute checksum
-                let checksum = self.vandermonde.mul_vec_at(&rec_data, data_check_idx - D);
-                self.write_checksum(current_data_slice, &checksum);
+    // copy the chunk to the replacement folder, reconstruct it if it can not be read
+    fn copy_chunk(&self, recover_rec: &Receiver<RecoverMsg<X>>, data_slice: usize) -> Result<()> {
+        let file_path = if self.data_check_idx(self.dev_idx, data_slice) < D {
+            self.data_file(data_slice)
+        } else {
+            self.checksum_file(data_slice)
+        };
+        let chunk = if self.device.has_chunk(data_slice) {
+            match disk::read_chunk(&file_path) {
+                Ok(Some(chunk)) => Some(galois::from_bytes(chunk)),
+                // nothing to copy
+                Ok(None) => return Ok(()),
+                Err(_) => None,
             }
-        }
+        } else {
+            None
+        };
+        let chunk = match chunk {
+            Some(chunk) => chunk,
+            None => self.reconstruct(recover_rec, data_slice)?,
+        };
+        disk::write(
+            self.device.copy_path(&file_path),
+            galois::as_bytes_ref(&chunk),
+            self.durability,
+        );
         Ok(())
     }
 }
@@ -497,8 +1855,238 @@ where
     [(); D + D]:,
 {
     max_data_slices: usize,
-    coms: [Sender<Msg<X>>; D + C],
-    handles: [JoinHandle<()>; D + C],
+    paths: [PathBuf; D + C],
+    durability: Durability,
+    layout: Arc<dyn Layout>,
+    transport: Arc<dyn Transport<X>>,
+    requests: Arc<Requests<X>>,
+    // bytes of chunks queued at the nodes, writes wait for it
+    budget: Arc<Budget>,
+    // kept so a spare can take over the channels of a slot, empty if the nodes run in
+    // their own processes
+    receivers: Vec<Receiver<Msg<X>>>,
+    recover_receivers: Vec<Receiver<RecoverMsg<X>>>,
+    // accepts the replies of nodes in other processes
+    listener: Option<Listener>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    vandermonde: Matrix<C, D>,
+    // slices with writes that are maybe not yet done, cleared by `flush`
+    bitmap: Bitmap,
+    // held while writes are sent and while a rebuild window runs
+    lock: Arc<Mutex<()>>,
+    // last version handed out
+    clock: AtomicU64,
+    // version of the last write of the whole slice, for the slices with writes in flight
+    epochs: Mutex<HashMap<usize, Version>>,
+    health: Arc<Health>,
+    // nodes report I/O errors here
+    failures: Receiver<usize>,
+    // nodes that were down and answer heartbeats again
+    returned: Receiver<usize>,
+    monitor: Monitor,
+    spares: Mutex<Vec<Spare<X>>>,
+    rebuild_bandwidth: Option<u64>,
+    rebuilds: Mutex<Vec<Running>>,
+    read_timeout: Duration,
+    hedge: Option<f64>,
+    latencies: Latencies,
+    aggregation: Aggregation,
+    repair: Repair,
+}
+
+// latencies of the last reads, for the hedge delay
+#[derive(Default)]
+struct Latencies(Mutex<VecDeque<Duration>>);
+
+const LATENCIES: usize = 1024;
+
+impl Latencies {
+    fn record(&self, latency: Duration) {
+        let mut latencies = self.0.lock().unwrap();
+        if latencies.len() == LATENCIES {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    // None until enough reads are known
+    fn percentile(&self, p: f64) -> Option<Duration> {
+        let mut latencies: Vec<_> = self.0.lock().unwrap().iter().copied().collect();
+        if latencies.len() < 32 {
+            return None;
+        }
+        latencies.sort();
+        Some(latencies[((latencies.len() - 1) as f64 * p) as usize])
+    }
+}
+
+// slices of a rebuild or copy between two checkpoints
+const WINDOW: usize = 64;
+
+// number of slices a recovering node has requested from the other nodes at once
+const RECOVER_WINDOW: usize = 16;
+
+// time a recovering node waits for the other nodes before it asks again
+const RECOVER_TIMEOUT: Duration = Duration::from_secs(1);
+
+// times a slice is asked for before the recovery fails
+const RECOVER_ATTEMPTS: usize = 3;
+
+// pieces a chunk is split into when it is repaired along the helpers
+const REPAIR_PIECES: usize = 8;
+
+// bytes of a piece of the chunk, the last one can be shorter
+fn piece_len(chunk: usize) -> usize {
+    chunk.div_ceil(REPAIR_PIECES)
+}
+
+// send a request to the node, the reply arrives on the returned receiver
+fn request<const X: usize>(
+    transport: &dyn Transport<X>,
+    requests: &Requests<X>,
+    dev_idx: usize,
+    msg: impl FnOnce(RequestId) -> Msg<X>,
+) -> Result<Receiver<Reply<X>>> {
+    let (request, reply) = requests.start();
+    if let Err(err) = transport.send(dev_idx, msg(request)) {
+        requests.cancel(request);
+        return Err(err);
+    }
+    Ok(reply)
+}
+
+// how often a wait for a reply looks if the node was marked down
+const WAIT: Duration = Duration::from_millis(100);
+
+// the reply of the node, None if it is marked down before it answers
+fn wait<const X: usize>(
+    reply: Receiver<Reply<X>>,
+    health: &Health,
+    dev_idx: usize,
+) -> Option<Reply<X>> {
+    loop {
+        match reply.recv_timeout(WAIT) {
+            Ok(reply) => return Some(reply),
+            Err(RecvTimeoutError::Timeout) => {
+                if health.state(dev_idx) == NodeState::Down {
+                    return None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+// nodes that are down are skipped. A round also finishes the messages the nodes sent in
+// the round before
+fn ping_all<const X: usize>(
+    transport: &dyn Transport<X>,
+    requests: &Requests<X>,
+    health: &Health,
+    rounds: usize,
+) {
+    for _ in 0..rounds {
+        // a node that can not be reached is not waited for like one that is down, the
+        // monitor marks it down
+        let replies: Vec<_> = (0..transport.nodes())
+            .filter(|dev_idx| health.state(*dev_idx) != NodeState::Down)
+            .filter_map(|dev_idx| {
+                let reply = request(transport, requests, dev_idx, |request| Msg::Ping {
+                    request,
+                });
+                Some((dev_idx, reply.ok()?))
+            })
+            .collect();
+        for (dev_idx, reply) in replies {
+            wait(reply, health, dev_idx);
+        }
+    }
+}
+
+// beat the nodes until `stop` is dropped. A node that misses `suspicion` heartbeats in a
+// row is marked down and its chunks no longer count against the budget. A node that is
+// down and answers again is sent to `returned`
+fn monitor<const X: usize>(
+    transport: &dyn Transport<X>,
+    requests: &Requests<X>,
+    health: &Health,
+    budget: &Budget,
+    heartbeat: Heartbeat,
+    returned: &Sender<usize>,
+    stop: &Receiver<()>,
+) {
+    let mut missed = vec![0; transport.nodes()];
+    while stop.recv_timeout(heartbeat.interval) == Err(RecvTimeoutError::Timeout) {
+        let beats: Vec<_> = (0..transport.nodes())
+            .map(|dev_idx| {
+                let (request, reply) = requests.start();
+                let sent = transport.heartbeat(dev_idx, request).is_ok();
+                (request, sent.then_some(reply))
+            })
+            .collect();
+        let deadline = Instant::now() + heartbeat.timeout;
+        for (dev_idx, (request, reply)) in beats.into_iter().enumerate() {
+            let answered = reply.is_some_and(|reply| reply.recv_deadline(deadline).is_ok());
+            // a late answer is dropped
+            requests.cancel(request);
+            let state = health.state(dev_idx);
+            if answered {
+                missed[dev_idx] = 0;
+                if state == NodeState::Down {
+                    let _ = returned.send(dev_idx);
+                }
+                continue;
+            }
+            missed[dev_idx] += 1;
+            let up = matches!(state, NodeState::Healthy | NodeState::Rebuilding);
+            if up && missed[dev_idx] >= heartbeat.suspicion {
+                health.down(dev_idx);
+                budget.forget(dev_idx);
+            }
+        }
+    }
+}
+
+// send a job to a node slice by slice. No write is in flight while a slice runs, the writes
+// waiting for `lock` go on between slices. A rebuild `job` is checkpointed, throttled and
+// can be cancelled between windows of `WINDOW` slices. False if the node is gone or down or
+// the job was cancelled
+#[allow(clippy::too_many_arguments)]
+fn run_windows<const D: usize, const X: usize>(
+    transport: &dyn Transport<X>,
+    requests: &Requests<X>,
+    health: &Health,
+    lock: &Mutex<()>,
+    rounds: usize,
+    dev_idx: usize,
+    data_slices: Range<usize>,
+    msg: impl Fn(Range<usize>, RequestId) -> Msg<X>,
+    job: Option<&Job>,
+) -> bool {
+    for start in data_slices.clone().step_by(WINDOW) {
+        if let Some(job) = job.filter(|job| job.is_cancelled()) {
+            // stores the checkpoint
+            job.rebuilt_until(start);
+            return false;
+        }
+        let window = start..data_slices.end.min(start + WINDOW);
+        for data_slice in window.clone() {
+            let _guard = lock.lock().unwrap();
+            ping_all(transport, requests, health, rounds);
+            let reply = request(transport, requests, dev_idx, |request| {
+                msg(data_slice..data_slice + 1, request)
+            });
+            if !reply.is_ok_and(|reply| wait(reply, health, dev_idx).is_some()) {
+                return false;
+            }
+        }
+        if let Some(job) = job {
+            job.rebuilt_until(window.end);
+            // D chunks read, one written per slice
+            job.throttle((window.len() * (D + 1) * X) as u64);
+        }
+    }
+    true
 }
 
 impl<const D: usize, const C: usize, const X: usize> Checkpoint<D, C, X>
@@ -508,8 +2096,730 @@ where
     [(); C + C]:,
     [(); D + D]:,
 {
-    fn dev_idx(data_slice: usize, data_idx: usize) -> usize {
-        (data_idx + data_slice) % (D + C)
+    fn dev_idx(&self, data_slice: usize, data_idx: usize) -> usize {
+        self.layout.device(D, C, data_slice, data_idx)
+    }
+
+    // the nodes of a slice talk to each other, so there is exactly one node per chunk of a
+    // slice. A layout over more devices like `Declustered` or `Weighted` only works with the
+    // `Controller`
+    fn check_layout(config: &Config) -> std::result::Result<(), layout::Error> {
+        if config.layout.devices(D, C) != D + C {
+            panic!("layout {} needs the `Controller`", config.layout.name())
+        }
+        // refuses failure domains that hold too many chunks of a slice
+        config.layout(D, C)?;
+        Ok(())
+    }
+
+    // the nodes are threads of this process, or in their own processes with `config.peers`
+    fn spawn(paths: [PathBuf; D + C], config: Config, bitmap: Bitmap, open: bool) -> Self {
+        let requests = Arc::new(Requests::default());
+        let budget = Arc::new(Budget::new(config.inflight_bytes, D + C));
+        let (failure_send, failures) = unbounded();
+        let (returned_send, returned) = unbounded();
+        let mut receivers = vec![];
+        let mut recover_receivers = vec![];
+        let (transport, listener): (Arc<dyn Transport<X>>, _) = match &config.peers {
+            None => {
+                let coms;
+                let recover_coms;
+                (coms, receivers) = (0..D + C).map(|_| unbounded()).unzip();
+                (recover_coms, recover_receivers) = (0..D + C).map(|_| unbounded()).unzip();
+                let channels = Channels::new(coms, recover_coms, requests.clone(), budget.clone());
+                (Arc::new(channels), None)
+            }
+            Some(peers) => {
+                if !config.spares.is_empty() {
+                    panic!("spares need the nodes in the process of the head node")
+                }
+                let replies = requests.clone();
+                let credits = budget.clone();
+                let listener = Listener::bind::<X>(peers.head, move |packet| match packet {
+                    Packet::Reply(reply) => replies.complete(reply),
+                    Packet::Credit { dev_idx, bytes } => credits.release(dev_idx, bytes),
+                    _ => {}
+                })
+                .unwrap();
+                (Arc::new(Tcp::new(peers)), Some(listener))
+            }
+        };
+
+        let mut health = Health::new(vec![NodeState::Healthy; D + C], failure_send);
+        if listener.is_some() {
+            health = health.broadcast(transport.clone());
+        }
+        let health = Arc::new(health);
+        let monitor = {
+            let (stop, stopped) = unbounded();
+            let transport = transport.clone();
+            let requests = requests.clone();
+            let health = health.clone();
+            let budget = budget.clone();
+            let heartbeat = config.heartbeat;
+            let handle = std::thread::Builder::new()
+                .name("monitor".to_string())
+                .spawn(move || {
+                    monitor(
+                        &*transport,
+                        &requests,
+                        &health,
+                        &budget,
+                        heartbeat,
+                        &returned_send,
+                        &stopped,
+                    )
+                })
+                .unwrap();
+            Monitor { stop, handle }
+        };
+
+        let checkpoint = Self {
+            max_data_slices: 0,
+            paths,
+            durability: config.durability,
+            layout: config.layout(D, C).unwrap(),
+            transport,
+            requests,
+            budget,
+            receivers,
+            recover_receivers,
+            listener,
+            handles: Mutex::new(vec![]),
+            vandermonde: Matrix::<C, D>::reed_solomon(),
+            bitmap,
+            lock: Arc::new(Mutex::new(())),
+            clock: AtomicU64::new(0),
+            epochs: Mutex::new(HashMap::new()),
+            health,
+            failures,
+            returned,
+            monitor,
+            spares: Mutex::new(vec![]),
+            rebuild_bandwidth: config.rebuild_bandwidth,
+            rebuilds: Mutex::new(vec![]),
+            read_timeout: config.read_timeout,
+            hedge: config.hedge,
+            latencies: Latencies::default(),
+            aggregation: config.aggregation,
+            repair: config.repair,
+        };
+        if checkpoint.listener.is_none() {
+            checkpoint.spawn_nodes(open);
+        }
+        for spare in config.spares {
+            checkpoint.add_spare(spare);
+        }
+        checkpoint
+    }
+
+    fn spawn_nodes(&self, open: bool) {
+        let mut handles = self.handles.lock().unwrap();
+        for i in 0..D + C {
+            let path = self.paths[i].clone();
+            let v = self.vandermonde.clone();
+            let durability = self.durability;
+            let layout = self.layout.clone();
+            let t = self.transport.clone();
+            let health = self.health.clone();
+            let r = self.receivers[i].clone();
+            let rec_r = self.recover_receivers[i].clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("thread{i}"))
+                .spawn(move || {
+                    let node = if open {
+                        Node::open(path, i, v, durability, layout, t, health)
+                    } else {
+                        Node::new(path, i, v, durability, layout, t, health)
+                    };
+                    let _ = node.start(r, rec_r);
+                })
+                .unwrap();
+            handles.push(handle);
+        }
+    }
+
+    /// Register a standby device. An idle node thread waits on it and takes over the slot
+    /// of the next device that fails.
+    pub fn add_spare(&self, path: PathBuf) {
+        let (promote, promotions) = unbounded::<Promotion<X>>();
+        let paths = self.paths.clone();
+        let v = self.vandermonde.clone();
+        let durability = self.durability;
+        let layout = self.layout.clone();
+        let t = self.transport.clone();
+        let health = self.health.clone();
+        let handle = std::thread::Builder::new()
+            .name("spare".to_string())
+            .spawn(move || {
+                // dropping the sender ends an unused spare
+                let Ok(promotion) = promotions.recv() else {
+                    return;
+                };
+                let slot_path = paths[promotion.dev_idx].clone();
+                let dev_idx = promotion.dev_idx;
+                let node = Node::open(slot_path, dev_idx, v, durability, layout, t, health);
+                node.device.promote(&path);
+                node.device.start_rebuild(promotion.data_slices);
+                let _ = node.start(promotion.rec, promotion.recover_rec);
+            })
+            .unwrap();
+        self.spares.lock().unwrap().push(Spare { promote, handle });
+    }
+
+    // stop the node of the slot and let a spare take over. False without spares, or if the
+    // node is down but its thread still runs, it is rebuilt once it answers again
+    fn promote_spare(&self, dev_idx: usize) -> bool {
+        let down = self.health.state(dev_idx) == NodeState::Down;
+        // nodes in other processes have no thread here
+        let running = self
+            .handles
+            .lock()
+            .unwrap()
+            .get(dev_idx)
+            .is_some_and(|handle| !handle.is_finished());
+        if down && running {
+            return false;
+        }
+        let Some(spare) = self.spares.lock().unwrap().pop() else {
+            return false;
+        };
+        // the new node must not miss a forwarded chunk
+        self.flush();
+        // the thread of a node that is down has ended already, the spare would get the `Fail`
+        if !down {
+            self.transport.send(dev_idx, Msg::Fail).unwrap();
+        }
+        let old = std::mem::replace(&mut self.handles.lock().unwrap()[dev_idx], spare.handle);
+        // it panicked if it is down
+        let _ = old.join();
+        // the old node is gone, the spare can take the channels
+        self.health.set(dev_idx, NodeState::Rebuilding);
+        let job = self.rebuild_job(dev_idx, false);
+        spare
+            .promote
+            .send(Promotion {
+                dev_idx,
+                data_slices: job.remaining(),
+                rec: self.receivers[dev_idx].clone(),
+                recover_rec: self.recover_receivers[dev_idx].clone(),
+            })
+            .unwrap();
+        self.spawn_rebuild(dev_idx, job);
+        true
+    }
+
+    // the rebuild of all slices of the node, from its checkpoint if `resume`
+    fn rebuild_job(&self, dev_idx: usize, resume: bool) -> Job {
+        let path = &self.paths[dev_idx];
+        let end = self.max_data_slices + 1;
+        if resume {
+            Job::resume(&[path], end, self.durability, self.rebuild_bandwidth)
+        } else {
+            Job::new(&[path], end, self.durability, self.rebuild_bandwidth)
+        }
+    }
+
+    // recover the node in windows while the head keeps serving requests. The node must
+    // already know the slices of the job are missing
+    fn spawn_rebuild(&self, dev_idx: usize, job: Job) {
+        let job = Arc::new(job);
+        let transport = self.transport.clone();
+        let requests = self.requests.clone();
+        let lock = self.lock.clone();
+        let health = self.health.clone();
+        let rounds = self.aggregation.hops(D);
+        let repair = self.repair;
+        let handle = {
+            let job = job.clone();
+            std::thread::Builder::new()
+                .name(format!("rebuild{dev_idx}"))
+                .spawn(move || {
+                    let done = run_windows::<D, X>(
+                        &*transport,
+                        &requests,
+                        &health,
+                        &lock,
+                        rounds,
+                        dev_idx,
+                        job.remaining(),
+                        |data_slices, request| Msg::Recover {
+                            data_slices,
+                            request,
+                            repair,
+                        },
+                        Some(&job),
+                    );
+                    if done {
+                        health.set(dev_idx, NodeState::Healthy);
+                    }
+                })
+                .unwrap()
+        };
+        self.rebuilds.lock().unwrap().push(Running {
+            dev_idxs: vec![dev_idx],
+            job,
+            handle,
+        });
+    }
+
+    // replace the nodes that reported I/O errors or went down and rebuild the nodes that
+    // are back
+    fn handle_failures(&self) {
+        while let Ok(dev_idx) = self.failures.try_recv() {
+            self.promote_spare(dev_idx);
+        }
+        while let Ok(dev_idx) = self.returned.try_recv() {
+            // reported again on every heartbeat until it is handled
+            if self.health.state(dev_idx) != NodeState::Down {
+                continue;
+            }
+            // a node that was down missed writes or is a replacement with empty storage
+            let job = self.destroy_storage(dev_idx);
+            // a new process does not know the states of the others
+            self.health.announce();
+            self.spawn_rebuild(dev_idx, job);
+        }
+    }
+
+    // the node rebuilds its storage in place, the rebuild job for it
+    fn destroy_storage(&self, dev_idx: usize) -> Job {
+        self.health.set(dev_idx, NodeState::Rebuilding);
+        let job = self.rebuild_job(dev_idx, false);
+        self.transport
+            .send(
+                dev_idx,
+                Msg::DestroyStorage {
+                    data_slices: job.remaining(),
+                },
+            )
+            .unwrap();
+        job
+    }
+
+    // wait until the writes of the data chunks and their copies for the checksum nodes fit
+    // into the budget
+    fn charge(&self, data_slice: usize, data_idxs: Range<usize>) {
+        let mut dev_idxs = vec![];
+        for data_idx in data_idxs {
+            let dev_idx = self.dev_idx(data_slice, data_idx);
+            // the head node sends the copies itself
+            if !self.is_down(dev_idx) {
+                dev_idxs.push(dev_idx);
+            }
+            dev_idxs.extend((0..C).map(|check_idx| self.dev_idx(data_slice, check_idx + D)));
+        }
+        self.budget.acquire(&dev_idxs, X as u64);
+    }
+
+    // like `charge` for a write of the whole slice along `Aggregation`: the data chunks, the
+    // partial checksums of the children at every parent and the checksums the root sends
+    fn charge_aggregated(&self, data_slice: usize, fan_in: usize) {
+        let mut dev_idxs = vec![];
+        for data_idx in 0..D {
+            dev_idxs.push(self.dev_idx(data_slice, data_idx));
+            match Aggregation::parent(data_idx, fan_in) {
+                Some(parent) => {
+                    dev_idxs.extend(std::iter::repeat_n(self.dev_idx(data_slice, parent), C))
+                }
+                None => {
+                    dev_idxs.extend((0..C).map(|check_idx| self.dev_idx(data_slice, check_idx + D)))
+                }
+            }
+        }
+        self.budget.acquire(&dev_idxs, X as u64);
+    }
+
+    // the fan-in of a write of the whole slice, None if the checksum nodes get the data
+    // chunks. The nodes of the slice pass on each other's partial checksums, so they all
+    // have to be healthy
+    fn fan_in(&self, data_slice: usize) -> Option<usize> {
+        let fan_in = self.aggregation.fan_in()?;
+        (0..D + C)
+            .all(|idx| self.health.is_healthy(self.dev_idx(data_slice, idx)))
+            .then_some(fan_in)
+    }
+
+    /// Like `add_data`, but fails with `Error::Busy` instead of waiting when the nodes have
+    /// more than `Config::inflight_bytes` queued.
+    pub fn try_add_data(&mut self, data: &[&[u8; X]; D], data_slice: usize) -> Result<()> {
+        if !self.budget.fits((D * (C + 1) * X) as u64) {
+            return Err(Error::Busy);
+        }
+        self.add_data(data, data_slice);
+        Ok(())
+    }
+
+    /// Like `read_data`, but fails with `Error::Lost` when fewer than D nodes send their
+    /// chunk within `Config::read_timeout`.
+    pub fn try_read_data(&self, data_slice: usize) -> Result<[Box<[u8; X]>; D]> {
+        self.handle_failures();
+        let mut chunks = self.read_chunks(data_slice, 0..D)?.into_iter();
+        Ok(core::array::from_fn(|_| {
+            galois::as_bytes(chunks.next().unwrap())
+        }))
+    }
+
+    /// Like `read_data_at`, but fails with `Error::Lost` like `try_read_data`.
+    pub fn try_read_data_at(&self, data_slice: usize, data_idx: usize) -> Result<Box<[u8; X]>> {
+        self.handle_failures();
+        let chunk = self.read_chunks(data_slice, data_idx..data_idx + 1)?.pop();
+        Ok(galois::as_bytes(chunk.unwrap()))
+    }
+
+    // the version of the next write. Taken with `lock` held, so every node gets the writes
+    // in the order of their versions
+    fn next_version(&self) -> Version {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // the epoch the writes of single chunks of the slice build on
+    fn epoch(&self, data_slice: usize) -> Version {
+        let epochs = self.epochs.lock().unwrap();
+        epochs.get(&data_slice).copied().unwrap_or(0)
+    }
+
+    fn is_down(&self, dev_idx: usize) -> bool {
+        self.health.state(dev_idx) == NodeState::Down
+    }
+
+    // a data node that is down can not update the checksums of the slice, the head node
+    // sends the checksum nodes what it would have sent
+    fn forward(&self, data_slice: usize, msg: impl Fn() -> Msg<X>) {
+        for check_idx in 0..C {
+            let check_dev = self.dev_idx(data_slice, check_idx + D);
+            self.transport.send(check_dev, msg()).unwrap();
+        }
+    }
+
+    /// Block until the running background rebuilds are done.
+    pub fn wait_for_rebuild(&self) {
+        rebuild::join(&self.rebuilds, None)
+    }
+
+    /// Stop the running rebuilds after their current window, they continue from their
+    /// checkpoint with `resume_rebuild` or on the next open. Without it `shutdown` waits
+    /// for them.
+    pub fn cancel_rebuilds(&self) {
+        rebuild::cancel(&self.rebuilds, None)
+    }
+
+    /// State of every node as the nodes see it.
+    pub fn node_states(&self) -> Vec<NodeState> {
+        self.health.states()
+    }
+
+    /// Progress of the running rebuilds by device.
+    pub fn rebuild_progress(&self) -> Vec<(usize, Progress)> {
+        rebuild::progress(&self.rebuilds)
+    }
+
+    /// Stop the rebuild of the node after the current window. The node stays degraded,
+    /// the rebuild continues from its checkpoint with `resume_rebuild` or when the array
+    /// is opened again.
+    pub fn cancel_rebuild(&self, dev_idx: usize) {
+        rebuild::cancel(&self.rebuilds, Some(dev_idx))
+    }
+
+    /// Continue a cancelled rebuild from its checkpoint.
+    pub fn resume_rebuild(&self, dev_idx: usize) {
+        self.cancel_rebuild(dev_idx);
+        if self.status(dev_idx).current {
+            return;
+        }
+        let job = self.rebuild_job(dev_idx, true);
+        self.transport
+            .send(
+                dev_idx,
+                Msg::Rebuild {
+                    data_slices: job.remaining(),
+                },
+            )
+            .unwrap();
+        self.spawn_rebuild(dev_idx, job);
+    }
+
+    // send a request to the node, the reply arrives on the returned receiver
+    fn request(&self, dev_idx: usize, msg: impl FnOnce(RequestId) -> Msg<X>) -> Receiver<Reply<X>> {
+        request(&*self.transport, &self.requests, dev_idx, msg).unwrap()
+    }
+
+    fn status(&self, dev_idx: usize) -> Status {
+        let reply = self.request(dev_idx, |request| Msg::Status { request });
+        reply.recv().unwrap().status()
+    }
+
+    fn wait(&self, dev_idx: usize, reply: Receiver<Reply<X>>) -> Option<Reply<X>> {
+        wait(reply, &self.health, dev_idx)
+    }
+
+    // every write sent so far is handled by the nodes it passes through
+    fn ping_nodes(&self) {
+        let rounds = self.aggregation.hops(D);
+        ping_all(&*self.transport, &self.requests, &self.health, rounds);
+    }
+
+    /// Wait until every write sent so far is on disk and clear the write-intent bitmap.
+    /// Data nodes forward chunks to the checksum nodes while handling a message, so there
+    /// is a ping round for every hop of a write, see `Aggregation`.
+    pub fn flush(&self) {
+        // no write starts until the ping is answered, so the bit of a slice written in the
+        // meantime is not cleared
+        let _guard = self.lock.lock().unwrap();
+        let sent = self.clock.load(Ordering::Relaxed);
+        let dirty = self.bitmap.set_slices();
+        self.ping_nodes();
+        for data_slice in dirty {
+            self.bitmap.clear(data_slice);
+        }
+        // the checksum nodes know these epochs, later writes do not have to wait for them
+        self.epochs.lock().unwrap().retain(|_, epoch| *epoch > sent);
+    }
+
+    /// Compute the data chunks of a slice from any D nodes that still have their storage.
+    fn decode(&self, data_slice: usize) -> [Box<[Galois; X]>; D] {
+        // checksums must include every write sent so far
+        self.flush();
+        let replies: Vec<_> = (0..D + C)
+            .filter(|dev_idx| self.health.is_healthy(*dev_idx))
+            .map(|dev_idx| {
+                let reply = self.request(dev_idx, |request| Msg::HeadNodeChunkRequest {
+                    data_slice,
+                    request,
+                });
+                (dev_idx, reply)
+            })
+            .collect();
+
+        let mut chunks: [Option<Box<[Galois; X]>>; D + C] = core::array::from_fn(|_| None);
+        for (dev_idx, reply) in replies {
+            let idx = self.layout.chunk(D, C, data_slice, dev_idx).unwrap();
+            let chunk = self.wait(dev_idx, reply).and_then(Reply::chunk);
+            // a rebuilding node is never a source
+            if self.health.is_healthy(dev_idx) {
+                chunks[idx] = chunk;
+            }
+        }
+        self.solve(chunks)
+    }
+
+    // the data chunks of a slice from the first D of its chunks
+    fn solve(&self, chunks: [Option<Box<[Galois; X]>>; D + C]) -> [Box<[Galois; X]>; D] {
+        let mut r_data = vec![];
+        let mut r_data_idx = vec![];
+        let mut r_check_idx = vec![];
+        for (idx, chunk) in chunks.into_iter().enumerate() {
+            if let Some(chunk) = chunk {
+                if r_data.len() == D {
+                    break;
+                }
+                r_data.push(chunk);
+                if idx < D {
+                    r_data_idx.push(idx);
+                } else {
+                    r_check_idx.push(idx - D);
+                }
+            }
+        }
+        if r_data.len() < D {
+            panic!("Too man devices lost")
+        }
+
+        let mut rec_matrix = self.vandermonde.recovery_matrix(r_data_idx, r_check_idx);
+        let mut rec_data: [Box<[Galois; X]>; D] = r_data.try_into().unwrap();
+        rec_matrix.gaussian_elimination(&mut rec_data);
+        rec_data
+    }
+
+    // the data chunks of the slice from their nodes. Nodes that are down, have no storage or
+    // do not answer within `read_timeout`, or the hedge delay, are read around: the other
+    // nodes are asked too and the slice is decoded from the first D chunks. `Error::Lost` if
+    // fewer than D nodes answer
+    fn read_chunks(
+        &self,
+        data_slice: usize,
+        data_idxs: Range<usize>,
+    ) -> Result<Vec<Box<[Galois; X]>>> {
+        let start = Instant::now();
+        let (send, replies) = unbounded();
+        // chunk index of every request that is not answered yet
+        let mut pending = HashMap::new();
+        let ask = |pending: &mut HashMap<RequestId, usize>, idx: usize, backup: bool| {
+            let request = self.requests.start_on(send.clone());
+            pending.insert(request, idx);
+            let msg = if backup {
+                Msg::HeadNodeChunkRequest {
+                    data_slice,
+                    request,
+                }
+            } else {
+                Msg::HeadNodeDataRequest {
+                    data_slice,
+                    request,
+                }
+            };
+            let dev_idx = self.dev_idx(data_slice, idx);
+            self.transport.send(dev_idx, msg).unwrap();
+        };
+
+        let mut chunks: [Option<Box<[Galois; X]>>; D + C] = core::array::from_fn(|_| None);
+        let mut asked = [false; D + C];
+        let mut read_around = false;
+        for idx in data_idxs.clone() {
+            if self.is_down(self.dev_idx(data_slice, idx)) {
+                read_around = true;
+            } else {
+                ask(&mut pending, idx, false);
+                asked[idx] = true;
+            }
+        }
+        let hedge = self.hedge.and_then(|p| self.latencies.percentile(p));
+        let mut deadline = start + hedge.map_or(self.read_timeout, |h| h.min(self.read_timeout));
+        let mut backups = false;
+        loop {
+            if data_idxs.clone().all(|idx| chunks[idx].is_some()) {
+                return Ok(data_idxs.map(|idx| chunks[idx].take().unwrap()).collect());
+            }
+            if backups && chunks.iter().flatten().count() >= D {
+                let decoded = self.solve(chunks);
+                return Ok(data_idxs.map(|idx| decoded[idx].clone()).collect());
+            }
+            if read_around && !backups {
+                // checksums must include the writes to the slice
+                if self.bitmap.is_set(data_slice) {
+                    self.flush();
+                }
+                for (idx, asked) in asked.iter_mut().enumerate() {
+                    if !*asked && self.health.is_healthy(self.dev_idx(data_slice, idx)) {
+                        ask(&mut pending, idx, true);
+                        *asked = true;
+                    }
+                }
+                backups = true;
+                deadline = Instant::now() + self.read_timeout;
+            }
+            if pending.is_empty() {
+                return Err(Error::Lost { data_slice });
+            }
+            match replies.recv_deadline(deadline) {
+                Ok(reply) => {
+                    let Some(idx) = pending.remove(&reply.request()) else {
+                        continue;
+                    };
+                    let dev_idx = self.dev_idx(data_slice, idx);
+                    let data_request = data_idxs.contains(&idx);
+                    if data_request {
+                        self.latencies.record(start.elapsed());
+                    }
+                    match reply.chunk() {
+                        // a rebuilding node is never a source to decode from
+                        Some(chunk) if data_request || self.health.is_healthy(dev_idx) => {
+                            chunks[idx] = Some(chunk)
+                        }
+                        _ => read_around = true,
+                    }
+                }
+                Err(_) if backups => return Err(Error::Lost { data_slice }),
+                Err(_) => read_around = true,
+            }
+        }
+    }
+
+    /// Open an existing array like `RAID::open`, but return an error if it can not be used.
+    pub fn try_open(root_path: PathBuf, config: Config) -> std::result::Result<Self, meta::Error> {
+        Meta::check::<D, C, X>(&root_path, &config)?;
+        Self::check_layout(&config).map_err(meta::Error::Layout)?;
+        disk::remove_tmp_files(&root_path);
+        let paths: [PathBuf; D + C] =
+            core::array::from_fn(|i| root_path.join(format!("device{i}")));
+        let mut config = config;
+        if config.peers.is_none() {
+            // spares that already took over a slot
+            let in_use: Vec<_> = paths
+                .iter()
+                .filter_map(|path| Device::new(path.clone(), config.durability).spare())
+                .collect();
+            config.spares.retain(|spare| !in_use.contains(spare));
+        }
+
+        let bitmap = Bitmap::open(&root_path.join("bitmap"), config.durability);
+        let mut checkpoint = Self::spawn(paths, config, bitmap, true);
+        // the nodes only answer the status before they know the states
+        let statuses: Vec<_> = (0..D + C).map(|i| checkpoint.status(i)).collect();
+        for (dev_idx, status) in statuses.iter().enumerate() {
+            checkpoint.health.set(dev_idx, status.state());
+        }
+        checkpoint.health.announce();
+        checkpoint.max_data_slices = statuses.iter().map(|s| s.max_data_slice).max().unwrap();
+        // nodes that kept running still know the versions of the last head node
+        *checkpoint.clock.get_mut() = statuses.iter().map(|s| s.version).max().unwrap();
+        // missing devices stay missing until they are reattached or rebuilt
+        let degraded = statuses.iter().any(|status| !status.current);
+
+        // a rebuild was interrupted, continue it. All nodes know their missing slices
+        // before any of them asks the others for chunks
+        let rebuilding: Vec<_> = (0..D + C)
+            .filter(|i| statuses[*i].state() == NodeState::Rebuilding)
+            .map(|dev_idx| (dev_idx, checkpoint.rebuild_job(dev_idx, true)))
+            .collect();
+        for (dev_idx, job) in &rebuilding {
+            checkpoint
+                .transport
+                .send(
+                    *dev_idx,
+                    Msg::Rebuild {
+                        data_slices: job.remaining(),
+                    },
+                )
+                .unwrap();
+        }
+        for (dev_idx, job) in rebuilding {
+            checkpoint.spawn_rebuild(dev_idx, job);
+        }
+
+        let dirty = checkpoint.bitmap.set_slices();
+        if degraded && !dirty.is_empty() {
+            checkpoint.shutdown();
+            return Err(meta::Error::DegradedDirty { dirty: dirty.len() });
+        }
+        for data_slice in dirty {
+            checkpoint.resync(data_slice);
+        }
+        checkpoint.flush();
+        Ok(checkpoint)
+    }
+
+    /// Bring back a node whose storage was missing for a while. Only the slices written in
+    /// the meantime are rebuilt. Returns the number of rebuilt slices.
+    pub fn reattach_device(&self, dev_idx: usize) -> std::result::Result<usize, device::Error> {
+        self.flush();
+        let reply = self.request(dev_idx, |request| Msg::Reattach { request });
+        let result = reply.recv().unwrap().reattach();
+        if result.is_ok() {
+            self.health.set(dev_idx, NodeState::Healthy);
+        }
+        result
+    }
+
+    /// Write the data chunks of a slice again, so the checksum nodes recompute the checksums.
+    fn resync(&self, data_slice: usize) {
+        let data = self.read_data(data_slice);
+        self.charge(data_slice, 0..D);
+        let version = self.next_version();
+        self.epochs.lock().unwrap().insert(data_slice, version);
+        for (data_idx, data) in data.into_iter().enumerate() {
+            let dev_idx = self.dev_idx(data_slice, data_idx);
+            self.transport
+                .send(
+                    dev_idx,
+                    Msg::NewData {
+                        data_slice,
+                        data: galois::from_bytes(data),
+                        version,
+                    },
+                )
+                .unwrap()
+        }
     }
 }
 
@@ -520,146 +2830,226 @@ where
     [(); C + C]:,
     [(); D + D]:,
 {
-    fn new(root_path: PathBuf) -> Self {
+    fn with_config(root_path: PathBuf, config: Config) -> Self {
+        Self::check_layout(&config).unwrap();
         let paths: [PathBuf; D + C] =
             core::array::from_fn(|i| root_path.join(format!("device{i}")));
-        for path in &paths {
-            let _ = std::fs::remove_dir_all(path);
-            create_dir(path).unwrap()
-        }
-
-        let channels: [(Sender<Msg<X>>, Receiver<Msg<X>>); D + C] =
-            core::array::from_fn(|_| unbounded());
-        let recover_channels: [(Sender<RecoverMsg<X>>, Receiver<RecoverMsg<X>>); D + C] =
-            core::array::from_fn(|_| unbounded());
-
-        let coms = core::array::from_fn(|i| channels[i].0.clone());
-        let recover_coms = core::array::from_fn(|i| recover_channels[i].0.clone());
-
-        let vandermonde = Matrix::<C, D>::reed_solomon();
-        let handles = core::array::from_fn(|i| {
-            let path = paths[i].clone();
-            let v = vandermonde.clone();
-            let c = coms.clone();
-            let rec_c = recover_coms.clone();
-            let r = channels[i].1.clone();
-            let rec_r = recover_channels[i].1.clone();
-            std::thread::Builder::new()
-                .name(format!("thread{i}"))
-                .spawn(move || {
-                    let node = Node::new(path, i, v, c, rec_c);
-                    let _ = node.start(r, rec_r);
-                })
-                .unwrap()
-        });
-
-        Self {
-            max_data_slices: 0,
-            handles,
-            coms,
+        // nodes in other processes start with empty storage
+        if config.peers.is_none() {
+            for path in &paths {
+                let _ = std::fs::remove_dir_all(path);
+                create_dir(path).unwrap()
+            }
         }
+        Meta::new::<D, C, X>(&config).store(&root_path, config.durability);
+
+        let bitmap = Bitmap::create(&root_path.join("bitmap"), config.durability);
+        Self::spawn(paths, config, bitmap, false)
+    }
+
+    fn open(root_path: PathBuf, config: Config) -> Self {
+        Self::try_open(root_path, config).unwrap()
     }
 
     fn add_data(&mut self, data: &[&[u8; X]; D], data_slice: usize) {
+        self.handle_failures();
         self.max_data_slices = self.max_data_slices.max(data_slice);
+        let fan_in = self.fan_in(data_slice);
+        match fan_in {
+            Some(fan_in) => self.charge_aggregated(data_slice, fan_in),
+            None => self.charge(data_slice, 0..D),
+        }
+        let _guard = self.lock.lock().unwrap();
+        self.bitmap.set(data_slice);
+        let version = self.next_version();
+        self.epochs.lock().unwrap().insert(data_slice, version);
         for data_idx in 0..D {
             let pdata = galois::from_slice_raw(data[data_idx]);
-            let dev_idx = Self::dev_idx(data_slice, data_idx);
-            self.coms[dev_idx]
-                .send(Msg::NewData {
-                    data_slice: data_slice,
+            let dev_idx = self.dev_idx(data_slice, data_idx);
+            if let Some(fan_in) = fan_in {
+                let msg = Msg::NewDataAggregated {
+                    data_slice,
                     data: pdata,
-                })
+                    version,
+                    fan_in,
+                };
+                self.transport.send(dev_idx, msg).unwrap();
+                continue;
+            }
+            if self.is_down(dev_idx) {
+                self.forward(data_slice, || Msg::NewDataChecksum {
+                    data_slice,
+                    data: pdata.clone(),
+                    dev_idx,
+                    version,
+                });
+                continue;
+            }
+            self.transport
+                .send(
+                    dev_idx,
+                    Msg::NewData {
+                        data_slice,
+                        data: pdata,
+                        version,
+                    },
+                )
                 .unwrap()
         }
     }
 
     fn add_data_at(&mut self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        self.handle_failures();
         self.max_data_slices = self.max_data_slices.max(data_slice);
+        let dev_idx = self.dev_idx(data_slice, data_idx);
+        self.charge(data_slice, data_idx..data_idx + 1);
+        let _guard = self.lock.lock().unwrap();
+        self.bitmap.set(data_slice);
         let data = galois::from_slice_raw(data);
-        let dev_idx = Self::dev_idx(data_slice, data_idx);
-        self.coms[dev_idx]
-            .send(Msg::NewDataAt { data_slice, data })
-            .unwrap()
+        let version = self.next_version();
+        let epoch = self.epoch(data_slice);
+        if self.is_down(dev_idx) {
+            self.forward(data_slice, || Msg::NewDataChecksumAt {
+                data_slice,
+                data: data.clone(),
+                dev_idx,
+                version,
+                epoch,
+            });
+            return;
+        }
+        let msg = Msg::NewDataAt {
+            data_slice,
+            data,
+            version,
+            epoch,
+        };
+        self.transport.send(dev_idx, msg).unwrap()
     }
 
     fn read_data(&self, data_slice: usize) -> [Box<[u8; X]>; D] {
-        let receivers: [oneshot::Receiver<CheckpointMsg<X>>; D] = std::array::from_fn(|i| {
-            let dev_idx = Self::dev_idx(data_slice, i);
-            let (rt, tx) = oneshot::channel();
-            self.coms[dev_idx]
-                .send(Msg::HeadNodeDataRequest {
-                    data_slice,
-                    oneshot_send: rt,
-                })
-                .unwrap();
-            tx
-        });
-
-        let mut result = core::array::from_fn(|_| galois::as_bytes(galois::zeros()));
-        for (i, receiver) in receivers.into_iter().enumerate() {
-            let msg = receiver.recv().unwrap();
-            assert_eq!(msg.data_slice, data_slice);
-            result[i] = galois::as_bytes(msg.data);
-        }
-        result
+        self.try_read_data(data_slice).unwrap()
     }
 
     fn read_data_at(&self, data_slice: usize, data_idx: usize) -> Box<[u8; X]> {
-        let dev_idx = Self::dev_idx(data_slice, data_idx);
-        let (rt, tx) = oneshot::channel();
-        self.coms[dev_idx]
-            .send(Msg::HeadNodeDataRequest {
-                data_slice,
-                oneshot_send: rt,
-            })
-            .unwrap();
-
-        let msg = tx.recv().unwrap();
-        assert_eq!(msg.data_slice, data_slice);
-        galois::as_bytes(msg.data)
+        self.try_read_data_at(data_slice, data_idx).unwrap()
     }
 
     fn destroy_devices(&self, dev_idxs: &[usize]) {
+        // all lost nodes are known before any rebuild asks for chunks
         for dev_idx in dev_idxs {
-            self.coms[*dev_idx]
-                .send(Msg::DestroyStorage {
-                    max_data_slice: self.max_data_slices,
-                })
-                .unwrap()
+            self.health.set(*dev_idx, NodeState::Failed);
+        }
+        let healthy = (0..D + C).filter(|i| self.health.is_healthy(*i)).count();
+        if healthy < D {
+            panic!("Too man devices lost")
+        }
+        // without a spare the node rebuilds its storage in place
+        let mut in_place = vec![];
+        for dev_idx in dev_idxs {
+            if self.promote_spare(*dev_idx) {
+                continue;
+            }
+            in_place.push((*dev_idx, self.destroy_storage(*dev_idx)));
+        }
+        let in_place: Vec<_> = in_place
+            .into_iter()
+            .map(|(dev_idx, job)| {
+                self.spawn_rebuild(dev_idx, job);
+                dev_idx
+            })
+            .collect();
+        for dev_idx in in_place {
+            rebuild::join(&self.rebuilds, Some(dev_idx));
         }
     }
 
+    fn replace_device(&self, dev_idx: usize, path: PathBuf) {
+        self.handle_failures();
+        self.transport.send(dev_idx, Msg::Replace { path }).unwrap();
+        let data_slices = 0..self.max_data_slices + 1;
+        run_windows::<D, X>(
+            &*self.transport,
+            &self.requests,
+            &self.health,
+            &self.lock,
+            self.aggregation.hops(D),
+            dev_idx,
+            data_slices,
+            |data_slices, request| Msg::Copy {
+                data_slices,
+                request,
+            },
+            None,
+        );
+        let reply = self.request(dev_idx, |request| Msg::Replaced { request });
+        reply.recv().unwrap();
+    }
+
     // wait for every thread to finish. Used for the benchmarks
     fn ping(&self) {
-        let mut txs = vec![];
-
-        for dev_idx in 0..D + C {
-            let (rt, tx) = oneshot::channel();
-            txs.push(tx);
-            self.coms[dev_idx]
-                .send(Msg::Ping { oneshot_send: rt })
-                .unwrap()
-        }
-        for tx in txs {
-            tx.recv().unwrap()
-        }
+        self.flush();
     }
 
     fn update_data(&self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        self.handle_failures();
         let data = galois::from_slice(galois::from_bytes_ref(data));
-        let dev_idx = Self::dev_idx(data_slice, data_idx);
-        self.coms[dev_idx]
-            .send(Msg::UpdateData { data_slice, data })
-            .unwrap()
+        let dev_idx = self.dev_idx(data_slice, data_idx);
+        // a node without its old chunk decodes it from the others, their checksums must
+        // include the earlier writes to the slice
+        if self.bitmap.is_set(data_slice) {
+            self.flush();
+        }
+        // the old chunk of a node that is down comes from the other nodes
+        let old = self
+            .is_down(dev_idx)
+            .then(|| self.decode(data_slice).into_iter().nth(data_idx).unwrap());
+        self.charge(data_slice, data_idx..data_idx + 1);
+        let _guard = self.lock.lock().unwrap();
+        self.bitmap.set(data_slice);
+        let version = self.next_version();
+        let epoch = self.epoch(data_slice);
+        match old {
+            None => {
+                let msg = Msg::UpdateData {
+                    data_slice,
+                    data,
+                    version,
+                    epoch,
+                };
+                self.transport.send(dev_idx, msg).unwrap()
+            }
+            Some(old) => {
+                let diff: Box<[Galois; X]> = galois::from_fn(|i| data[i] - old[i]);
+                self.forward(data_slice, || Msg::UpdateDataChecksum {
+                    data_slice,
+                    diff: diff.clone(),
+                    dev_idx,
+                    version,
+                    epoch,
+                });
+            }
+        }
     }
 
     fn shutdown(self) {
+        self.wait_for_rebuild();
+        self.flush();
+        drop(self.monitor.stop);
+        self.monitor.handle.join().unwrap();
         for dev_idx in 0..D + C {
-            self.coms[dev_idx].send(Msg::Shutdown).unwrap()
+            self.transport.send(dev_idx, Msg::Shutdown).unwrap()
+        }
+        // nodes in other processes get the shutdown before the links close, nodes that are
+        // down do not hold it up
+        self.transport.sync();
+        // the thread of a node that is down may have panicked
+        for handle in self.handles.into_inner().unwrap() {
+            let _ = handle.join();
         }
-        for handle in self.handles {
-            handle.join().unwrap();
+        for spare in self.spares.into_inner().unwrap() {
+            drop(spare.promote);
+            spare.handle.join().unwrap();
         }
     }
 }
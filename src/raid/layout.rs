@@ -0,0 +1,740 @@
+//! Placement of the chunks of a slice on the devices.
+//!
+//! Chunk `idx` of a slice is data chunk `idx` for `idx < data` and checksum `idx - data`
+//! after that. A layout maps every chunk of a slice to a different device. Most layouts use
+//! exactly `data + checks` devices, a declustered one spreads the slices over more. Only the
+//! `Controller` can use more devices, a `Checkpoint` has one node for every chunk of a slice.
+//! The layout of an array is stored in its meta file.
+//!
+//! Devices that fail together, like disks behind one controller, share a failure domain.
+//! No domain may hold more than `checks` chunks of a slice, or one without checksums,
+//! so losing a whole domain never loses data. Devices of different sizes get weights, only
+//! a `Weighted` layout fills them in proportion.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Why the devices do not fit a layout.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// fewer failure domain labels than devices
+    Domains { labels: usize, devices: usize },
+    /// fewer weights than devices
+    Weights { weights: usize, devices: usize },
+    /// a device with weight 0
+    ZeroWeight,
+    /// the layout fills every device the same, the weights differ
+    Unweighted { layout: String },
+    /// some domain would hold more than `max` chunks of a slice
+    DomainsDoNotFit { max: usize },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub trait Layout: fmt::Debug + Send + Sync {
+    /// Name in the meta file, an array must be opened with the layout it was created with.
+    fn name(&self) -> String;
+
+    /// Number of devices of the array.
+    fn devices(&self, data: usize, checks: usize) -> usize {
+        data + checks
+    }
+
+    /// Device of chunk `idx` of the slice.
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize;
+
+    /// The layout for the devices of the pool. Fails if a domain would hold more chunks of a
+    /// slice than allowed or if the layout can not follow the weights.
+    fn for_pool(&self, data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>>;
+
+    /// Chunk of the slice stored on the device, the inverse of `device`.
+    /// None if the device has no chunk of the slice.
+    fn chunk(
+        &self,
+        data: usize,
+        checks: usize,
+        data_slice: usize,
+        dev_idx: usize,
+    ) -> Option<usize> {
+        (0..data + checks).find(|&idx| self.device(data, checks, data_slice, idx) == dev_idx)
+    }
+}
+
+/// Every chunk moves one device further with each slice. The default layout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rotation;
+
+impl Layout for Rotation {
+    fn name(&self) -> String {
+        "rotation".to_string()
+    }
+
+    fn for_pool(&self, _data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        every_slice_everywhere(*self, checks, pool)
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        (idx + data_slice) % (data + checks)
+    }
+
+    fn chunk(
+        &self,
+        data: usize,
+        checks: usize,
+        data_slice: usize,
+        dev_idx: usize,
+    ) -> Option<usize> {
+        let n = data + checks;
+        Some((dev_idx + n - data_slice % n) % n)
+    }
+}
+
+/// The checksums always live on the last `checks` devices, like RAID 4.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Dedicated;
+
+impl Layout for Dedicated {
+    fn name(&self) -> String {
+        "dedicated".to_string()
+    }
+
+    fn for_pool(&self, _data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        every_slice_everywhere(*self, checks, pool)
+    }
+
+    fn device(&self, _data: usize, _checks: usize, _data_slice: usize, idx: usize) -> usize {
+        idx
+    }
+
+    fn chunk(
+        &self,
+        _data: usize,
+        _checks: usize,
+        _data_slice: usize,
+        dev_idx: usize,
+    ) -> Option<usize> {
+        Some(dev_idx)
+    }
+}
+
+/// The checksums start on the last device and move one device to the left with each
+/// slice. The data follows the checksums and wraps around.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LeftSymmetric;
+
+/// Like `LeftSymmetric`, but the data fills the other devices from the first one on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LeftAsymmetric;
+
+/// The checksums start on the first device and move one device to the right with each
+/// slice. The data follows the checksums and wraps around.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RightSymmetric;
+
+/// Like `RightSymmetric`, but the data fills the other devices from the first one on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RightAsymmetric;
+
+/// The devices of an array as the placement sees them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pool {
+    /// failure domain of every device
+    pub domains: Vec<usize>,
+    /// relative capacity of every device
+    pub weights: Vec<u64>,
+}
+
+impl Pool {
+    /// Pool of the first `devices` devices with the domain labels and weights. No labels put
+    /// every device in its own domain, no weights give every device the same.
+    pub fn new(labels: &[String], weights: &[u64], devices: usize) -> Result<Self> {
+        if !labels.is_empty() && labels.len() < devices {
+            return Err(Error::Domains {
+                labels: labels.len(),
+                devices,
+            });
+        }
+        if !weights.is_empty() && weights.len() < devices {
+            return Err(Error::Weights {
+                weights: weights.len(),
+                devices,
+            });
+        }
+        if weights.contains(&0) {
+            return Err(Error::ZeroWeight);
+        }
+        let mut ids = HashMap::new();
+        let domains = if labels.is_empty() {
+            (0..devices).collect()
+        } else {
+            labels[..devices]
+                .iter()
+                .map(|label| {
+                    let next = ids.len();
+                    *ids.entry(label).or_insert(next)
+                })
+                .collect()
+        };
+        let weights = if weights.is_empty() {
+            vec![1; devices]
+        } else {
+            weights[..devices].to_vec()
+        };
+        Ok(Self { domains, weights })
+    }
+
+    // devices of every domain, in the same order on every machine
+    fn members(&self) -> BTreeMap<usize, Vec<usize>> {
+        let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (dev_idx, domain) in self.domains.iter().enumerate() {
+            members.entry(*domain).or_default().push(dev_idx);
+        }
+        members
+    }
+
+    fn check_equal_weights(&self, layout: &dyn Layout) -> Result<()> {
+        if self.weights.iter().any(|&weight| weight != self.weights[0]) {
+            return Err(Error::Unweighted {
+                layout: layout.name(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// most chunks of a slice one domain may hold
+fn max_chunks(checks: usize) -> usize {
+    checks.max(1)
+}
+
+fn check_domains_fit(pool: &Pool, width: usize, checks: usize) -> Result<()> {
+    let fits = pool
+        .members()
+        .values()
+        .map(|members| members.len().min(max_chunks(checks)))
+        .sum::<usize>()
+        >= width;
+    if !fits {
+        return Err(Error::DomainsDoNotFit {
+            max: max_chunks(checks),
+        });
+    }
+    Ok(())
+}
+
+// every device has a chunk of every slice, so a domain holds a chunk per device
+fn every_slice_everywhere(
+    layout: impl Layout + 'static,
+    checks: usize,
+    pool: &Pool,
+) -> Result<Arc<dyn Layout>> {
+    pool.check_equal_weights(&layout)?;
+    check_domains_fit(pool, pool.domains.len(), checks)?;
+    Ok(Arc::new(layout))
+}
+
+// device of the first checksum, the others follow it
+fn left_parity(n: usize, data_slice: usize) -> usize {
+    n - 1 - data_slice % n
+}
+
+fn right_parity(n: usize, data_slice: usize) -> usize {
+    data_slice % n
+}
+
+fn symmetric(data: usize, checks: usize, parity: usize, idx: usize) -> usize {
+    let n = data + checks;
+    if idx < data {
+        (parity + checks + idx) % n
+    } else {
+        (parity + idx - data) % n
+    }
+}
+
+fn asymmetric(data: usize, checks: usize, parity: usize, idx: usize) -> usize {
+    let n = data + checks;
+    if idx >= data {
+        return (parity + idx - data) % n;
+    }
+    // the checksums wrap around, the data is between their end and their start
+    let end = parity + checks;
+    if end > n {
+        end - n + idx
+    } else if idx < parity {
+        idx
+    } else {
+        idx + checks
+    }
+}
+
+impl Layout for LeftSymmetric {
+    fn name(&self) -> String {
+        "left-symmetric".to_string()
+    }
+
+    fn for_pool(&self, _data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        every_slice_everywhere(*self, checks, pool)
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        symmetric(data, checks, left_parity(data + checks, data_slice), idx)
+    }
+}
+
+impl Layout for LeftAsymmetric {
+    fn name(&self) -> String {
+        "left-asymmetric".to_string()
+    }
+
+    fn for_pool(&self, _data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        every_slice_everywhere(*self, checks, pool)
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        asymmetric(data, checks, left_parity(data + checks, data_slice), idx)
+    }
+}
+
+impl Layout for RightSymmetric {
+    fn name(&self) -> String {
+        "right-symmetric".to_string()
+    }
+
+    fn for_pool(&self, _data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        every_slice_everywhere(*self, checks, pool)
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        symmetric(data, checks, right_parity(data + checks, data_slice), idx)
+    }
+}
+
+impl Layout for RightAsymmetric {
+    fn name(&self) -> String {
+        "right-asymmetric".to_string()
+    }
+
+    fn for_pool(&self, _data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        every_slice_everywhere(*self, checks, pool)
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        asymmetric(data, checks, right_parity(data + checks, data_slice), idx)
+    }
+}
+
+/// Every slice is placed on a pseudo random subset of `devices` devices, which can be more
+/// than `data + checks`. A failed device shares its slices with all others, so a rebuild
+/// reads from every survivor instead of the same `data` devices.
+///
+/// The slices are placed in rounds. Every round shuffles the devices and lays the slices of
+/// the round one after the other onto the shuffled order, wrapping around. A round has just
+/// enough slices to end at the end of the order, so every device gets the same number of
+/// chunks in every round. A device whose domain already holds enough chunks of the slice is
+/// skipped, the slice takes the next one.
+///
+/// Only the `Controller` supports it, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Declustered {
+    pub devices: usize,
+    // domain of every device, empty if every device is its own domain
+    domains: Vec<usize>,
+    cache: SliceCache,
+}
+
+impl Declustered {
+    pub fn new(devices: usize) -> Self {
+        Self {
+            devices,
+            domains: vec![],
+            cache: SliceCache::default(),
+        }
+    }
+
+    fn cached_devices(&self, data: usize, checks: usize, data_slice: usize) -> Arc<[usize]> {
+        self.cache.get(data, checks, data_slice, || {
+            self.slice_devices(data, checks, data_slice)
+        })
+    }
+
+    fn slices_per_round(&self, width: usize) -> usize {
+        self.devices / gcd(self.devices, width)
+    }
+
+    // devices of the round in shuffled order
+    fn order(&self, round: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.devices).collect();
+        let mut state = round as u64;
+        for i in (1..self.devices).rev() {
+            let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+            order.swap(i, j);
+        }
+        order
+    }
+
+    fn check_width(&self, width: usize) {
+        if width > self.devices {
+            panic!("a slice of {width} chunks needs at least {width} devices")
+        }
+    }
+
+    // devices of the chunks of the slice
+    fn slice_devices(&self, data: usize, checks: usize, data_slice: usize) -> Vec<usize> {
+        let width = data + checks;
+        self.check_width(width);
+        let slices = self.slices_per_round(width);
+        let order = self.order(data_slice / slices);
+        let start = data_slice % slices * width;
+        if self.domains.is_empty() {
+            return (start..start + width)
+                .map(|position| order[position % self.devices])
+                .collect();
+        }
+        let mut devices = Vec::with_capacity(width);
+        let mut chunks = HashMap::new();
+        for position in start..start + self.devices {
+            let dev_idx = order[position % self.devices];
+            let chunks = chunks.entry(self.domains[dev_idx]).or_insert(0);
+            if *chunks < max_chunks(checks) {
+                *chunks += 1;
+                devices.push(dev_idx);
+                if devices.len() == width {
+                    return devices;
+                }
+            }
+        }
+        panic!("the failure domains do not fit a slice")
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// the same numbers on every machine, unlike a seeded `rand` generator
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// slices a `SliceCache` holds before it starts over
+const SLICE_CACHE: usize = 4096;
+
+// data devices, checksum devices and the slice
+type SliceKey = (usize, usize, usize);
+
+// devices of the recently used slices, finding them takes time linear in the devices
+#[derive(Debug, Default)]
+struct SliceCache(Mutex<HashMap<SliceKey, Arc<[usize]>>>);
+
+impl Clone for SliceCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl SliceCache {
+    fn get(
+        &self,
+        data: usize,
+        checks: usize,
+        data_slice: usize,
+        compute: impl FnOnce() -> Vec<usize>,
+    ) -> Arc<[usize]> {
+        let key = (data, checks, data_slice);
+        if let Some(devices) = self.0.lock().unwrap().get(&key) {
+            return devices.clone();
+        }
+        let devices: Arc<[usize]> = compute().into();
+        let mut cache = self.0.lock().unwrap();
+        if cache.len() >= SLICE_CACHE {
+            cache.clear();
+        }
+        cache.insert(key, devices.clone());
+        devices
+    }
+}
+
+impl Layout for Declustered {
+    fn name(&self) -> String {
+        format!("declustered-{}", self.devices)
+    }
+
+    fn devices(&self, data: usize, checks: usize) -> usize {
+        self.check_width(data + checks);
+        self.devices
+    }
+
+    fn for_pool(&self, data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        pool.check_equal_weights(self)?;
+        // a slice takes at most `max_chunks` devices of every domain
+        check_domains_fit(pool, data + checks, checks)?;
+        Ok(Arc::new(Self {
+            devices: self.devices,
+            domains: pool.domains.clone(),
+            cache: SliceCache::default(),
+        }))
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        self.cached_devices(data, checks, data_slice)[idx]
+    }
+
+    fn chunk(
+        &self,
+        data: usize,
+        checks: usize,
+        data_slice: usize,
+        dev_idx: usize,
+    ) -> Option<usize> {
+        self.cached_devices(data, checks, data_slice)
+            .iter()
+            .position(|&dev| dev == dev_idx)
+    }
+}
+
+// one chunk in fixed point
+const UNIT: u64 = 1 << 32;
+
+/// Like `Declustered`, but every device gets chunks in proportion to its weight, so a device
+/// twice as large fills up at the same pace as the others.
+///
+/// Every device has a share of the slices it holds a chunk of, its weight spread over the
+/// `data + checks` chunks of a slice. No share is above one chunk and no domain gets more
+/// than it may hold, the rest goes to the other devices. A slice shuffles the domains and the
+/// devices in them and lays the shares one after the other. It takes the devices under
+/// `data + checks` points one chunk apart, starting at a random offset. A device covers at
+/// most one point and a domain at most as many as it may hold.
+///
+/// Only the `Controller` supports it, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Weighted {
+    pub devices: usize,
+    pool: Pool,
+    // share of every device in `UNIT`s for slices of `width` chunks
+    width: usize,
+    shares: Vec<u64>,
+    cache: SliceCache,
+}
+
+impl Weighted {
+    pub fn new(devices: usize) -> Self {
+        Self {
+            devices,
+            pool: Pool::new(&[], &[], devices).unwrap(),
+            width: 0,
+            shares: vec![],
+            cache: SliceCache::default(),
+        }
+    }
+
+    fn cached_devices(&self, data: usize, checks: usize, data_slice: usize) -> Arc<[usize]> {
+        self.cache.get(data, checks, data_slice, || {
+            self.slice_devices(data, checks, data_slice)
+        })
+    }
+
+    fn check_width(&self, width: usize) {
+        if width > self.devices {
+            panic!("a slice of {width} chunks needs at least {width} devices")
+        }
+    }
+
+    // shares for `width` chunks per slice
+    fn shares(&self, width: usize, checks: usize) -> Vec<u64> {
+        let members = self.pool.members();
+        let domains: Vec<&Vec<usize>> = members.values().collect();
+        let weight = |devices: &[usize]| -> f64 {
+            devices
+                .iter()
+                .map(|&dev_idx| self.pool.weights[dev_idx] as f64)
+                .sum()
+        };
+        let domain_shares = water_fill(
+            &domains
+                .iter()
+                .map(|devices| weight(devices))
+                .collect::<Vec<_>>(),
+            &domains
+                .iter()
+                .map(|devices| devices.len().min(max_chunks(checks)) as f64)
+                .collect::<Vec<_>>(),
+            width as f64,
+        );
+        let mut shares = vec![0; self.devices];
+        let mut domain_units = vec![0; domains.len()];
+        for (domain, devices) in domains.iter().enumerate() {
+            let device_shares = water_fill(
+                &devices
+                    .iter()
+                    .map(|&dev_idx| self.pool.weights[dev_idx] as f64)
+                    .collect::<Vec<_>>(),
+                &vec![1.0; devices.len()],
+                domain_shares[domain],
+            );
+            for (&dev_idx, share) in devices.iter().zip(device_shares) {
+                shares[dev_idx] = ((share * UNIT as f64) as u64).min(UNIT);
+                domain_units[domain] += shares[dev_idx];
+            }
+            // the float shares can end a bit above the cap
+            let cap = devices.len().min(max_chunks(checks)) as u64 * UNIT;
+            while domain_units[domain] > cap {
+                let largest = *devices.iter().max_by_key(|&&i| shares[i]).unwrap();
+                shares[largest] -= 1;
+                domain_units[domain] -= 1;
+            }
+        }
+        // hand out what the rounding lost, without going over a cap
+        let mut missing = width as u64 * UNIT - shares.iter().sum::<u64>();
+        while missing > 0 {
+            let before = missing;
+            for (domain, devices) in domains.iter().enumerate() {
+                let cap = devices.len().min(max_chunks(checks)) as u64 * UNIT;
+                for &dev_idx in devices.iter() {
+                    if missing > 0 && shares[dev_idx] < UNIT && domain_units[domain] < cap {
+                        shares[dev_idx] += 1;
+                        domain_units[domain] += 1;
+                        missing -= 1;
+                    }
+                }
+            }
+            assert!(missing < before);
+        }
+        shares
+    }
+
+    // devices of the chunks of the slice
+    fn slice_devices(&self, data: usize, checks: usize, data_slice: usize) -> Vec<usize> {
+        let width = data + checks;
+        self.check_width(width);
+        let computed;
+        let shares = if self.width == width {
+            &self.shares
+        } else {
+            computed = self.shares(width, checks);
+            &computed
+        };
+
+        let mut state = data_slice as u64;
+        let mut members: Vec<Vec<usize>> = self.pool.members().into_values().collect();
+        shuffle(&mut members, &mut state);
+        let mut point = splitmix64(&mut state) % UNIT;
+        let mut end = 0;
+        let mut devices = Vec::with_capacity(width);
+        for mut domain in members {
+            shuffle(&mut domain, &mut state);
+            for dev_idx in domain {
+                end += shares[dev_idx];
+                if point < end {
+                    devices.push(dev_idx);
+                    point += UNIT;
+                }
+            }
+        }
+        devices
+    }
+}
+
+fn shuffle<T>(items: &mut [T], state: &mut u64) {
+    for i in (1..items.len()).rev() {
+        let j = (splitmix64(state) % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+// spread `total` over the entries in proportion to their weights, an entry that would get
+// more than its cap gets the cap and the others share the rest
+fn water_fill(weights: &[f64], caps: &[f64], total: f64) -> Vec<f64> {
+    let mut shares = vec![0.0; weights.len()];
+    let mut open: Vec<usize> = (0..weights.len()).collect();
+    let mut left = total;
+    while !open.is_empty() {
+        let weight: f64 = open.iter().map(|&i| weights[i]).sum();
+        let full: Vec<usize> = open
+            .iter()
+            .copied()
+            .filter(|&i| left * weights[i] / weight >= caps[i])
+            .collect();
+        if full.is_empty() {
+            for &i in &open {
+                shares[i] = left * weights[i] / weight;
+            }
+            break;
+        }
+        for &i in &full {
+            shares[i] = caps[i];
+            left -= caps[i];
+        }
+        open.retain(|i| !full.contains(i));
+    }
+    shares
+}
+
+impl Layout for Weighted {
+    fn name(&self) -> String {
+        format!("weighted-{}", self.devices)
+    }
+
+    fn devices(&self, data: usize, checks: usize) -> usize {
+        self.check_width(data + checks);
+        self.devices
+    }
+
+    fn for_pool(&self, data: usize, checks: usize, pool: &Pool) -> Result<Arc<dyn Layout>> {
+        check_domains_fit(pool, data + checks, checks)?;
+        let mut layout = Self {
+            devices: self.devices,
+            pool: pool.clone(),
+            width: 0,
+            shares: vec![],
+            cache: SliceCache::default(),
+        };
+        layout.shares = layout.shares(data + checks, checks);
+        layout.width = data + checks;
+        Ok(Arc::new(layout))
+    }
+
+    fn device(&self, data: usize, checks: usize, data_slice: usize, idx: usize) -> usize {
+        self.cached_devices(data, checks, data_slice)[idx]
+    }
+
+    fn chunk(
+        &self,
+        data: usize,
+        checks: usize,
+        data_slice: usize,
+        dev_idx: usize,
+    ) -> Option<usize> {
+        self.cached_devices(data, checks, data_slice)
+            .iter()
+            .position(|&dev| dev == dev_idx)
+    }
+}
+
+/// The built-in layout with the name.
+pub fn by_name(name: &str) -> Option<Arc<dyn Layout>> {
+    if let Some(devices) = name.strip_prefix("declustered-") {
+        return Some(Arc::new(Declustered::new(devices.parse().ok()?)));
+    }
+    if let Some(devices) = name.strip_prefix("weighted-") {
+        return Some(Arc::new(Weighted::new(devices.parse().ok()?)));
+    }
+    let layout: Arc<dyn Layout> = match name {
+        "rotation" => Arc::new(Rotation),
+        "dedicated" => Arc::new(Dedicated),
+        "left-symmetric" => Arc::new(LeftSymmetric),
+        "left-asymmetric" => Arc::new(LeftAsymmetric),
+        "right-symmetric" => Arc::new(RightSymmetric),
+        "right-asymmetric" => Arc::new(RightAsymmetric),
+        _ => return None,
+    };
+    Some(layout)
+}
@@ -0,0 +1,172 @@
+//! On-disk description of an array, stored next to the device folders.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::raid::disk;
+use crate::raid::disk::Durability;
+use crate::raid::layout;
+use crate::raid::layout::{Layout, Rotation};
+use crate::raid::{parity, reshape, Config};
+
+#[derive(Debug)]
+pub enum Error {
+    /// the description can not be read
+    Io(io::Error),
+    /// a line of the description that is not a known `key=value`
+    Corrupt(String),
+    /// the array was created with another geometry, layout, domains or weights
+    Mismatch {
+        stored: Box<Meta>,
+        expected: Box<Meta>,
+    },
+    /// the array is being reshaped or its checksum devices are being changed, it must be
+    /// opened with `Reshape` or `ParityChange`
+    Changing,
+    /// devices are missing and slices were being written, their checksums can not be
+    /// recomputed
+    DegradedDirty { dirty: usize },
+    /// the failure domains or weights do not fit the layout
+    Layout(layout::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meta {
+    pub data_devices: usize,
+    pub checksum_devices: usize,
+    pub chunk_size: usize,
+    pub layout: String,
+    pub domains: Vec<String>,
+    pub weights: Vec<u64>,
+}
+
+impl Meta {
+    pub fn new<const D: usize, const C: usize, const X: usize>(config: &Config) -> Self {
+        let devices = config.layout.devices(D, C);
+        Self {
+            data_devices: D,
+            checksum_devices: C,
+            chunk_size: X,
+            layout: config.layout.name(),
+            domains: config.domains.iter().take(devices).cloned().collect(),
+            weights: config.weights.iter().take(devices).copied().collect(),
+        }
+    }
+
+    fn path(root_path: &Path) -> PathBuf {
+        root_path.join("meta")
+    }
+
+    pub fn store(&self, root_path: &Path, durability: Durability) {
+        let content = format!(
+            "data_devices={}\nchecksum_devices={}\nchunk_size={}\nlayout={}\ndomains={}\nweights={}\n",
+            self.data_devices,
+            self.checksum_devices,
+            self.chunk_size,
+            self.layout,
+            self.domains.join(","),
+            self.weights
+                .iter()
+                .map(|weight| weight.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        disk::write(Self::path(root_path), content.as_bytes(), durability);
+    }
+
+    pub fn load(root_path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(Self::path(root_path)).map_err(Error::Io)?;
+        let mut meta = Self {
+            data_devices: 0,
+            checksum_devices: 0,
+            chunk_size: 0,
+            // arrays from before layouts were stored
+            layout: Rotation.name(),
+            domains: vec![],
+            weights: vec![],
+        };
+        for line in content.lines() {
+            let corrupt = || Error::Corrupt(line.to_string());
+            let (key, value) = line.split_once('=').ok_or_else(corrupt)?;
+            match key {
+                "data_devices" => meta.data_devices = value.parse().map_err(|_| corrupt())?,
+                "checksum_devices" => {
+                    meta.checksum_devices = value.parse().map_err(|_| corrupt())?
+                }
+                "chunk_size" => meta.chunk_size = value.parse().map_err(|_| corrupt())?,
+                "layout" => meta.layout = value.to_string(),
+                "domains" => {
+                    meta.domains = value
+                        .split(',')
+                        .filter(|label| !label.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                "weights" => {
+                    meta.weights = value
+                        .split(',')
+                        .filter(|weight| !weight.is_empty())
+                        .map(|weight| weight.parse().map_err(|_| corrupt()))
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(corrupt()),
+            }
+        }
+        Ok(meta)
+    }
+
+    /// Load the stored description and make sure it matches the geometry `D`, `C`, `X`,
+    /// the layout, the failure domains and the weights.
+    pub fn check<const D: usize, const C: usize, const X: usize>(
+        root_path: &Path,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        if reshape::is_reshaping(root_path) || parity::is_changing(root_path) {
+            return Err(Error::Changing);
+        }
+        let stored = Self::load(root_path)?;
+        let expected = Self::new::<D, C, X>(config);
+        if stored != expected {
+            return Err(Error::Mismatch {
+                stored: Box::new(stored),
+                expected: Box::new(expected),
+            });
+        }
+        Ok(stored)
+    }
+}
+
+/// parse `{data_slice}_{idx}d.bin` or `{data_slice}_{idx}c.bin`
+pub fn parse_chunk_name(name: &str) -> Option<(usize, usize, bool)> {
+    let name = name.strip_suffix(".bin")?;
+    let (data_slice, idx) = name.split_once('_')?;
+    let (idx, is_data) = match idx.strip_suffix('d') {
+        Some(idx) => (idx, true),
+        None => (idx.strip_suffix('c')?, false),
+    };
+    Some((data_slice.parse().ok()?, idx.parse().ok()?, is_data))
+}
+
+/// Highest slice with a chunk on one of the devices.
+pub fn max_data_slice(paths: &[PathBuf]) -> usize {
+    let mut max_data_slice = 0;
+    for path in paths {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                let io::ErrorKind::NotFound = err.kind() else {
+                    panic!("{:?}", err)
+                };
+                continue;
+            }
+        };
+        for entry in entries {
+            let name = entry.unwrap().file_name();
+            if let Some((data_slice, _, _)) = parse_chunk_name(&name.to_string_lossy()) {
+                max_data_slice = max_data_slice.max(data_slice);
+            }
+        }
+    }
+    max_data_slice
+}
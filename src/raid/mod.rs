@@ -1,15 +1,125 @@
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::raid::disk::Durability;
+use crate::raid::distributed::{Aggregation, Heartbeat, Repair};
+use crate::raid::layout::{Layout, Pool, Rotation};
+use crate::raid::transport::Peers;
 
 pub mod distributed;
 pub mod controller;
+pub mod bitmap;
+pub mod cluster;
+pub mod device;
+pub mod disk;
+pub mod layout;
+pub mod meta;
+pub mod parity;
+pub mod rebuild;
+pub mod reshape;
+pub mod transport;
+#[cfg(target_os = "linux")]
+pub mod uring;
+
+/// Settings of an array that are fixed when it is created.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub durability: Durability,
+    /// Standby device folders. A spare takes over the slot of a failed device.
+    pub spares: Vec<PathBuf>,
+    /// Limit for the traffic of a rebuild in bytes per second, chunks read plus written.
+    pub rebuild_bandwidth: Option<u64>,
+    /// Threads that rebuild a device in parallel, 0 uses one per core.
+    pub rebuild_workers: usize,
+    /// Placement of the chunks on the devices, fixed when the array is created.
+    pub layout: Arc<dyn Layout>,
+    /// Failure domain label of every device, empty if every device fails on its own.
+    /// Devices added by a reshape take the labels after the current ones. Labels must not
+    /// contain a comma.
+    pub domains: Vec<String>,
+    /// Relative capacity of every device, empty if they are all the same. Devices added by
+    /// a reshape take the weights after the current ones.
+    pub weights: Vec<u64>,
+    /// Addresses of the head node and the nodes of a `Checkpoint` whose nodes run in their
+    /// own processes, see `Node::serve`. None runs the nodes as threads of the head node.
+    pub peers: Option<Peers>,
+    /// How the head node of a `Checkpoint` notices nodes that stopped responding.
+    pub heartbeat: Heartbeat,
+    /// Bytes of chunks the head node of a `Checkpoint` lets queue at the nodes, the copies
+    /// for the checksum nodes included. Writes wait while it is used up, None never waits.
+    pub inflight_bytes: Option<u64>,
+    /// Time the head node of a `Checkpoint` waits for a chunk before it decodes it from the
+    /// other nodes.
+    pub read_timeout: Duration,
+    /// Latency percentile of the recent reads, for example 0.95, after which a read also
+    /// asks the other nodes and takes whatever completes first. None waits `read_timeout`.
+    pub hedge: Option<f64>,
+    /// How the data nodes of a `Checkpoint` get the checksums of a written slice to the
+    /// checksum nodes.
+    pub aggregation: Aggregation,
+    /// How a rebuilt node of a `Checkpoint` gets its chunks from the other nodes.
+    pub repair: Repair,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            durability: Durability::default(),
+            spares: vec![],
+            rebuild_bandwidth: None,
+            rebuild_workers: 0,
+            layout: Arc::new(Rotation),
+            domains: vec![],
+            weights: vec![],
+            peers: None,
+            heartbeat: Heartbeat::default(),
+            inflight_bytes: Some(256 << 20),
+            read_timeout: Duration::from_secs(5),
+            hedge: None,
+            aggregation: Aggregation::default(),
+            repair: Repair::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The layout for slices of `data` and `checks` chunks on the devices with their failure
+    /// domains and weights.
+    pub(crate) fn layout(
+        &self,
+        data: usize,
+        checks: usize,
+    ) -> Result<Arc<dyn Layout>, layout::Error> {
+        let devices = self.layout.devices(data, checks);
+        let pool = Pool::new(&self.domains, &self.weights, devices)?;
+        self.layout.for_pool(data, checks, &pool)
+    }
+
+    pub(crate) fn rebuild_workers(&self) -> usize {
+        match self.rebuild_workers {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            workers => workers,
+        }
+    }
+}
 
 pub trait RAID<const D: usize, const C: usize, const X: usize>: Sized {
-    fn new(root_path: PathBuf) -> Self;
+    fn new(root_path: PathBuf) -> Self {
+        Self::with_config(root_path, Config::default())
+    }
+    fn with_config(root_path: PathBuf, config: Config) -> Self;
+    /// Open an existing array and resync the slices that were being written
+    fn open(root_path: PathBuf, config: Config) -> Self;
     fn add_data(&mut self, data: &[&[u8; X]; D], data_slice: usize);
     fn add_data_at(&mut self, data: &[u8; X], data_slice: usize, data_idx: usize);
     fn read_data(&self, data_slice: usize) -> [Box<[u8; X]>; D];
     fn read_data_at(&self, data_slice: usize, data_idx: usize) -> Box<[u8; X]>;
     fn destroy_devices(&self, dev_idxs: &[usize]);
+    /// Copy the device onto the empty folder `path` while it keeps serving, then switch
+    /// the slot over. Chunks that can not be read are reconstructed from the other devices.
+    /// A copy to the same folder that was interrupted by a crash continues where it stopped.
+    fn replace_device(&self, dev_idx: usize, path: PathBuf);
     fn ping(&self) {}
     fn update_data(&self, data: &[u8; X], data_slice: usize, data_idx: usize);
     fn shutdown(self) {}
@@ -0,0 +1,326 @@
+//! Change of the number of checksum devices from `C` to `C2`.
+//!
+//! The checksums of every slice are computed again with the Reed-Solomon matrix for `C2`
+//! checksums and the chunks are moved to the folders of the new geometry. Slices before the
+//! marker in `root/parity` are in the new geometry. A slice is written to its new folders
+//! and the marker is stored before its old chunks are removed, a slice that was half moved
+//! by a crash is read from the chunks in its new folders first and moved again. A write
+//! sets the bit of its slice in `root/bitmap` like a write to the `Controller`, the open
+//! resyncs it in the geometry of the slice.
+//!
+//! The change works on the folders of a closed array, the data stays accessible through
+//! `ParityChange` until `finish` opens the array again, in the `Controller` or in a
+//! `Checkpoint` with its nodes in the head node. Nodes that run in their own processes keep
+//! their own folders and are refused. A chunk only moves if the layout puts it on another
+//! device with the new number of checksums. A `Dedicated` layout keeps the data and the
+//! checksums that are already there in place and only writes the new checksums, the
+//! rotating layouts move nearly every chunk.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::galois;
+use crate::galois::Galois;
+use crate::raid::bitmap::Bitmap;
+use crate::raid::controller::Array;
+use crate::raid::device::Device;
+use crate::raid::disk;
+use crate::raid::disk::{Batch, Durability};
+use crate::raid::meta;
+use crate::raid::meta::Meta;
+use crate::raid::rebuild::Progress;
+use crate::raid::reshape::check_clean;
+use crate::raid::{Config, RAID};
+
+pub struct ParityChange<const D: usize, const C: usize, const C2: usize, const X: usize>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+    [(); C2 + D]:,
+    [(); D + C2]:,
+    [(); C2 + C2]:,
+{
+    root_path: PathBuf,
+    config: Config,
+    old: Array<D, C, X>,
+    new: Array<D, C2, X>,
+    // slices with a write in progress
+    bitmap: Bitmap,
+    slices: usize,
+    // slices before `next` are in the new geometry, held while a slice is moved or accessed
+    next: Mutex<usize>,
+    first: usize,
+    started: Instant,
+}
+
+fn marker_path(root_path: &Path) -> PathBuf {
+    root_path.join("parity")
+}
+
+/// A parity change was started and not finished yet.
+pub fn is_changing(root_path: &Path) -> bool {
+    marker_path(root_path).exists()
+}
+
+impl<const D: usize, const C: usize, const C2: usize, const X: usize> ParityChange<D, C, C2, X>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+    [(); C2 + D]:,
+    [(); D + C2]:,
+    [(); C2 + C2]:,
+{
+    /// Start changing the checksum devices of a closed array, or continue an interrupted
+    /// change. The array must not be degraded and its nodes must not run in other processes.
+    pub fn open(root_path: PathBuf, config: Config) -> Self {
+        if config.peers.is_some() {
+            panic!("a parity change needs the folders of every node")
+        }
+        let durability = config.durability;
+        let old_count = config.layout.devices(D, C);
+        let new_count = config.layout.devices(D, C2);
+        let paths: Vec<PathBuf> = (0..old_count.max(new_count))
+            .map(|i| root_path.join(format!("device{i}")))
+            .collect();
+        disk::remove_tmp_files(&root_path);
+        for path in &paths {
+            disk::remove_tmp_files(path);
+        }
+        let old_devices: Vec<_> = paths[..old_count]
+            .iter()
+            .map(|path| Device::new(path.clone(), durability))
+            .collect();
+        let new_devices: Vec<_> = paths[..new_count]
+            .iter()
+            .map(|path| Device::new(path.clone(), durability))
+            .collect();
+
+        let (slices, next) = if is_changing(&root_path) {
+            Self::load_marker(&root_path)
+        } else {
+            Meta::check::<D, C, X>(&root_path, &config).unwrap();
+            check_clean(&root_path, &old_devices, durability);
+            for device in new_devices.iter().skip(old_count) {
+                let _ = fs::remove_dir_all(&device.path);
+                fs::create_dir(&device.path).unwrap();
+                device.create();
+            }
+            let slices = meta::max_data_slice(&paths[..old_count]) + 1;
+            Self::store_marker(&root_path, slices, 0, durability);
+            (slices, 0)
+        };
+
+        let change = Self {
+            bitmap: Bitmap::open(&root_path.join("bitmap"), durability),
+            root_path,
+            old: Array::new(durability, config.layout(D, C).unwrap(), old_devices),
+            new: Array::new(durability, config.layout(D, C2).unwrap(), new_devices),
+            config,
+            slices,
+            next: Mutex::new(next),
+            first: next,
+            started: Instant::now(),
+        };
+        // the change ended but the marker was not removed yet
+        if Meta::load(&change.root_path).unwrap().checksum_devices == C2 {
+            *change.next.lock().unwrap() = slices;
+        }
+        // the crash came after the marker, before the old chunks were removed
+        if next > 0 {
+            change.remove_old(next - 1);
+        }
+        change.resync();
+        change
+    }
+
+    fn load_marker(root_path: &Path) -> (usize, usize) {
+        let content = fs::read_to_string(marker_path(root_path)).unwrap();
+        let (mut slices, mut next) = (0, 0);
+        for line in content.lines() {
+            let (key, value) = line.split_once('=').unwrap();
+            let value: usize = value.parse().unwrap();
+            match key {
+                "checksum_devices" if value == C2 => {}
+                "checksum_devices" => panic!("change to {value} checksum devices in progress"),
+                "slices" => slices = value,
+                "next" => next = value,
+                _ => panic!("unknown key {key}"),
+            }
+        }
+        (slices, next)
+    }
+
+    fn store_marker(root_path: &Path, slices: usize, next: usize, durability: Durability) {
+        let content = format!("checksum_devices={C2}\nslices={slices}\nnext={next}\n");
+        disk::write(marker_path(root_path), content.as_bytes(), durability);
+    }
+
+    // recompute the checksums of the slices whose write was interrupted
+    fn resync(&self) {
+        let next = self.next.lock().unwrap();
+        for data_slice in self.bitmap.set_slices() {
+            if data_slice < *next {
+                self.new.resync(data_slice);
+            } else {
+                self.old.resync(data_slice);
+            }
+            self.bitmap.clear(data_slice);
+        }
+    }
+
+    // a crash can leave the slice half moved, a chunk in the new folder is complete
+    fn read_moving(&self, data_slice: usize, data_idx: usize) -> Box<[Galois; X]> {
+        match disk::read_chunk(&self.new.data_file(data_slice, data_idx)) {
+            Ok(Some(chunk)) => galois::from_bytes(chunk),
+            _ => self.old.read_data_chunk(data_slice, data_idx),
+        }
+    }
+
+    // the chunk is at the same place in both geometries and has the same content
+    fn is_kept(&self, data_slice: usize, idx: usize) -> bool {
+        if idx < D {
+            self.new.data_file(data_slice, idx) == self.old.data_file(data_slice, idx)
+        } else {
+            let check_idx = idx - D;
+            check_idx < C
+                && self.new.checksum_file(data_slice, check_idx)
+                    == self.old.checksum_file(data_slice, check_idx)
+                && self.new.reed[check_idx] == self.old.reed[check_idx]
+        }
+    }
+
+    // write the chunks of the slice that are not already in place and store the marker after it
+    fn move_slice(&self, next: &mut usize, data: &[Box<[Galois; X]>; D]) {
+        let data_slice = *next;
+        let mut batch = Batch::new(self.config.durability);
+        for (data_idx, chunk) in data.iter().enumerate() {
+            if !self.is_kept(data_slice, data_idx) {
+                self.new
+                    .write_chunk(&mut batch, data_slice, data_idx, chunk);
+            }
+        }
+        for check_idx in 0..C2 {
+            if !self.is_kept(data_slice, D + check_idx) {
+                let checksum = self.new.reed.mul_vec_at(data, check_idx);
+                self.new
+                    .write_chunk(&mut batch, data_slice, D + check_idx, &checksum);
+            }
+        }
+        batch.commit();
+        *next += 1;
+        Self::store_marker(&self.root_path, self.slices, *next, self.config.durability);
+        self.remove_old(data_slice);
+    }
+
+    // remove the old chunks of a moved slice that are not at the same place in the new geometry
+    fn remove_old(&self, data_slice: usize) {
+        let kept: HashSet<PathBuf> = (0..D)
+            .map(|data_idx| self.new.data_file(data_slice, data_idx))
+            .chain((0..C2).map(|check_idx| self.new.checksum_file(data_slice, check_idx)))
+            .collect();
+        let old = (0..D)
+            .map(|data_idx| self.old.data_file(data_slice, data_idx))
+            .chain((0..C).map(|check_idx| self.old.checksum_file(data_slice, check_idx)));
+        for path in old {
+            if !kept.contains(&path) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Move the next slice into the new geometry. False once every slice is moved.
+    pub fn step(&self) -> bool {
+        let mut next = self.next.lock().unwrap();
+        let data_slice = *next;
+        if data_slice >= self.slices {
+            return false;
+        }
+        let data: [Box<[Galois; X]>; D] =
+            core::array::from_fn(|data_idx| self.read_moving(data_slice, data_idx));
+        self.move_slice(&mut next, &data);
+        true
+    }
+
+    /// Move every slice while the data stays accessible.
+    pub fn run(&self) {
+        while self.step() {}
+    }
+
+    pub fn progress(&self) -> Progress {
+        let next = *self.next.lock().unwrap();
+        let done_now = next - self.first;
+        let remaining = (done_now > 0).then(|| {
+            self.started
+                .elapsed()
+                .mul_f64((self.slices - next) as f64 / done_now as f64)
+        });
+        Progress {
+            done: next,
+            total: self.slices,
+            remaining,
+        }
+    }
+
+    pub fn read_data_at(&self, data_slice: usize, data_idx: usize) -> Box<[u8; X]> {
+        let next = self.next.lock().unwrap();
+        let chunk = if data_slice < *next {
+            self.new.read_data_chunk(data_slice, data_idx)
+        } else if data_slice == *next {
+            self.read_moving(data_slice, data_idx)
+        } else {
+            self.old.read_data_chunk(data_slice, data_idx)
+        };
+        galois::as_bytes(chunk)
+    }
+
+    pub fn update_data(&self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        if data_slice >= self.slices {
+            panic!("not allowed")
+        }
+        let mut next = self.next.lock().unwrap();
+        let data = galois::from_bytes_ref(data);
+        // the slice may be half moved, it is moved before the write
+        if data_slice == *next {
+            let slice: [Box<[Galois; X]>; D] =
+                core::array::from_fn(|i| self.read_moving(data_slice, i));
+            self.move_slice(&mut next, &slice);
+        }
+        if data_slice < *next {
+            let old = self.new.read_data_chunk(data_slice, data_idx);
+            self.bitmap.set(data_slice);
+            self.new.update_chunk(data_slice, data_idx, &old, data);
+            self.bitmap.clear(data_slice);
+        } else {
+            let old = self.old.read_data_chunk(data_slice, data_idx);
+            self.bitmap.set(data_slice);
+            self.old.update_chunk(data_slice, data_idx, &old, data);
+            self.bitmap.clear(data_slice);
+        }
+    }
+
+    /// Store the new geometry and open the array with `C2` checksum devices in any backend.
+    pub fn finish<R: RAID<D, C2, X>>(self) -> R {
+        self.run();
+        // devices that are not part of the new geometry
+        let old_count = self.config.layout.devices(D, C);
+        let new_count = self.config.layout.devices(D, C2);
+        for dev_idx in new_count..old_count {
+            let path = self.root_path.join(format!("device{dev_idx}"));
+            let _ = fs::remove_dir_all(&path);
+            let _ = fs::remove_file(&path);
+            for extension in ["generation", "dirty", "rebuild"] {
+                let _ = fs::remove_file(path.with_extension(extension));
+            }
+        }
+        Meta::new::<D, C2, X>(&self.config).store(&self.root_path, self.config.durability);
+        fs::remove_file(marker_path(&self.root_path)).unwrap();
+        R::open(self.root_path, self.config)
+    }
+}
@@ -0,0 +1,216 @@
+//! Rebuild jobs that can be watched, throttled, cancelled and resumed.
+//!
+//! The next slice to rebuild is stored next to the device folder in `deviceN.rebuild`.
+//! A rebuild that was cancelled or interrupted by a crash continues from there. A job that
+//! rebuilds several devices at once stores it next to each of them.
+
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::raid::disk;
+use crate::raid::disk::Durability;
+
+// number of rebuilt slices after which the checkpoint is written again
+pub(crate) const CHECKPOINT_INTERVAL: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// slices rebuilt, including the ones done before a restart
+    pub done: usize,
+    pub total: usize,
+    /// estimated time until the rebuild is done, None until the first slice is rebuilt
+    pub remaining: Option<Duration>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} slices", self.done, self.total)?;
+        if let Some(remaining) = self.remaining {
+            write!(f, ", {}s left", remaining.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Job {
+    checkpoint_paths: Vec<PathBuf>,
+    durability: Durability,
+    // bytes per second
+    bandwidth: Option<u64>,
+    // slice the job started at and the end of the rebuild
+    first: usize,
+    end: usize,
+    next: AtomicUsize,
+    stored: Mutex<usize>,
+    bytes: AtomicU64,
+    started: Instant,
+    cancelled: AtomicBool,
+}
+
+impl Job {
+    fn with_first(
+        device_paths: &[&Path],
+        first: usize,
+        end: usize,
+        durability: Durability,
+        bandwidth: Option<u64>,
+    ) -> Self {
+        Self {
+            checkpoint_paths: device_paths
+                .iter()
+                .map(|path| path.with_extension("rebuild"))
+                .collect(),
+            durability,
+            bandwidth,
+            first,
+            end,
+            next: AtomicUsize::new(first),
+            stored: Mutex::new(first),
+            bytes: AtomicU64::new(0),
+            started: Instant::now(),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    /// Rebuild the slices `0..end` of the devices from scratch.
+    pub fn new(
+        device_paths: &[&Path],
+        end: usize,
+        durability: Durability,
+        bandwidth: Option<u64>,
+    ) -> Self {
+        let job = Self::with_first(device_paths, 0, end, durability, bandwidth);
+        for path in &job.checkpoint_paths {
+            let _ = fs::remove_file(path);
+        }
+        job
+    }
+
+    /// Continue a rebuild of the slices `0..end` from the earliest checkpoint of the
+    /// devices. A missing or unreadable checkpoint starts from scratch.
+    pub fn resume(
+        device_paths: &[&Path],
+        end: usize,
+        durability: Durability,
+        bandwidth: Option<u64>,
+    ) -> Self {
+        let first = device_paths
+            .iter()
+            .map(|path| {
+                fs::read_to_string(path.with_extension("rebuild"))
+                    .ok()
+                    .and_then(|content| content.trim().parse().ok())
+                    .unwrap_or(0)
+            })
+            .min()
+            .unwrap_or(0);
+        Self::with_first(device_paths, first.min(end), end, durability, bandwidth)
+    }
+
+    /// Slices that are not rebuilt yet.
+    pub fn remaining(&self) -> Range<usize> {
+        self.next.load(Ordering::SeqCst)..self.end
+    }
+
+    pub fn progress(&self) -> Progress {
+        let next = self.next.load(Ordering::SeqCst);
+        let done_now = next - self.first;
+        let remaining = (done_now > 0).then(|| {
+            self.started
+                .elapsed()
+                .mul_f64((self.end - next) as f64 / done_now as f64)
+        });
+        Progress {
+            done: next,
+            total: self.end,
+            remaining,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// All slices before `data_slice` are rebuilt. Stores the checkpoint from time to time.
+    /// The checkpoint is removed at the end, before the device gets its generation.
+    pub fn rebuilt_until(&self, data_slice: usize) {
+        self.next.store(data_slice, Ordering::SeqCst);
+        let mut stored = self.stored.lock().unwrap();
+        if data_slice >= self.end {
+            for path in &self.checkpoint_paths {
+                let _ = fs::remove_file(path);
+            }
+        } else if data_slice >= *stored + CHECKPOINT_INTERVAL || self.is_cancelled() {
+            let content = format!("{data_slice}\n");
+            for path in &self.checkpoint_paths {
+                disk::write(path.clone(), content.as_bytes(), self.durability);
+            }
+            *stored = data_slice;
+        }
+    }
+
+    /// `bytes` were read and written for the rebuild. Sleeps to keep the bandwidth limit.
+    pub fn throttle(&self, bytes: u64) {
+        let bytes = self.bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        if let Some(bandwidth) = self.bandwidth {
+            let min_time = Duration::from_secs_f64(bytes as f64 / bandwidth as f64);
+            if let Some(wait) = min_time.checked_sub(self.started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// A rebuild of one or more devices running in its own thread.
+pub(crate) struct Running {
+    pub dev_idxs: Vec<usize>,
+    pub job: Arc<Job>,
+    pub handle: JoinHandle<()>,
+}
+
+/// Wait for the rebuilds of the device, or of all devices with `None`.
+pub(crate) fn join(running: &Mutex<Vec<Running>>, dev_idx: Option<usize>) {
+    let finished: Vec<_> = {
+        let mut running = running.lock().unwrap();
+        let (finished, rest) = running
+            .drain(..)
+            .partition(|r| dev_idx.is_none_or(|dev_idx| r.dev_idxs.contains(&dev_idx)));
+        *running = rest;
+        finished
+    };
+    for r in finished {
+        r.handle.join().unwrap();
+    }
+}
+
+pub(crate) fn cancel(running: &Mutex<Vec<Running>>, dev_idx: Option<usize>) {
+    for r in running.lock().unwrap().iter() {
+        if dev_idx.is_none_or(|dev_idx| r.dev_idxs.contains(&dev_idx)) {
+            r.job.cancel();
+        }
+    }
+    join(running, dev_idx);
+}
+
+pub(crate) fn progress(running: &Mutex<Vec<Running>>) -> Vec<(usize, Progress)> {
+    running
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|r| {
+            r.dev_idxs
+                .iter()
+                .map(|dev_idx| (*dev_idx, r.job.progress()))
+        })
+        .collect()
+}
@@ -0,0 +1,308 @@
+//! Online expansion of a `Controller` array from `D` to `D2` data devices.
+//!
+//! The data is seen as one sequence of chunks, chunk `n` is data chunk `n % D` of slice
+//! `n / D`. The reshape writes the new slices in order. A new slice only needs old slices
+//! with the same or a higher number, so old slice `s` can be overwritten by new slice `s`.
+//! Slices before the marker in `root/reshape` are in the new geometry, the others in the old.
+//! The data of the slice being moved is kept in `root/reshape.backup` until it is written,
+//! so a crash in between can be repaired on the next open. A write sets the bit of its slice
+//! in `root/bitmap` like a write to the `Controller`. Bits before the marker are slices of the
+//! new geometry, the others of the old one, the open resyncs them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::galois;
+use crate::galois::Galois;
+use crate::raid::bitmap::Bitmap;
+use crate::raid::controller::{Array, Controller};
+use crate::raid::device::Device;
+use crate::raid::disk;
+use crate::raid::disk::Durability;
+use crate::raid::meta;
+use crate::raid::meta::Meta;
+use crate::raid::rebuild::Progress;
+use crate::raid::{Config, RAID};
+
+pub struct Reshape<const D: usize, const D2: usize, const C: usize, const X: usize>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+    [(); C + D2]:,
+    [(); D2 + C]:,
+    [(); D2 + D2]:,
+{
+    root_path: PathBuf,
+    config: Config,
+    old: Array<D, C, X>,
+    new: Array<D2, C, X>,
+    // slices with a write in progress
+    bitmap: Bitmap,
+    // number of slices in the old and the new geometry
+    old_slices: usize,
+    new_slices: usize,
+    // slices before `next` are in the new geometry, held while a slice is moved or accessed
+    next: Mutex<usize>,
+    first: usize,
+    started: Instant,
+}
+
+fn marker_path(root_path: &Path) -> PathBuf {
+    root_path.join("reshape")
+}
+
+fn backup_path(root_path: &Path) -> PathBuf {
+    root_path.join("reshape.backup")
+}
+
+/// A reshape was started and not finished yet.
+pub fn is_reshaping(root_path: &Path) -> bool {
+    marker_path(root_path).exists()
+}
+
+// a change of the geometry needs every chunk of the array
+pub(crate) fn check_clean(root_path: &Path, devices: &[Device], durability: Durability) {
+    if devices.iter().any(|device| !device.is_current()) {
+        panic!("array is degraded")
+    }
+    if !Bitmap::open(&root_path.join("bitmap"), durability)
+        .set_slices()
+        .is_empty()
+    {
+        panic!("array has dirty slices")
+    }
+}
+
+impl<const D: usize, const D2: usize, const C: usize, const X: usize> Reshape<D, D2, C, X>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+    [(); C + D2]:,
+    [(); D2 + C]:,
+    [(); D2 + D2]:,
+{
+    /// Start adding `D2 - D` data devices to a closed array, or continue an interrupted
+    /// reshape. The array must not be degraded.
+    pub fn open(root_path: PathBuf, config: Config) -> Self {
+        if D2 <= D {
+            panic!("a reshape needs more data devices")
+        }
+        let durability = config.durability;
+        let old_count = config.layout.devices(D, C);
+        let new_count = config.layout.devices(D2, C);
+        let paths: Vec<PathBuf> = (0..old_count.max(new_count))
+            .map(|i| root_path.join(format!("device{i}")))
+            .collect();
+        disk::remove_tmp_files(&root_path);
+        for path in &paths {
+            disk::remove_tmp_files(path);
+        }
+        let old_devices: Vec<_> = paths[..old_count]
+            .iter()
+            .map(|path| Device::new(path.clone(), durability))
+            .collect();
+        let new_devices: Vec<_> = paths[..new_count]
+            .iter()
+            .map(|path| Device::new(path.clone(), durability))
+            .collect();
+
+        let (old_slices, next) = if is_reshaping(&root_path) {
+            Self::load_marker(&root_path)
+        } else {
+            Meta::check::<D, C, X>(&root_path, &config).unwrap();
+            check_clean(&root_path, &old_devices, durability);
+            for device in new_devices.iter().skip(old_count) {
+                let _ = fs::remove_dir_all(&device.path);
+                fs::create_dir(&device.path).unwrap();
+                device.create();
+            }
+            let old_slices = meta::max_data_slice(&paths[..old_count]) + 1;
+            Self::store_marker(&root_path, old_slices, 0, durability);
+            (old_slices, 0)
+        };
+
+        let reshape = Self {
+            bitmap: Bitmap::open(&root_path.join("bitmap"), durability),
+            root_path,
+            old: Array::new(durability, config.layout(D, C).unwrap(), old_devices),
+            new: Array::new(durability, config.layout(D2, C).unwrap(), new_devices),
+            config,
+            old_slices,
+            new_slices: (old_slices * D).div_ceil(D2),
+            next: Mutex::new(next),
+            first: next,
+            started: Instant::now(),
+        };
+        // the reshape ended but the marker was not removed yet
+        if Meta::load(&reshape.root_path).unwrap().data_devices == D2 {
+            *reshape.next.lock().unwrap() = reshape.new_slices;
+        }
+        reshape.repair();
+        reshape.resync();
+        reshape
+    }
+
+    fn load_marker(root_path: &Path) -> (usize, usize) {
+        let content = fs::read_to_string(marker_path(root_path)).unwrap();
+        let (mut old_slices, mut next) = (0, 0);
+        for line in content.lines() {
+            let (key, value) = line.split_once('=').unwrap();
+            let value: usize = value.parse().unwrap();
+            match key {
+                "data_devices" if value == D2 => {}
+                "data_devices" => panic!("reshape to {value} data devices in progress"),
+                "old_slices" => old_slices = value,
+                "next" => next = value,
+                _ => panic!("unknown key {key}"),
+            }
+        }
+        (old_slices, next)
+    }
+
+    fn store_marker(root_path: &Path, old_slices: usize, next: usize, durability: Durability) {
+        let content = format!("data_devices={D2}\nold_slices={old_slices}\nnext={next}\n");
+        disk::write(marker_path(root_path), content.as_bytes(), durability);
+    }
+
+    // finish moving the slice of the backup if the crash happened while it was written
+    fn repair(&self) {
+        let Ok(backup) = fs::read(backup_path(&self.root_path)) else {
+            return;
+        };
+        let (data_slice, data) = backup.split_at(8);
+        let data_slice = usize::from_le_bytes(data_slice.try_into().unwrap());
+        let mut next = self.next.lock().unwrap();
+        if data_slice == *next {
+            let data: [Box<[Galois; X]>; D2] = core::array::from_fn(|i| {
+                galois::from_bytes(data[i * X..(i + 1) * X].to_vec().try_into().unwrap())
+            });
+            self.move_slice(data_slice, &data);
+            *next += 1;
+        }
+    }
+
+    // recompute the checksums of the slices whose write was interrupted
+    fn resync(&self) {
+        let next = self.next.lock().unwrap();
+        for data_slice in self.bitmap.set_slices() {
+            if data_slice < *next {
+                self.new.resync(data_slice);
+            } else {
+                self.old.resync(data_slice);
+            }
+            self.bitmap.clear(data_slice);
+        }
+    }
+
+    // chunk `chunk` of the data, from the geometry it is currently stored in
+    fn read(&self, next: usize, chunk: usize) -> Box<[Galois; X]> {
+        if chunk / D2 < next {
+            self.new.read_data_chunk(chunk / D2, chunk % D2)
+        } else {
+            self.old.read_data_chunk(chunk / D, chunk % D)
+        }
+    }
+
+    fn move_slice(&self, data_slice: usize, data: &[Box<[Galois; X]>; D2]) {
+        self.old.remove_slice(data_slice);
+        self.new.write_slice(data_slice, data);
+        Self::store_marker(
+            &self.root_path,
+            self.old_slices,
+            data_slice + 1,
+            self.config.durability,
+        );
+    }
+
+    /// Move the next slice into the new geometry. False once every slice is moved.
+    pub fn step(&self) -> bool {
+        let mut next = self.next.lock().unwrap();
+        let data_slice = *next;
+        if data_slice >= self.new_slices {
+            return false;
+        }
+        let data: [Box<[Galois; X]>; D2] =
+            core::array::from_fn(|i| self.read(data_slice, data_slice * D2 + i));
+
+        let mut backup = data_slice.to_le_bytes().to_vec();
+        for chunk in &data {
+            backup.extend_from_slice(galois::as_bytes_ref(chunk));
+        }
+        disk::write(
+            backup_path(&self.root_path),
+            &backup,
+            self.config.durability,
+        );
+        self.move_slice(data_slice, &data);
+        *next += 1;
+        true
+    }
+
+    /// Move every slice while the data stays accessible with `read_chunk` and `write_chunk`.
+    pub fn run(&self) {
+        while self.step() {}
+    }
+
+    pub fn progress(&self) -> Progress {
+        let next = *self.next.lock().unwrap();
+        let done_now = next - self.first;
+        let remaining = (done_now > 0).then(|| {
+            self.started
+                .elapsed()
+                .mul_f64((self.new_slices - next) as f64 / done_now as f64)
+        });
+        Progress {
+            done: next,
+            total: self.new_slices,
+            remaining,
+        }
+    }
+
+    /// Read chunk `chunk` of the data, `chunk / D` is its slice in the old geometry and
+    /// `chunk / D2` in the new one.
+    pub fn read_chunk(&self, chunk: usize) -> Box<[u8; X]> {
+        let next = self.next.lock().unwrap();
+        galois::as_bytes(self.read(*next, chunk))
+    }
+
+    pub fn write_chunk(&self, chunk: usize, data: &[u8; X]) {
+        if chunk >= self.new_slices * D2 {
+            panic!("not allowed")
+        }
+        let next = self.next.lock().unwrap();
+        let data = galois::from_bytes_ref(data);
+        if chunk / D2 < *next {
+            let (data_slice, data_idx) = (chunk / D2, chunk % D2);
+            let old = self.new.read_data_chunk(data_slice, data_idx);
+            self.bitmap.set(data_slice);
+            self.new.update_chunk(data_slice, data_idx, &old, data);
+            self.bitmap.clear(data_slice);
+        } else {
+            let (data_slice, data_idx) = (chunk / D, chunk % D);
+            let old = self.old.read_data_chunk(data_slice, data_idx);
+            self.bitmap.set(data_slice);
+            self.old.update_chunk(data_slice, data_idx, &old, data);
+            self.bitmap.clear(data_slice);
+        }
+    }
+
+    /// Store the new geometry and open the array with `D2` data devices.
+    pub fn finish(self) -> Controller<D2, C, X> {
+        self.run();
+        // old slices past the end of the new geometry
+        let old_end = (self.new_slices * D2).div_ceil(D).max(self.old_slices);
+        for data_slice in self.new_slices..old_end {
+            self.old.remove_slice(data_slice);
+        }
+        Meta::new::<D2, C, X>(&self.config).store(&self.root_path, self.config.durability);
+        let _ = fs::remove_file(backup_path(&self.root_path));
+        fs::remove_file(marker_path(&self.root_path)).unwrap();
+        Controller::open(self.root_path, self.config)
+    }
+}
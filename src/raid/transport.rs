@@ -0,0 +1,1279 @@
+//! How the head node and the nodes reach each other. `Channels` keeps every node in the
+//! process of the head node, `Tcp` reaches nodes that run in their own processes.
+//!
+//! A TCP link starts with a hello that holds the wire `VERSION` and the id of the link. The
+//! receiver answers with the last frame it delivered for that id, so after a reconnect the
+//! sender only resends what is missing. Every frame has a sequence number and is
+//! acknowledged once it is queued at the receiver. Frames are delivered once and in order,
+//! and `sync` waits for the acknowledgements.
+//!
+//! Heartbeats have a link of their own to every node, so they do not queue behind chunks.
+//! The loop of the node answers them, a node that is stuck misses them although its
+//! process still acknowledges the frames.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+
+use crate::galois;
+use crate::galois::Galois;
+use crate::raid::device;
+use crate::raid::distributed::{
+    Error, Msg, NodeState, RecoverMsg, Repair, Reply, RequestId, Status,
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Version of the wire encoding, both ends of a link must have the same.
+pub const VERSION: u8 = 4;
+
+const MAGIC: [u8; 4] = *b"raid";
+
+// first wait before a reconnect, doubled up to `MAX_RETRY`
+const RETRY: Duration = Duration::from_millis(10);
+const MAX_RETRY: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends the messages of one process to the nodes and the replies to the head node.
+pub trait Transport<const X: usize>: Send + Sync {
+    /// Number of nodes.
+    fn nodes(&self) -> usize;
+    fn send(&self, dev_idx: usize, msg: Msg<X>) -> Result<()>;
+    fn send_recover(&self, dev_idx: usize, msg: RecoverMsg<X>) -> Result<()>;
+    /// Answer a request of the head node.
+    fn reply(&self, reply: Reply<X>) -> Result<()>;
+    /// Block until every message sent so far is queued at its receiver or the receiver is
+    /// unreachable.
+    fn sync(&self);
+    /// Send a `Msg::Heartbeat` to the node, the node answers it like a request.
+    fn heartbeat(&self, dev_idx: usize, request: RequestId) -> Result<()>;
+    /// Give the head node back the `Budget` of a chunk the node took off its queue.
+    fn credit(&self, dev_idx: usize, bytes: u64) -> Result<()>;
+}
+
+/// Bytes of chunks queued at every node, counted by the head node. Writes wait while the
+/// sum is over the limit, the nodes give the bytes back as they take the chunks off their
+/// queues.
+pub struct Budget {
+    limit: Option<u64>,
+    queued: Mutex<Vec<u64>>,
+    freed: Condvar,
+}
+
+impl Budget {
+    pub fn new(limit: Option<u64>, nodes: usize) -> Self {
+        Self {
+            limit,
+            queued: Mutex::new(vec![0; nodes]),
+            freed: Condvar::new(),
+        }
+    }
+
+    // a chunk larger than the limit fits when nothing is queued
+    fn fits_in(&self, queued: &[u64], bytes: u64) -> bool {
+        let sum: u64 = queued.iter().sum();
+        self.limit
+            .is_none_or(|limit| sum == 0 || sum + bytes <= limit)
+    }
+
+    /// True if `bytes` more can be queued without waiting.
+    pub fn fits(&self, bytes: u64) -> bool {
+        self.fits_in(&self.queued.lock().unwrap(), bytes)
+    }
+
+    /// Wait until a chunk of `bytes` fits for every node of `dev_idxs` and count it.
+    pub fn acquire(&self, dev_idxs: &[usize], bytes: u64) {
+        let total = bytes * dev_idxs.len() as u64;
+        let mut queued = self.queued.lock().unwrap();
+        while !self.fits_in(&queued, total) {
+            queued = self.freed.wait(queued).unwrap();
+        }
+        for dev_idx in dev_idxs {
+            queued[*dev_idx] += bytes;
+        }
+    }
+
+    pub fn release(&self, dev_idx: usize, bytes: u64) {
+        let mut queued = self.queued.lock().unwrap();
+        // credits of a node that was forgotten
+        queued[dev_idx] = queued[dev_idx].saturating_sub(bytes);
+        self.freed.notify_all();
+    }
+
+    /// Drop what is queued at a node that is down, it does not give it back.
+    pub fn forget(&self, dev_idx: usize) {
+        self.queued.lock().unwrap()[dev_idx] = 0;
+        self.freed.notify_all();
+    }
+}
+
+/// Requests of the head node that wait for their reply.
+#[derive(Default)]
+pub struct Requests<const X: usize> {
+    next: AtomicU64,
+    waiting: Mutex<HashMap<RequestId, Sender<Reply<X>>>>,
+}
+
+impl<const X: usize> Requests<X> {
+    /// A new request id and where its reply arrives.
+    pub fn start(&self) -> (RequestId, Receiver<Reply<X>>) {
+        let (send, rec) = bounded(1);
+        (self.start_on(send), rec)
+    }
+
+    /// A new request id whose reply goes to `send`, which can take the replies of several
+    /// requests.
+    pub fn start_on(&self, send: Sender<Reply<X>>) -> RequestId {
+        let request = self.next.fetch_add(1, Ordering::Relaxed);
+        self.waiting.lock().unwrap().insert(request, send);
+        request
+    }
+
+    /// Stop waiting for the reply of the request, it is dropped when it arrives.
+    pub fn cancel(&self, request: RequestId) {
+        self.waiting.lock().unwrap().remove(&request);
+    }
+
+    /// Hand the reply to its request. Replies nobody waits for are dropped.
+    pub fn complete(&self, reply: Reply<X>) {
+        if let Some(send) = self.waiting.lock().unwrap().remove(&reply.request()) {
+            let _ = send.send(reply);
+        }
+    }
+}
+
+/// Nodes that run as threads of the head node.
+pub struct Channels<const X: usize> {
+    coms: Vec<Sender<Msg<X>>>,
+    recover_coms: Vec<Sender<RecoverMsg<X>>>,
+    requests: Arc<Requests<X>>,
+    budget: Arc<Budget>,
+}
+
+impl<const X: usize> Channels<X> {
+    pub fn new(
+        coms: Vec<Sender<Msg<X>>>,
+        recover_coms: Vec<Sender<RecoverMsg<X>>>,
+        requests: Arc<Requests<X>>,
+        budget: Arc<Budget>,
+    ) -> Self {
+        Self {
+            coms,
+            recover_coms,
+            requests,
+            budget,
+        }
+    }
+}
+
+impl<const X: usize> Transport<X> for Channels<X> {
+    fn nodes(&self) -> usize {
+        self.coms.len()
+    }
+
+    fn send(&self, dev_idx: usize, msg: Msg<X>) -> Result<()> {
+        self.coms[dev_idx].send(msg)?;
+        Ok(())
+    }
+
+    fn send_recover(&self, dev_idx: usize, msg: RecoverMsg<X>) -> Result<()> {
+        self.recover_coms[dev_idx].send(msg)?;
+        Ok(())
+    }
+
+    fn reply(&self, reply: Reply<X>) -> Result<()> {
+        self.requests.complete(reply);
+        Ok(())
+    }
+
+    // a message is queued as soon as it is sent
+    fn sync(&self) {}
+
+    // a node thread that panicked or is stuck does not answer
+    fn heartbeat(&self, dev_idx: usize, request: RequestId) -> Result<()> {
+        self.send(dev_idx, Msg::Heartbeat { request })
+    }
+
+    fn credit(&self, dev_idx: usize, bytes: u64) -> Result<()> {
+        self.budget.release(dev_idx, bytes);
+        Ok(())
+    }
+}
+
+/// Addresses of the head node and of every node, when the nodes run in their own processes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peers {
+    pub head: SocketAddr,
+    pub nodes: Vec<SocketAddr>,
+}
+
+/// Nodes in other processes, reached over TCP. Links connect on their first message and
+/// reconnect until the `Tcp` is dropped.
+pub struct Tcp {
+    nodes: Vec<Link>,
+    beats: Vec<Link>,
+    head: Link,
+}
+
+impl Tcp {
+    pub fn new(peers: &Peers) -> Self {
+        Self {
+            nodes: peers.nodes.iter().map(|addr| Link::new(*addr)).collect(),
+            beats: peers.nodes.iter().map(|addr| Link::new(*addr)).collect(),
+            head: Link::new(peers.head),
+        }
+    }
+}
+
+impl<const X: usize> Transport<X> for Tcp {
+    fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn send(&self, dev_idx: usize, msg: Msg<X>) -> Result<()> {
+        self.nodes[dev_idx].send(encode(&Packet::Msg(msg)))?;
+        Ok(())
+    }
+
+    fn send_recover(&self, dev_idx: usize, msg: RecoverMsg<X>) -> Result<()> {
+        self.nodes[dev_idx].send(encode(&Packet::Recover(msg)))?;
+        Ok(())
+    }
+
+    fn reply(&self, reply: Reply<X>) -> Result<()> {
+        self.head.send(encode(&Packet::Reply(reply)))?;
+        Ok(())
+    }
+
+    fn sync(&self) {
+        for link in self.nodes.iter().chain([&self.head]) {
+            link.sync();
+        }
+    }
+
+    fn heartbeat(&self, dev_idx: usize, request: RequestId) -> Result<()> {
+        self.beats[dev_idx].send(encode(&Packet::<X>::Msg(Msg::Heartbeat { request })))?;
+        Ok(())
+    }
+
+    fn credit(&self, dev_idx: usize, bytes: u64) -> Result<()> {
+        self.head
+            .send(encode(&Packet::<X>::Credit { dev_idx, bytes }))?;
+        Ok(())
+    }
+}
+
+// sequence number and body
+type Frame = (u64, Vec<u8>);
+
+// highest sequence number the receiver of a link has queued and if the last connect failed
+#[derive(Default)]
+struct Acks {
+    state: Mutex<(u64, bool)>,
+    changed: Condvar,
+}
+
+impl Acks {
+    fn get(&self) -> u64 {
+        self.state.lock().unwrap().0
+    }
+
+    fn set(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        if seq > state.0 {
+            state.0 = seq;
+            self.changed.notify_all();
+        }
+    }
+
+    fn set_unreachable(&self, unreachable: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.1 != unreachable {
+            state.1 = unreachable;
+            self.changed.notify_all();
+        }
+    }
+
+    // until `seq` is queued at the receiver or the receiver is unreachable
+    fn wait(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        while state.0 < seq && !state.1 {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+// frames to one address. A thread writes them and resends the unacknowledged ones after a
+// reconnect
+struct Link {
+    // last sequence number handed out and the queue of the thread
+    queue: Mutex<(u64, Sender<Frame>)>,
+    acks: Arc<Acks>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Link {
+    fn new(addr: SocketAddr) -> Self {
+        let (send, frames) = unbounded();
+        let acks = Arc::new(Acks::default());
+        let closed = Arc::new(AtomicBool::new(false));
+        let id = rand::random();
+        {
+            let acks = acks.clone();
+            let closed = closed.clone();
+            std::thread::Builder::new()
+                .name(format!("link {addr}"))
+                .spawn(move || run_link(addr, id, frames, &acks, &closed))
+                .unwrap();
+        }
+        Self {
+            queue: Mutex::new((0, send)),
+            acks,
+            closed,
+        }
+    }
+
+    // the sequence number of the frame
+    fn send(&self, body: Vec<u8>) -> Result<u64> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.0 += 1;
+        let seq = queue.0;
+        queue.1.send((seq, body))?;
+        Ok(seq)
+    }
+
+    fn sync(&self) {
+        let seq = self.queue.lock().unwrap().0;
+        self.acks.wait(seq);
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        // the thread writes what is queued and stops retrying an unreachable address
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+// an open connection of a link
+struct Connection {
+    stream: BufWriter<TcpStream>,
+    // the acknowledgements stopped, frames that are not acknowledged are maybe lost
+    broken: Arc<AtomicBool>,
+    // highest sequence number written to this connection
+    written: u64,
+}
+
+impl Connection {
+    fn open(addr: SocketAddr, id: u64, acks: &Arc<Acks>) -> io::Result<Self> {
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        let mut hello = MAGIC.to_vec();
+        hello.push(VERSION);
+        hello.extend(id.to_le_bytes());
+        stream.write_all(&hello)?;
+        let mut reader = stream.try_clone()?;
+        // everything up to here was delivered on an earlier connection
+        acks.set(read_u64(&mut reader)?);
+
+        let broken = Arc::new(AtomicBool::new(false));
+        {
+            let acks = acks.clone();
+            let broken = broken.clone();
+            std::thread::Builder::new()
+                .name(format!("acks {addr}"))
+                .spawn(move || {
+                    while let Ok(seq) = read_u64(&mut reader) {
+                        acks.set(seq);
+                    }
+                    broken.store(true, Ordering::Relaxed);
+                })
+                .unwrap();
+        }
+        Ok(Self {
+            stream: BufWriter::new(stream),
+            broken,
+            written: 0,
+        })
+    }
+
+    // write the frames that are not yet on this connection
+    fn write(&mut self, frames: &VecDeque<Frame>) -> io::Result<()> {
+        for (seq, body) in frames {
+            if *seq <= self.written {
+                continue;
+            }
+            self.stream.write_all(&(body.len() as u32).to_le_bytes())?;
+            self.stream.write_all(&seq.to_le_bytes())?;
+            self.stream.write_all(body)?;
+            self.written = *seq;
+        }
+        self.stream.flush()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // ends the thread that reads the acknowledgements
+        let _ = self.stream.get_ref().shutdown(Shutdown::Both);
+    }
+}
+
+fn run_link(
+    addr: SocketAddr,
+    id: u64,
+    frames: Receiver<Frame>,
+    acks: &Arc<Acks>,
+    closed: &AtomicBool,
+) {
+    let mut unacked: VecDeque<Frame> = VecDeque::new();
+    let mut connection: Option<Connection> = None;
+    loop {
+        match frames.recv_timeout(MAX_RETRY) {
+            Ok(frame) => unacked.push_back(frame),
+            // see if a broken connection has to be replaced
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        let mut retry = RETRY;
+        loop {
+            let acked = acks.get();
+            while unacked.front().is_some_and(|(seq, _)| *seq <= acked) {
+                unacked.pop_front();
+            }
+            if connection
+                .as_ref()
+                .is_some_and(|c| c.broken.load(Ordering::Relaxed))
+            {
+                connection = None;
+                continue;
+            }
+            if unacked.is_empty() {
+                break;
+            }
+            let result = match connection.as_mut() {
+                Some(connection) => connection.write(&unacked),
+                None => match Connection::open(addr, id, acks) {
+                    // resend what the receiver does not have yet
+                    Ok(new) => {
+                        acks.set_unreachable(false);
+                        connection = Some(new);
+                        continue;
+                    }
+                    Err(err) => {
+                        acks.set_unreachable(true);
+                        Err(err)
+                    }
+                },
+            };
+            match result {
+                Ok(()) => break,
+                Err(_) => {
+                    connection = None;
+                    if closed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    std::thread::sleep(retry);
+                    retry = (retry * 2).min(MAX_RETRY);
+                }
+            }
+        }
+    }
+}
+
+/// Accepts the links of the other processes and hands every packet to `deliver`, once and
+/// in the order of its link. Stops accepting when dropped.
+pub struct Listener {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Listener {
+    pub fn bind<const X: usize>(
+        addr: SocketAddr,
+        deliver: impl Fn(Packet<X>) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            let deliver = Arc::new(deliver);
+            // last delivered sequence number by link id
+            let delivered = Arc::new(Mutex::new(HashMap::new()));
+            std::thread::Builder::new()
+                .name(format!("listener {addr}"))
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        if stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        let Ok(stream) = stream else {
+                            continue;
+                        };
+                        let deliver = deliver.clone();
+                        let delivered = delivered.clone();
+                        std::thread::spawn(move || {
+                            // the sender reconnects
+                            let _ = receive(stream, &delivered, &*deliver);
+                        });
+                    }
+                })
+                .unwrap()
+        };
+        Ok(Self {
+            addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The address the listener is bound to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // wakes up the accepting thread
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+fn receive<const X: usize>(
+    stream: TcpStream,
+    delivered: &Mutex<HashMap<u64, u64>>,
+    deliver: &dyn Fn(Packet<X>),
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut acks = stream.try_clone()?;
+    let mut stream = BufReader::new(stream);
+    let mut hello = [0; 13];
+    stream.read_exact(&mut hello)?;
+    if hello[..4] != MAGIC || hello[4] != VERSION {
+        return Err(invalid("unknown wire version"));
+    }
+    let id = u64::from_le_bytes(hello[5..].try_into().unwrap());
+    let last = *delivered.lock().unwrap().entry(id).or_insert(0);
+    acks.write_all(&last.to_le_bytes())?;
+
+    loop {
+        let mut header = [0; 12];
+        stream.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let seq = u64::from_le_bytes(header[4..].try_into().unwrap());
+        if len > X + 4096 {
+            return Err(invalid("frame too long"));
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body)?;
+        let packet = decode(&body)?;
+        // resent after a reconnect. The packet is delivered without the lock, the links of
+        // other senders go on meanwhile
+        let new = {
+            let mut delivered = delivered.lock().unwrap();
+            let last = delivered.get_mut(&id).unwrap();
+            let new = seq > *last;
+            *last = (*last).max(seq);
+            new
+        };
+        if new {
+            deliver(packet);
+        }
+        acks.write_all(&seq.to_le_bytes())?;
+    }
+}
+
+fn read_u64(stream: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    stream.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// What goes over a link.
+#[derive(Debug)]
+pub enum Packet<const X: usize> {
+    Msg(Msg<X>),
+    Recover(RecoverMsg<X>),
+    Reply(Reply<X>),
+    Credit { dev_idx: usize, bytes: u64 },
+}
+
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn range(&mut self, range: &Range<usize>) {
+        self.usize(range.start);
+        self.usize(range.end);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.0.extend(bytes);
+    }
+
+    fn chunk<const X: usize>(&mut self, chunk: &[Galois; X]) {
+        self.0.extend(galois::as_bytes_ref(chunk));
+    }
+
+    // part of a chunk, or coefficients
+    fn galois(&mut self, values: &[Galois]) {
+        self.bytes(galois::as_bytes_slice(values));
+    }
+
+    fn usizes(&mut self, values: &[usize]) {
+        self.usize(values.len());
+        for value in values {
+            self.usize(*value);
+        }
+    }
+
+    fn maybe_chunk<const X: usize>(&mut self, chunk: &Option<Box<[Galois; X]>>) {
+        self.bool(chunk.is_some());
+        if let Some(chunk) = chunk {
+            self.chunk(chunk);
+        }
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid("frame too short"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> io::Result<usize> {
+        self.u64()?
+            .try_into()
+            .map_err(|_| invalid("number too large"))
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("unknown bool")),
+        }
+    }
+
+    fn range(&mut self) -> io::Result<Range<usize>> {
+        Ok(self.usize()?..self.usize()?)
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    fn galois(&mut self) -> io::Result<Vec<Galois>> {
+        Ok(self
+            .bytes()?
+            .iter()
+            .map(|value| Galois::new(*value))
+            .collect())
+    }
+
+    fn usizes(&mut self) -> io::Result<Vec<usize>> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.usize()).collect()
+    }
+
+    fn chunk<const X: usize>(&mut self) -> io::Result<Box<[Galois; X]>> {
+        let bytes = self.take(X)?.to_vec().into_boxed_slice();
+        Ok(galois::from_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn maybe_chunk<const X: usize>(&mut self) -> io::Result<Option<Box<[Galois; X]>>> {
+        Ok(match self.bool()? {
+            true => Some(self.chunk()?),
+            false => None,
+        })
+    }
+}
+
+/// The body of a frame: a tag for the packet, a tag for its variant and the fields in order.
+/// Numbers are little endian `u64`, chunks are `X` bytes.
+pub fn encode<const X: usize>(packet: &Packet<X>) -> Vec<u8> {
+    let mut e = Encoder::default();
+    match packet {
+        Packet::Msg(msg) => {
+            e.u8(0);
+            encode_msg(&mut e, msg);
+        }
+        Packet::Recover(RecoverMsg::RequestedData {
+            data_slice,
+            data,
+            dev_idx,
+            round,
+        }) => {
+            e.u8(1);
+            e.u8(0);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.usize(*dev_idx);
+            e.u64(*round);
+        }
+        Packet::Recover(RecoverMsg::RepairedPiece {
+            data_slice,
+            round,
+            offset,
+            data,
+        }) => {
+            e.u8(1);
+            e.u8(1);
+            e.usize(*data_slice);
+            e.u64(*round);
+            e.usize(*offset);
+            e.galois(data);
+        }
+        Packet::Recover(RecoverMsg::RepairFailed { data_slice, round }) => {
+            e.u8(1);
+            e.u8(2);
+            e.usize(*data_slice);
+            e.u64(*round);
+        }
+        Packet::Reply(reply) => {
+            e.u8(2);
+            encode_reply(&mut e, reply);
+        }
+        Packet::Credit { dev_idx, bytes } => {
+            e.u8(4);
+            e.usize(*dev_idx);
+            e.u64(*bytes);
+        }
+    }
+    e.0
+}
+
+fn encode_msg<const X: usize>(e: &mut Encoder, msg: &Msg<X>) {
+    match msg {
+        Msg::NewData {
+            data_slice,
+            data,
+            version,
+        } => {
+            e.u8(0);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.u64(*version);
+        }
+        Msg::NewDataAt {
+            data_slice,
+            data,
+            version,
+            epoch,
+        } => {
+            e.u8(1);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.u64(*version);
+            e.u64(*epoch);
+        }
+        Msg::NewDataChecksum {
+            data_slice,
+            data,
+            dev_idx,
+            version,
+        } => {
+            e.u8(2);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.usize(*dev_idx);
+            e.u64(*version);
+        }
+        Msg::NewDataChecksumAt {
+            data_slice,
+            data,
+            dev_idx,
+            version,
+            epoch,
+        } => {
+            e.u8(3);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.usize(*dev_idx);
+            e.u64(*version);
+            e.u64(*epoch);
+        }
+        Msg::UpdateData {
+            data_slice,
+            data,
+            version,
+            epoch,
+        } => {
+            e.u8(4);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.u64(*version);
+            e.u64(*epoch);
+        }
+        Msg::UpdateDataChecksum {
+            data_slice,
+            diff,
+            dev_idx,
+            version,
+            epoch,
+        } => {
+            e.u8(5);
+            e.usize(*data_slice);
+            e.chunk(diff);
+            e.usize(*dev_idx);
+            e.u64(*version);
+            e.u64(*epoch);
+        }
+        Msg::NeedRecover {
+            data_slice,
+            dev_idx,
+            round,
+        } => {
+            e.u8(6);
+            e.usize(*data_slice);
+            e.usize(*dev_idx);
+            e.u64(*round);
+        }
+        Msg::HeadNodeDataRequest {
+            data_slice,
+            request,
+        } => {
+            e.u8(7);
+            e.usize(*data_slice);
+            e.u64(*request);
+        }
+        Msg::HeadNodeChunkRequest {
+            data_slice,
+            request,
+        } => {
+            e.u8(8);
+            e.usize(*data_slice);
+            e.u64(*request);
+        }
+        Msg::Reattach { request } => {
+            e.u8(9);
+            e.u64(*request);
+        }
+        Msg::DestroyStorage { data_slices } => {
+            e.u8(10);
+            e.range(data_slices);
+        }
+        Msg::Fail => e.u8(11),
+        Msg::Rebuild { data_slices } => {
+            e.u8(12);
+            e.range(data_slices);
+        }
+        Msg::Recover {
+            data_slices,
+            request,
+            repair,
+        } => {
+            e.u8(13);
+            e.range(data_slices);
+            e.u64(*request);
+            e.bool(*repair == Repair::Pipelined);
+        }
+        Msg::Replace { path } => {
+            e.u8(14);
+            e.bytes(path.to_str().unwrap().as_bytes());
+        }
+        Msg::Copy {
+            data_slices,
+            request,
+        } => {
+            e.u8(15);
+            e.range(data_slices);
+            e.u64(*request);
+        }
+        Msg::Replaced { request } => {
+            e.u8(16);
+            e.u64(*request);
+        }
+        Msg::Ping { request } => {
+            e.u8(17);
+            e.u64(*request);
+        }
+        Msg::Status { request } => {
+            e.u8(18);
+            e.u64(*request);
+        }
+        Msg::Shutdown => e.u8(19),
+        Msg::State { dev_idx, state } => {
+            e.u8(20);
+            e.usize(*dev_idx);
+            e.u8(match state {
+                NodeState::Healthy => 0,
+                NodeState::Rebuilding => 1,
+                NodeState::Failed => 2,
+                NodeState::Down => 3,
+            });
+        }
+        Msg::Heartbeat { request } => {
+            e.u8(21);
+            e.u64(*request);
+        }
+        Msg::NewDataAggregated {
+            data_slice,
+            data,
+            version,
+            fan_in,
+        } => {
+            e.u8(22);
+            e.usize(*data_slice);
+            e.chunk(data);
+            e.u64(*version);
+            e.usize(*fan_in);
+        }
+        Msg::PartialChecksum {
+            data_slice,
+            check_idx,
+            partial,
+            version,
+            fan_in,
+        } => {
+            e.u8(23);
+            e.usize(*data_slice);
+            e.usize(*check_idx);
+            e.chunk(partial);
+            e.u64(*version);
+            e.usize(*fan_in);
+        }
+        Msg::NewChecksum {
+            data_slice,
+            checksum,
+            version,
+        } => {
+            e.u8(24);
+            e.usize(*data_slice);
+            e.chunk(checksum);
+            e.u64(*version);
+        }
+        Msg::Repair {
+            data_slice,
+            dev_idx,
+            round,
+            helpers,
+            coefficients,
+        } => {
+            e.u8(25);
+            e.usize(*data_slice);
+            e.usize(*dev_idx);
+            e.u64(*round);
+            e.usizes(helpers);
+            e.galois(coefficients);
+        }
+        Msg::RepairPiece {
+            data_slice,
+            dev_idx,
+            round,
+            helpers,
+            coefficients,
+            offset,
+            sum,
+        } => {
+            e.u8(26);
+            e.usize(*data_slice);
+            e.usize(*dev_idx);
+            e.u64(*round);
+            e.usizes(helpers);
+            e.galois(coefficients);
+            e.usize(*offset);
+            e.galois(sum);
+        }
+    }
+}
+
+fn encode_reply<const X: usize>(e: &mut Encoder, reply: &Reply<X>) {
+    match reply {
+        Reply::Chunk { request, data } => {
+            e.u8(0);
+            e.u64(*request);
+            e.maybe_chunk(data);
+        }
+        Reply::Reattach { request, result } => {
+            e.u8(1);
+            e.u64(*request);
+            match result {
+                Ok(rebuilt) => {
+                    e.u8(0);
+                    e.usize(*rebuilt);
+                }
+                Err(device::Error::Absent) => e.u8(1),
+                Err(device::Error::Stale {
+                    generation,
+                    expected,
+                }) => {
+                    e.u8(2);
+                    e.bool(generation.is_some());
+                    e.u64(generation.unwrap_or(0));
+                    e.u64(*expected);
+                }
+            }
+        }
+        Reply::Status { request, status } => {
+            e.u8(2);
+            e.u64(*request);
+            e.bool(status.present);
+            e.bool(status.current);
+            e.usize(status.max_data_slice);
+            e.u64(status.version);
+        }
+        Reply::Done { request } => {
+            e.u8(3);
+            e.u64(*request);
+        }
+    }
+}
+
+/// Inverse of `encode`, refuses bodies that are cut off, too long or have unknown tags.
+pub fn decode<const X: usize>(body: &[u8]) -> io::Result<Packet<X>> {
+    let mut d = Decoder(body);
+    let packet = match d.u8()? {
+        0 => Packet::Msg(decode_msg(&mut d)?),
+        1 => match d.u8()? {
+            0 => Packet::Recover(RecoverMsg::RequestedData {
+                data_slice: d.usize()?,
+                data: d.chunk()?,
+                dev_idx: d.usize()?,
+                round: d.u64()?,
+            }),
+            1 => Packet::Recover(RecoverMsg::RepairedPiece {
+                data_slice: d.usize()?,
+                round: d.u64()?,
+                offset: d.usize()?,
+                data: d.galois()?,
+            }),
+            2 => Packet::Recover(RecoverMsg::RepairFailed {
+                data_slice: d.usize()?,
+                round: d.u64()?,
+            }),
+            _ => return Err(invalid("unknown recover message")),
+        },
+        2 => Packet::Reply(decode_reply(&mut d)?),
+        4 => Packet::Credit {
+            dev_idx: d.usize()?,
+            bytes: d.u64()?,
+        },
+        _ => return Err(invalid("unknown packet")),
+    };
+    if !d.0.is_empty() {
+        return Err(invalid("frame too long"));
+    }
+    Ok(packet)
+}
+
+fn decode_msg<const X: usize>(d: &mut Decoder) -> io::Result<Msg<X>> {
+    Ok(match d.u8()? {
+        0 => Msg::NewData {
+            data_slice: d.usize()?,
+            data: d.chunk()?,
+            version: d.u64()?,
+        },
+        1 => Msg::NewDataAt {
+            data_slice: d.usize()?,
+            data: d.chunk()?,
+            version: d.u64()?,
+            epoch: d.u64()?,
+        },
+        2 => Msg::NewDataChecksum {
+            data_slice: d.usize()?,
+            data: d.chunk()?,
+            dev_idx: d.usize()?,
+            version: d.u64()?,
+        },
+        3 => Msg::NewDataChecksumAt {
+            data_slice: d.usize()?,
+            data: d.chunk()?,
+            dev_idx: d.usize()?,
+            version: d.u64()?,
+            epoch: d.u64()?,
+        },
+        4 => Msg::UpdateData {
+            data_slice: d.usize()?,
+            data: d.chunk()?,
+            version: d.u64()?,
+            epoch: d.u64()?,
+        },
+        5 => Msg::UpdateDataChecksum {
+            data_slice: d.usize()?,
+            diff: d.chunk()?,
+            dev_idx: d.usize()?,
+            version: d.u64()?,
+            epoch: d.u64()?,
+        },
+        6 => Msg::NeedRecover {
+            data_slice: d.usize()?,
+            dev_idx: d.usize()?,
+            round: d.u64()?,
+        },
+        7 => Msg::HeadNodeDataRequest {
+            data_slice: d.usize()?,
+            request: d.u64()?,
+        },
+        8 => Msg::HeadNodeChunkRequest {
+            data_slice: d.usize()?,
+            request: d.u64()?,
+        },
+        9 => Msg::Reattach { request: d.u64()? },
+        10 => Msg::DestroyStorage {
+            data_slices: d.range()?,
+        },
+        11 => Msg::Fail,
+        12 => Msg::Rebuild {
+            data_slices: d.range()?,
+        },
+        13 => Msg::Recover {
+            data_slices: d.range()?,
+            request: d.u64()?,
+            repair: match d.bool()? {
+                true => Repair::Pipelined,
+                false => Repair::Direct,
+            },
+        },
+        14 => {
+            let path = std::str::from_utf8(d.bytes()?).map_err(|_| invalid("path is not utf-8"))?;
+            Msg::Replace {
+                path: PathBuf::from(path),
+            }
+        }
+        15 => Msg::Copy {
+            data_slices: d.range()?,
+            request: d.u64()?,
+        },
+        16 => Msg::Replaced { request: d.u64()? },
+        17 => Msg::Ping { request: d.u64()? },
+        18 => Msg::Status { request: d.u64()? },
+        19 => Msg::Shutdown,
+        20 => Msg::State {
+            dev_idx: d.usize()?,
+            state: match d.u8()? {
+                0 => NodeState::Healthy,
+                1 => NodeState::Rebuilding,
+                2 => NodeState::Failed,
+                3 => NodeState::Down,
+                _ => return Err(invalid("unknown node state")),
+            },
+        },
+        21 => Msg::Heartbeat { request: d.u64()? },
+        22 => Msg::NewDataAggregated {
+            data_slice: d.usize()?,
+            data: d.chunk()?,
+            version: d.u64()?,
+            fan_in: d.usize()?,
+        },
+        23 => Msg::PartialChecksum {
+            data_slice: d.usize()?,
+            check_idx: d.usize()?,
+            partial: d.chunk()?,
+            version: d.u64()?,
+            fan_in: d.usize()?,
+        },
+        24 => Msg::NewChecksum {
+            data_slice: d.usize()?,
+            checksum: d.chunk()?,
+            version: d.u64()?,
+        },
+        25 => Msg::Repair {
+            data_slice: d.usize()?,
+            dev_idx: d.usize()?,
+            round: d.u64()?,
+            helpers: d.usizes()?,
+            coefficients: d.galois()?,
+        },
+        26 => Msg::RepairPiece {
+            data_slice: d.usize()?,
+            dev_idx: d.usize()?,
+            round: d.u64()?,
+            helpers: d.usizes()?,
+            coefficients: d.galois()?,
+            offset: d.usize()?,
+            sum: d.galois()?,
+        },
+        _ => return Err(invalid("unknown message")),
+    })
+}
+
+fn decode_reply<const X: usize>(d: &mut Decoder) -> io::Result<Reply<X>> {
+    Ok(match d.u8()? {
+        0 => Reply::Chunk {
+            request: d.u64()?,
+            data: d.maybe_chunk()?,
+        },
+        1 => {
+            let request = d.u64()?;
+            let result = match d.u8()? {
+                0 => Ok(d.usize()?),
+                1 => Err(device::Error::Absent),
+                2 => {
+                    let known = d.bool()?;
+                    let generation = d.u64()?;
+                    Err(device::Error::Stale {
+                        generation: known.then_some(generation),
+                        expected: d.u64()?,
+                    })
+                }
+                _ => return Err(invalid("unknown reattach result")),
+            };
+            Reply::Reattach { request, result }
+        }
+        2 => Reply::Status {
+            request: d.u64()?,
+            status: Status {
+                present: d.bool()?,
+                current: d.bool()?,
+                max_data_slice: d.usize()?,
+                version: d.u64()?,
+            },
+        },
+        3 => Reply::Done { request: d.u64()? },
+        _ => return Err(invalid("unknown reply")),
+    })
+}
@@ -0,0 +1,372 @@
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use crate::galois;
+use crate::galois::Galois;
+use crate::raid::controller::Controller;
+use crate::raid::disk;
+use crate::raid::disk::Batch;
+use crate::raid::{Config, RAID};
+
+// one chunk I/O of chunk `idx` of the slice. A short completion is submitted again for the
+// bytes after `done`
+struct Op {
+    fd: RawFd,
+    buffer: *mut u8,
+    idx: usize,
+    done: usize,
+    error: Option<io::Error>,
+}
+
+impl Op {
+    fn new(fd: RawFd, buffer: *mut u8, idx: usize) -> Self {
+        Self {
+            fd,
+            buffer,
+            idx,
+            done: 0,
+            error: None,
+        }
+    }
+
+    fn entry<const X: usize>(&self, write: bool, op_idx: usize) -> squeue::Entry {
+        let fd = types::Fd(self.fd);
+        let buffer = unsafe { self.buffer.add(self.done) };
+        let len = (X - self.done) as u32;
+        let entry = if write {
+            opcode::Write::new(fd, buffer, len)
+                .offset64(self.done as i64)
+                .build()
+        } else {
+            opcode::Read::new(fd, buffer, len)
+                .offset64(self.done as i64)
+                .build()
+        };
+        entry.user_data(op_idx as u64)
+    }
+}
+
+// why the ops of a slice did not all succeed
+enum Failure {
+    // every op completed, the failed ones have their error
+    Ops,
+    // the ring failed, the kernel may still use the buffers and files of the ops
+    Ring,
+}
+
+// chunks of one slice that are still written by the kernel, by chunk idx. An op of a chunk
+// that goes to two folders writes from the same buffer
+struct InFlight<const X: usize> {
+    data_slice: usize,
+    paths: Vec<PathBuf>,
+    files: Vec<File>,
+    buffers: Vec<Box<[Galois; X]>>,
+    ops: Vec<Op>,
+}
+
+/// Same on disk layout as the `Controller`, but all chunk I/Os of a slice are submitted
+/// together with io_uring. The writes of a slice are only awaited in the next call, so the
+/// encoding of slice n+1 overlaps with the I/O of slice n. Chunks are written to temp files
+/// and renamed once the kernel is done, see `disk::Batch`. A slice whose I/O fails is read
+/// or written again by the `Controller`, a media error fails the device like there.
+pub struct UringController<const D: usize, const C: usize, const X: usize>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    controller: Controller<D, C, X>,
+    ring: RefCell<IoUring>,
+    in_flight: RefCell<Option<InFlight<X>>>,
+}
+
+impl<const D: usize, const C: usize, const X: usize> UringController<D, C, X>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    fn with_controller(controller: Controller<D, C, X>) -> Self {
+        Self {
+            controller,
+            ring: RefCell::new(Self::new_ring()),
+            in_flight: RefCell::new(None),
+        }
+    }
+
+    fn new_ring() -> IoUring {
+        // every chunk of a slice, twice while a device is replaced
+        let entries = (2 * (D + C)).next_power_of_two() as u32;
+        IoUring::new(entries).unwrap()
+    }
+
+    // the old ring may still complete ops into buffers that were given up
+    fn replace_ring(&self) {
+        std::mem::forget(self.ring.replace(Self::new_ring()));
+    }
+
+    // submit the ops and wait until all of them are complete. After an error of an op the
+    // others are still awaited, the kernel must be done with the buffers before they are
+    // dropped. A ring that fails for good leaves the ops that did not complete to the kernel
+    fn wait(ring: &mut IoUring, ops: &mut [Op], write: bool) -> Result<(), Failure> {
+        let mut pending = ops.len();
+        while pending > 0 {
+            let submitted = ring.submit_and_wait(1);
+            let completed: Vec<_> = ring
+                .completion()
+                .map(|cqe| (cqe.user_data() as usize, cqe.result()))
+                .collect();
+            for (op_idx, result) in completed {
+                let op = &mut ops[op_idx];
+                if result <= 0 {
+                    pending -= 1;
+                    op.error = Some(match result {
+                        0 if write => io::ErrorKind::WriteZero.into(),
+                        0 => io::ErrorKind::UnexpectedEof.into(),
+                        _ => io::Error::from_raw_os_error(-result),
+                    });
+                    continue;
+                }
+                op.done += result as usize;
+                if op.done < X {
+                    let entry = op.entry::<X>(write, op_idx);
+                    unsafe { ring.submission().push(&entry).unwrap() };
+                } else {
+                    pending -= 1;
+                }
+            }
+            match submitted {
+                // a signal, or the kernel is out of memory or completions for a moment
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // EAGAIN and EBUSY
+                Err(err) if matches!(err.raw_os_error(), Some(11 | 16)) => {}
+                Err(_) if pending > 0 => return Err(Failure::Ring),
+                _ => {}
+            }
+        }
+        match ops.iter().any(|op| op.error.is_some()) {
+            true => Err(Failure::Ops),
+            false => Ok(()),
+        }
+    }
+
+    // a media error of an op fails the device of its chunk
+    fn fail_devices(&self, data_slice: usize, ops: &[Op]) {
+        for op in ops {
+            if op.error.as_ref().is_some_and(disk::is_media_error) {
+                let array = &self.controller.array;
+                array.fail_device(array.folder_id(data_slice, op.idx));
+            }
+        }
+    }
+
+    // wait until the previous slice is on disk
+    fn flush(&self) {
+        let Some(mut in_flight) = self.in_flight.borrow_mut().take() else {
+            return;
+        };
+        let data_slice = in_flight.data_slice;
+        let result = Self::wait(&mut self.ring.borrow_mut(), &mut in_flight.ops, true);
+        if let Err(failure) = result {
+            for path in &in_flight.paths {
+                let _ = fs::remove_file(disk::tmp_path(path));
+            }
+            self.fail_devices(data_slice, &in_flight.ops);
+            // write the slice again without io_uring, the first D buffers are the data
+            let data: [Box<[Galois; X]>; D] =
+                core::array::from_fn(|i| galois::from_slice(&in_flight.buffers[i]));
+            if let Failure::Ring = failure {
+                // the kernel may still read them
+                std::mem::forget(in_flight.buffers);
+                std::mem::forget(in_flight.files);
+                self.replace_ring();
+            }
+            self.controller.handle_failures();
+            let _guard = self.controller.array.lock(data_slice);
+            self.controller.array.write_slice(data_slice, &data);
+            self.controller.bitmap.clear(data_slice);
+            return;
+        }
+        // the kernel is done with the buffers
+        drop(in_flight.buffers);
+        let _guard = self.controller.array.lock(data_slice);
+        let mut batch = Batch::new(self.controller.array.durability);
+        for (path, file) in in_flight.paths.into_iter().zip(in_flight.files) {
+            batch.written(path, file);
+        }
+        batch.commit();
+        self.controller.bitmap.clear(data_slice);
+    }
+
+    // write the chunks of the slice, by chunk idx, to their folders
+    fn submit_writes(&self, data_slice: usize, chunks: Vec<Box<[Galois; X]>>) {
+        let mut ring = self.ring.borrow_mut();
+        let mut paths = vec![];
+        let mut files = vec![];
+        let mut buffers = Vec::with_capacity(chunks.len());
+        let mut ops = vec![];
+        for (idx, mut buffer) in chunks.into_iter().enumerate() {
+            for file_path in self.controller.array.write_paths(data_slice, idx) {
+                let file = File::create(disk::tmp_path(&file_path)).unwrap();
+                let op = Op::new(file.as_raw_fd(), buffer.as_mut_ptr().cast(), idx);
+                unsafe {
+                    ring.submission()
+                        .push(&op.entry::<X>(true, ops.len()))
+                        .unwrap()
+                };
+                paths.push(file_path);
+                files.push(file);
+                ops.push(op);
+            }
+            buffers.push(buffer);
+        }
+        ring.submit().unwrap();
+        *self.in_flight.borrow_mut() = Some(InFlight {
+            data_slice,
+            paths,
+            files,
+            buffers,
+            ops,
+        });
+    }
+}
+
+impl<const D: usize, const C: usize, const X: usize> RAID<D, C, X> for UringController<D, C, X>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    fn with_config(root_path: PathBuf, config: Config) -> Self {
+        Self::with_controller(Controller::with_config(root_path, config))
+    }
+
+    fn open(root_path: PathBuf, config: Config) -> Self {
+        Self::with_controller(Controller::open(root_path, config))
+    }
+
+    fn add_data(&mut self, data: &[&[u8; X]; D], data_slice: usize) {
+        self.controller.handle_failures();
+        if self.controller.is_degraded() {
+            self.flush();
+            return self.controller.add_data(data, data_slice);
+        }
+        self.controller.max_data_slices = self.controller.max_data_slices.max(data_slice);
+        // encode while the previous slice is still written
+        let data: [Box<[Galois; X]>; D] = core::array::from_fn(|i| galois::from_slice_raw(data[i]));
+        let data_ref: [&[Galois; X]; D] = core::array::from_fn(|i| &*data[i]);
+        let checksum = self.controller.array.reed.mul_vec(&data_ref);
+
+        let chunks = data.into_iter().chain(checksum).collect();
+
+        self.flush();
+        self.controller.bitmap.set(data_slice);
+        self.submit_writes(data_slice, chunks);
+    }
+
+    fn add_data_at(&mut self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        self.flush();
+        self.controller.add_data_at(data, data_slice, data_idx)
+    }
+
+    fn read_data(&self, data_slice: usize) -> [Box<[u8; X]>; D] {
+        self.flush();
+        self.controller.handle_failures();
+        if self.controller.is_degraded() {
+            return self.controller.read_data(data_slice);
+        }
+        let mut ring = self.ring.borrow_mut();
+        let mut result: [Box<[u8; X]>; D] = core::array::from_fn(|_| galois::zeros_raw());
+        let mut files = Vec::with_capacity(D);
+        let mut ops = Vec::with_capacity(D);
+        for (data_idx, buffer) in result.iter_mut().enumerate() {
+            let file_path = self.controller.data_file(data_slice, data_idx);
+            let file = match File::open(&file_path) {
+                Ok(file) => file,
+                Err(err) => {
+                    let io::ErrorKind::NotFound = err.kind() else {
+                        panic!("{:?}", err)
+                    };
+                    if data_slice > self.controller.max_data_slices {
+                        panic!("not allowed")
+                    }
+                    continue;
+                }
+            };
+            let op = Op::new(file.as_raw_fd(), buffer.as_mut_ptr(), data_idx);
+            unsafe {
+                ring.submission()
+                    .push(&op.entry::<X>(false, ops.len()))
+                    .unwrap()
+            };
+            files.push(file);
+            ops.push(op);
+        }
+        match Self::wait(&mut ring, &mut ops, false) {
+            Ok(()) => result,
+            // a short chunk file or a failing device, the controller reads around it
+            Err(failure) => {
+                drop(ring);
+                self.fail_devices(data_slice, &ops);
+                if let Failure::Ring = failure {
+                    // the kernel may still write to them
+                    std::mem::forget(result);
+                    std::mem::forget(files);
+                    self.replace_ring();
+                }
+                self.controller.read_data(data_slice)
+            }
+        }
+    }
+
+    fn read_data_at(&self, data_slice: usize, data_idx: usize) -> Box<[u8; X]> {
+        self.flush();
+        self.controller.read_data_at(data_slice, data_idx)
+    }
+
+    fn destroy_devices(&self, dev_idxs: &[usize]) {
+        self.flush();
+        self.controller.destroy_devices(dev_idxs)
+    }
+
+    fn replace_device(&self, dev_idx: usize, path: PathBuf) {
+        self.flush();
+        self.controller.replace_device(dev_idx, path)
+    }
+
+    fn ping(&self) {
+        self.flush();
+    }
+
+    fn update_data(&self, data: &[u8; X], data_slice: usize, data_idx: usize) {
+        self.flush();
+        self.controller.update_data(data, data_slice, data_idx)
+    }
+
+    fn shutdown(self) {
+        self.flush();
+        self.controller.wait_for_rebuild();
+    }
+}
+
+impl<const D: usize, const C: usize, const X: usize> Drop for UringController<D, C, X>
+where
+    [(); C + D]:,
+    [(); D + C]:,
+    [(); C + C]:,
+    [(); D + D]:,
+{
+    // the kernel must not write from freed buffers
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
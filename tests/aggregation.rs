@@ -0,0 +1,63 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use raid::raid::distributed::{Aggregation, Checkpoint};
+use raid::raid::{Config, RAID};
+
+const D: usize = 6;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 24;
+
+// the checksum files of every device by their path below the root
+fn checksums(root: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut checksums = BTreeMap::new();
+    for dev_idx in 0..D + C {
+        let device = root.join(format!("device{dev_idx}"));
+        for entry in fs::read_dir(&device).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            if name.ends_with("c.bin") {
+                let content = fs::read(device.join(&name)).unwrap();
+                checksums.insert(format!("device{dev_idx}/{name}"), content);
+            }
+        }
+    }
+    checksums
+}
+
+// the chunks along the data nodes add up to the checksums every data node sends directly
+#[test]
+fn aggregated_checksums_equal_direct() {
+    let slices: Vec<[Box<[u8; X]>; D]> = (0..SLICES).map(|_| common::random_slice()).collect();
+    let updates: Vec<_> = (0..SLICES).map(|_| common::random_chunk::<X>()).collect();
+    let mut written = vec![];
+    for aggregation in [Aggregation::Direct, Aggregation::Chain, Aggregation::Tree] {
+        let root = common::root(&format!(
+            "aggregated_checksums_equal_direct_{aggregation:?}"
+        ));
+        let config = Config {
+            aggregation,
+            ..Config::default()
+        };
+        let mut raid = Checkpoint::<D, C, X>::with_config(root.clone(), config);
+        for (data_slice, slice) in slices.iter().enumerate() {
+            raid.add_data(&common::refs(slice), data_slice);
+        }
+        // updates build on the aggregated checksums
+        for (data_slice, update) in updates.iter().enumerate() {
+            raid.update_data(update, data_slice, data_slice % D);
+        }
+        raid.ping();
+        raid.shutdown();
+        written.push(checksums(&root));
+    }
+    assert_eq!(written[0].len(), SLICES * C);
+    assert!(written[1] == written[0], "chain");
+    assert!(written[2] == written[0], "tree");
+}
@@ -0,0 +1,103 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use raid::raid::cluster::Cluster;
+use raid::raid::distributed::{Checkpoint, Error};
+use raid::raid::transport::Budget;
+use raid::raid::RAID;
+
+const D: usize = 3;
+const C: usize = 2;
+const X: usize = 4096;
+
+// room for the chunks of one write of a slice
+const LIMIT: usize = D * (C + 1) * X;
+
+fn cluster_file(root: &Path, port: u16) -> PathBuf {
+    let nodes: Vec<_> = (1..=D + C)
+        .map(|i| format!("127.0.0.1:{}", port + i as u16))
+        .collect();
+    let path = root.join("cluster.conf");
+    let content = format!(
+        "data_devices={D}\nchecksum_devices={C}\nchunk_size={X}\nhead=127.0.0.1:{port}\n\
+         nodes={}\ninflight_bytes={LIMIT}\n",
+        nodes.join(",")
+    );
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn spawn(root: &Path, conf: &Path, slot: usize) -> Child {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_raid-node"));
+    command.arg("--config").arg(conf);
+    command.args(["--slot", &slot.to_string()]);
+    command
+        .arg("--storage")
+        .arg(root.join(format!("node{slot}")));
+    command.spawn().unwrap()
+}
+
+fn signal(node: &Child, signal: &str) {
+    let status = Command::new("kill")
+        .args([signal, &node.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn budget_is_given_back() {
+    let budget = Budget::new(Some(2 * X as u64), 2);
+    assert!(budget.fits(2 * X as u64));
+    budget.acquire(&[0, 1], X as u64);
+    assert!(!budget.fits(X as u64));
+    budget.release(0, X as u64);
+    assert!(budget.fits(X as u64));
+    assert!(!budget.fits(2 * X as u64));
+    // a node that is down gives nothing back
+    budget.forget(1);
+    assert!(budget.fits(2 * X as u64));
+}
+
+// a node that does not take its chunks off the queue holds the budget until it goes on
+#[test]
+fn full_budget_refuses_writes() {
+    let root = common::root("full_budget_refuses_writes");
+    let conf = cluster_file(&root, 27470);
+    let nodes: Vec<_> = (0..D + C).map(|i| spawn(&root, &conf, i)).collect();
+    let head = root.join("head");
+    fs::create_dir(&head).unwrap();
+    let config = Cluster::load(&conf).config();
+    let mut raid = Checkpoint::<D, C, X>::with_config(head, config);
+    let slices: Vec<[Box<[u8; X]>; D]> = (0..2).map(|_| common::random_slice()).collect();
+    raid.ping();
+
+    // node 0 has the first data chunk of slice 0, stopped for less than a heartbeat
+    signal(&nodes[0], "-STOP");
+    raid.add_data(&common::refs(&slices[0]), 0);
+    let result = raid.try_add_data(&common::refs(&slices[1]), 1);
+    assert!(matches!(result, Err(Error::Busy)));
+    signal(&nodes[0], "-CONT");
+
+    let start = Instant::now();
+    while let Err(Error::Busy) = raid.try_add_data(&common::refs(&slices[1]), 1) {
+        if start.elapsed() > Duration::from_secs(10) {
+            panic!("timed out waiting until the budget is given back")
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    raid.ping();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+    raid.shutdown();
+    for mut node in nodes {
+        assert!(node.wait().unwrap().success());
+    }
+}
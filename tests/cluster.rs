@@ -0,0 +1,137 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use raid::raid::cluster::Cluster;
+use raid::raid::distributed::{Checkpoint, NodeState};
+use raid::raid::RAID;
+
+const D: usize = 3;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 20;
+
+// heartbeats every 50 ms, a node is down after 3 missed ones
+fn cluster_file(root: &Path, port: u16) -> PathBuf {
+    let nodes: Vec<_> = (1..=D + C)
+        .map(|i| format!("127.0.0.1:{}", port + i as u16))
+        .collect();
+    let path = root.join("cluster.conf");
+    let content = format!(
+        "data_devices={D}\nchecksum_devices={C}\nchunk_size={X}\nhead=127.0.0.1:{port}\n\
+         nodes={}\nheartbeat_interval=50\nheartbeat_timeout=50\nsuspicion=3\n",
+        nodes.join(",")
+    );
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn spawn(root: &Path, conf: &Path, slot: usize, open: bool) -> Child {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_raid-node"));
+    command.arg("--config").arg(conf);
+    command.args(["--slot", &slot.to_string()]);
+    command
+        .arg("--storage")
+        .arg(root.join(format!("node{slot}")));
+    if open {
+        command.arg("--open");
+    }
+    command.spawn().unwrap()
+}
+
+fn wait_for(what: &str, condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        if start.elapsed() > Duration::from_secs(10) {
+            panic!("timed out waiting until {what}")
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn check(raid: &Checkpoint<D, C, X>, slices: &[[Box<[u8; X]>; D]]) {
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+}
+
+#[test]
+fn nodes_in_processes() {
+    let root = common::root("nodes_in_processes");
+    let conf = cluster_file(&root, 27400);
+    let mut nodes: Vec<_> = (0..D + C).map(|i| spawn(&root, &conf, i, false)).collect();
+    let head = root.join("head");
+    fs::create_dir(&head).unwrap();
+    let config = Cluster::load(&conf).config();
+    let mut raid = Checkpoint::<D, C, X>::with_config(head, config);
+
+    let mut slices: Vec<[Box<[u8; X]>; D]> = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+    check(&raid, &slices);
+
+    // reads and writes go around a node that is killed
+    nodes[1].kill().unwrap();
+    nodes[1].wait().unwrap();
+    wait_for("node 1 is down", || {
+        raid.node_states()[1] == NodeState::Down
+    });
+    check(&raid, &slices);
+    for data_slice in [2, 7, 11] {
+        slices[data_slice][1] = common::random_chunk();
+        raid.update_data(&slices[data_slice][1], data_slice, 1);
+    }
+    raid.ping();
+    check(&raid, &slices);
+
+    // a new process on the address reconnects and is rebuilt on the next access
+    nodes[1] = spawn(&root, &conf, 1, true);
+    wait_for("node 1 is rebuilt", || {
+        raid.read_data(0);
+        raid.wait_for_rebuild();
+        raid.node_states()[1] == NodeState::Healthy
+    });
+    // the slices can only be decoded with the rebuilt node
+    raid.destroy_devices(&[0, 2]);
+    raid.ping();
+    check(&raid, &slices);
+
+    raid.shutdown();
+    for mut node in nodes {
+        assert!(node.wait().unwrap().success());
+    }
+}
+
+// the stderr of a node that exits with an error
+fn fails(root: &Path, conf: &Path, slot: usize, settings: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_raid-node"))
+        .arg("--config")
+        .arg(conf)
+        .args(["--slot", &slot.to_string()])
+        .arg("--storage")
+        .arg(root.join("node"))
+        .args(settings)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn node_reports_bad_arguments() {
+    let root = common::root("node_reports_bad_arguments");
+    let conf = cluster_file(&root, 27410);
+    assert!(fails(&root, &conf, D + C, &[]).contains("slot 5 is not one of the 5 nodes"));
+    let stderr = fails(&root, &conf, 0, &["--data_devices", "5"]);
+    assert!(stderr.contains("not built for 5+2 devices with chunks of 4096 bytes"));
+    assert!(!stderr.contains("panicked"));
+    assert!(fails(&root, &conf, 0, &["--slot"]).starts_with("usage: raid-node"));
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use crossbeam_channel::{unbounded, Receiver};
+
+use raid::galois::Galois;
+use raid::raid::distributed::{Msg, Reply, RequestId};
+use raid::raid::transport::{Listener, Packet, Peers, Tcp, Transport};
+
+/// The test as the head node of `raid-node` processes. It sends the messages of the head
+/// node and of the other nodes, so a single node can be run on its own.
+pub struct Head<const X: usize> {
+    peers: Peers,
+    transport: Tcp,
+    replies: Receiver<Reply<X>>,
+    _listener: Listener,
+    next: RequestId,
+}
+
+impl<const X: usize> Head<X> {
+    /// The head node on `port`, the nodes on the ports after it.
+    pub fn bind(port: u16, nodes: usize) -> Self {
+        let peers = Peers {
+            head: format!("127.0.0.1:{port}").parse().unwrap(),
+            nodes: (1..=nodes)
+                .map(|i| format!("127.0.0.1:{}", port + i as u16).parse().unwrap())
+                .collect(),
+        };
+        let (send, replies) = unbounded();
+        let listener = Listener::bind::<X>(peers.head, move |packet| {
+            if let Packet::Reply(reply) = packet {
+                let _ = send.send(reply);
+            }
+        })
+        .unwrap();
+        Self {
+            transport: Tcp::new(&peers),
+            peers,
+            replies,
+            _listener: listener,
+            next: 0,
+        }
+    }
+
+    /// Run the node of `slot` in its own process, it keeps its chunks if `open`.
+    pub fn spawn(&self, root: &Path, data: usize, checks: usize, slot: usize, open: bool) -> Child {
+        let nodes: Vec<_> = self
+            .peers
+            .nodes
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_raid-node"));
+        command.args(["--data_devices", &data.to_string()]);
+        command.args(["--checksum_devices", &checks.to_string()]);
+        command.args(["--chunk_size", &X.to_string()]);
+        command.args(["--head", &self.peers.head.to_string()]);
+        command.args(["--nodes", &nodes.join(",")]);
+        command.args(["--slot", &slot.to_string()]);
+        command
+            .arg("--storage")
+            .arg(root.join(format!("node{slot}")));
+        if open {
+            command.arg("--open");
+        }
+        command.spawn().unwrap()
+    }
+
+    pub fn send(&self, slot: usize, msg: Msg<X>) {
+        Transport::<X>::send(&self.transport, slot, msg).unwrap();
+    }
+
+    /// The chunk the node of `slot` has of the slice, once it handled the messages before.
+    pub fn read(&mut self, slot: usize, data_slice: usize) -> Box<[Galois; X]> {
+        self.try_read(slot, data_slice).unwrap()
+    }
+
+    /// Like `read`, None if the node can not give its chunk.
+    pub fn try_read(&mut self, slot: usize, data_slice: usize) -> Option<Box<[Galois; X]>> {
+        self.next += 1;
+        let request = self.next;
+        self.send(
+            slot,
+            Msg::HeadNodeChunkRequest {
+                data_slice,
+                request,
+            },
+        );
+        match self.replies.recv_timeout(Duration::from_secs(10)).unwrap() {
+            Reply::Chunk { data, .. } => data,
+            reply => panic!("{reply:?}"),
+        }
+    }
+
+    /// Stop the node of `slot` and wait for its process.
+    pub fn shutdown(&self, slot: usize, mut node: Child) {
+        self.send(slot, Msg::Shutdown);
+        assert!(node.wait().unwrap().success());
+    }
+}
@@ -0,0 +1,31 @@
+#![allow(dead_code)]
+
+pub mod head;
+
+use std::path::PathBuf;
+
+use rand::RngCore;
+
+use raid::galois;
+
+/// Empty folder for the array of a test.
+pub fn root(name: &str) -> PathBuf {
+    let root = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+pub fn random_chunk<const X: usize>() -> Box<[u8; X]> {
+    let mut chunk = galois::zeros_raw::<X>();
+    rand::thread_rng().fill_bytes(chunk.as_mut_slice());
+    chunk
+}
+
+pub fn random_slice<const D: usize, const X: usize>() -> [Box<[u8; X]>; D] {
+    core::array::from_fn(|_| random_chunk())
+}
+
+pub fn refs<const D: usize, const X: usize>(slice: &[Box<[u8; X]>; D]) -> [&[u8; X]; D] {
+    core::array::from_fn(|i| &*slice[i])
+}
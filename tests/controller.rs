@@ -0,0 +1,61 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use raid::raid::bitmap::Bitmap;
+use raid::raid::controller::Controller;
+use raid::raid::disk::Durability;
+use raid::raid::meta;
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+#[test]
+fn open_degraded_and_dirty() {
+    let root = common::root("open_degraded_and_dirty");
+    let mut raid = Controller::<D, C, X>::new(root.clone());
+    for data_slice in 0..4 {
+        raid.add_data(&common::refs(&common::random_slice()), data_slice);
+    }
+    raid.shutdown();
+    // a crash during a write to slice 2, then a device is lost
+    Bitmap::open(&root.join("bitmap"), Durability::NoSync).set(2);
+    std::fs::remove_dir_all(root.join("device1")).unwrap();
+
+    let result = Controller::<D, C, X>::try_open(root.clone(), Config::default());
+    assert!(matches!(
+        result,
+        Err(meta::Error::DegradedDirty { dirty: 1 })
+    ));
+}
+
+#[test]
+fn open_with_other_geometry() {
+    let root = common::root("open_with_other_geometry");
+    Controller::<D, C, X>::new(root.clone()).shutdown();
+    let result = Controller::<D, 1, X>::try_open(root.clone(), Config::default());
+    assert!(matches!(result, Err(meta::Error::Mismatch { .. })));
+
+    std::fs::write(root.join("meta"), "data_devices=four\n").unwrap();
+    let result = Controller::<D, C, X>::try_open(root, Config::default());
+    assert!(matches!(result, Err(meta::Error::Corrupt(_))));
+}
+
+#[test]
+fn short_chunk_is_decoded() {
+    let root = common::root("short_chunk_is_decoded");
+    let mut raid = Controller::<D, C, X>::new(root.clone());
+    let slice = common::random_slice();
+    raid.add_data(&common::refs(&slice), 0);
+    // a torn write of data chunk 1
+    let file_path = root.join("device1").join("0_1d.bin");
+    std::fs::write(&file_path, &slice[1][..X / 2]).unwrap();
+
+    assert_eq!(raid.read_data(0), slice);
+    assert_eq!(raid.read_data_at(0, 1), slice[1]);
+    // the device stays in the array
+    assert!(root.join("device1").exists());
+    assert!(!root.join("device1.failed").exists());
+}
@@ -0,0 +1,97 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use raid::raid::controller::Controller;
+use raid::raid::disk;
+use raid::raid::disk::{Batch, Durability};
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 8;
+
+const POLICIES: [Durability; 3] = [
+    Durability::NoSync,
+    Durability::PerWrite,
+    Durability::GroupCommit,
+];
+
+// every chunk of every slice is a whole file, no temp file is left
+fn check_files(root: &Path) {
+    for dev_idx in 0..D + C {
+        let names: Vec<_> = fs::read_dir(root.join(format!("device{dev_idx}")))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".bin") || name.ends_with(".tmp"))
+            .collect();
+        assert_eq!(names.len(), SLICES, "device {dev_idx}: {names:?}");
+        for name in names {
+            assert!(name.ends_with(".bin"), "{name}");
+            let path = root.join(format!("device{dev_idx}")).join(name);
+            assert_eq!(fs::metadata(path).unwrap().len(), X as u64);
+        }
+    }
+}
+
+#[test]
+fn every_policy_writes_complete_files() {
+    for durability in POLICIES {
+        let root = common::root(&format!(
+            "every_policy_writes_complete_files_{durability:?}"
+        ));
+        let config = Config {
+            durability,
+            ..Config::default()
+        };
+        let mut raid = Controller::<D, C, X>::with_config(root.clone(), config.clone());
+        let mut slices: Vec<[Box<[u8; X]>; D]> =
+            (0..SLICES).map(|_| common::random_slice()).collect();
+        for (data_slice, slice) in slices.iter().enumerate() {
+            raid.add_data(&common::refs(slice), data_slice);
+        }
+        slices[3][1] = common::random_chunk();
+        raid.update_data(&slices[3][1], 3, 1);
+        raid.shutdown();
+        check_files(&root);
+
+        let raid = Controller::<D, C, X>::open(root, config);
+        raid.destroy_devices(&[0, 5]);
+        for (data_slice, slice) in slices.iter().enumerate() {
+            assert_eq!(
+                &raid.read_data(data_slice),
+                slice,
+                "{durability:?} slice {data_slice}"
+            );
+        }
+        raid.shutdown();
+    }
+}
+
+// a group commit renames its temp files on `commit`, the other policies right away
+#[test]
+fn batch_renames_temp_files() {
+    for durability in POLICIES {
+        let root = common::root(&format!("batch_renames_temp_files_{durability:?}"));
+        let paths: Vec<_> = (0..3).map(|i| root.join(format!("{i}.bin"))).collect();
+        let mut batch = Batch::new(durability);
+        for (i, path) in paths.iter().enumerate() {
+            batch.write(path.clone(), &[i as u8; 16]);
+        }
+        let renamed = durability != Durability::GroupCommit;
+        for path in &paths {
+            assert_eq!(path.exists(), renamed, "{durability:?}");
+            assert_eq!(disk::tmp_path(path).exists(), !renamed, "{durability:?}");
+        }
+        batch.commit();
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(fs::read(path).unwrap(), [i as u8; 16]);
+            assert!(!disk::tmp_path(path).exists());
+        }
+    }
+}
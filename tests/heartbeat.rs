@@ -0,0 +1,108 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use raid::raid::distributed::{Checkpoint, Heartbeat, NodeState};
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 16;
+
+type Slices = Vec<[Box<[u8; X]>; D]>;
+
+fn create(root: &Path, spares: Vec<PathBuf>) -> (Checkpoint<D, C, X>, Slices) {
+    let config = Config {
+        spares,
+        heartbeat: Heartbeat {
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_millis(20),
+            suspicion: 3,
+        },
+        ..Config::default()
+    };
+    let mut raid = Checkpoint::<D, C, X>::with_config(root.to_path_buf(), config);
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+    (raid, slices)
+}
+
+// the thread of node 0 panics on the update of a data chunk it can not store, a folder is in
+// the way of the file. Its channels still take messages. The slice and the data idx of the
+// chunk
+fn kill_node(root: &Path, raid: &Checkpoint<D, C, X>) -> (usize, usize) {
+    let name = fs::read_dir(root.join("device0"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .find(|name| name.ends_with("d.bin"))
+        .unwrap();
+    let (data_slice, data_idx) = name.trim_end_matches("d.bin").split_once('_').unwrap();
+    let (data_slice, data_idx) = (data_slice.parse().unwrap(), data_idx.parse().unwrap());
+    let file_path = root.join("device0").join(&name);
+    fs::remove_file(&file_path).unwrap();
+    fs::create_dir_all(file_path.join("in_the_way")).unwrap();
+    raid.update_data(&common::random_chunk(), data_slice, data_idx);
+    (data_slice, data_idx)
+}
+
+fn wait_for(what: &str, condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        if start.elapsed() > Duration::from_secs(10) {
+            panic!("timed out waiting until {what}")
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// the node loop answers the heartbeats, not the channels of the node
+#[test]
+fn dead_node_is_down() {
+    let root = common::root("dead_node_is_down");
+    let (raid, slices) = create(&root, vec![]);
+    let (lost, _) = kill_node(&root, &raid);
+    wait_for("node 0 is down", || {
+        raid.node_states()[0] == NodeState::Down
+    });
+    for (data_slice, slice) in slices.iter().enumerate() {
+        if data_slice != lost {
+            assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+        }
+    }
+    raid.shutdown();
+}
+
+#[test]
+fn spare_takes_over_dead_node() {
+    let root = common::root("spare_takes_over_dead_node");
+    let spare = root.join("spare0");
+    let (raid, mut slices) = create(&root, vec![spare.clone()]);
+    let (lost, data_idx) = kill_node(&root, &raid);
+    // the spare is promoted on the next access
+    wait_for("the spare is rebuilt", || {
+        raid.read_data(0);
+        raid.wait_for_rebuild();
+        raid.node_states()[0] == NodeState::Healthy
+    });
+    assert_eq!(fs::read_link(root.join("device0")).unwrap(), spare);
+    // the update the dead node never passed on
+    slices[lost][data_idx] = common::random_chunk();
+    raid.update_data(&slices[lost][data_idx], lost, data_idx);
+    raid.ping();
+    // the slices can only be decoded with the spare
+    raid.destroy_devices(&[1, 2]);
+    raid.ping();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+    raid.shutdown();
+}
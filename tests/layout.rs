@@ -0,0 +1,320 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::sync::Arc;
+
+use raid::raid::controller::Controller;
+use raid::raid::distributed::Checkpoint;
+use raid::raid::layout::{
+    Declustered, Dedicated, Error, Layout, LeftAsymmetric, LeftSymmetric, Pool, RightAsymmetric,
+    RightSymmetric, Rotation, Weighted,
+};
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+
+// the cached devices of a slice are the ones a fresh layout computes
+fn same_as_fresh(layout: &dyn Layout, fresh: impl Fn() -> Arc<dyn Layout>) {
+    let devices = layout.devices(D, C);
+    for _ in 0..2 {
+        for data_slice in 0..200 {
+            let fresh = fresh();
+            let mut seen = vec![false; devices];
+            for idx in 0..D + C {
+                let dev_idx = layout.device(D, C, data_slice, idx);
+                assert_eq!(dev_idx, fresh.device(D, C, data_slice, idx));
+                assert_eq!(layout.chunk(D, C, data_slice, dev_idx), Some(idx));
+                assert!(!seen[dev_idx], "slice {data_slice} uses {dev_idx} twice");
+                seen[dev_idx] = true;
+            }
+            for dev_idx in (0..devices).filter(|dev_idx| !seen[*dev_idx]) {
+                assert_eq!(layout.chunk(D, C, data_slice, dev_idx), None);
+            }
+        }
+    }
+}
+
+#[test]
+fn declustered_cache() {
+    let pool = Pool::new(&[], &[], 11).unwrap();
+    let layout = Declustered::new(11).for_pool(D, C, &pool).unwrap();
+    same_as_fresh(&*layout, || {
+        Declustered::new(11).for_pool(D, C, &pool).unwrap()
+    });
+}
+
+#[test]
+fn weighted_cache() {
+    let weights = [1, 2, 1, 2, 4, 1, 1, 2, 1];
+    let pool = Pool::new(&[], &weights, 9).unwrap();
+    let layout = Weighted::new(9).for_pool(D, C, &pool).unwrap();
+    same_as_fresh(&*layout, || Weighted::new(9).for_pool(D, C, &pool).unwrap());
+}
+
+// chunks of every device over the first slices
+fn chunks_per_device(layout: &dyn Layout, devices: usize, slices: usize) -> Vec<usize> {
+    let mut chunks = vec![0; devices];
+    for data_slice in 0..slices {
+        for idx in 0..D + C {
+            chunks[layout.device(D, C, data_slice, idx)] += 1;
+        }
+    }
+    chunks
+}
+
+// every device gets its weight's part of the chunks, a device can hold at most one chunk of
+// every slice
+#[test]
+fn weighted_follows_weights() {
+    const SLICES: usize = 20000;
+    let weights = [1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3];
+    let pool = Pool::new(&[], &weights, 12).unwrap();
+    let layout = Weighted::new(12).for_pool(D, C, &pool).unwrap();
+    let total: u64 = weights.iter().sum();
+    let chunks = chunks_per_device(&*layout, 12, SLICES);
+    for (dev_idx, &weight) in weights.iter().enumerate() {
+        let expected = (SLICES * (D + C)) as f64 * weight as f64 / total as f64;
+        let error = (chunks[dev_idx] as f64 - expected).abs() / expected;
+        assert!(
+            error < 0.05,
+            "device {dev_idx}: {} of {expected}",
+            chunks[dev_idx]
+        );
+    }
+
+    // the large device gets a chunk of every slice, the others share the rest
+    let weights = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 20];
+    let pool = Pool::new(&[], &weights, 11).unwrap();
+    let layout = Weighted::new(11).for_pool(D, C, &pool).unwrap();
+    let chunks = chunks_per_device(&*layout, 11, SLICES);
+    assert_eq!(chunks[10], SLICES);
+    let expected = (SLICES * (D + C - 1)) as f64 / 10.0;
+    for (dev_idx, &count) in chunks[..10].iter().enumerate() {
+        let error = (count as f64 - expected).abs() / expected;
+        assert!(error < 0.05, "device {dev_idx}: {count} of {expected}");
+    }
+}
+
+fn labels(labels: &str) -> Vec<String> {
+    labels.split(',').map(str::to_string).collect()
+}
+
+// no domain holds more chunks of a slice than there are checksums
+fn check_domains(layout: &dyn Layout, pool: &Pool) {
+    for data_slice in 0..500 {
+        let mut chunks = vec![0; pool.domains.len()];
+        for idx in 0..D + C {
+            let dev_idx = layout.device(D, C, data_slice, idx);
+            chunks[pool.domains[dev_idx]] += 1;
+        }
+        assert!(
+            chunks.iter().all(|&n| n <= C),
+            "slice {data_slice}: {chunks:?}"
+        );
+    }
+}
+
+#[test]
+fn domains_hold_at_most_checks_chunks() {
+    let pool = Pool::new(&labels("a,a,b,b,c,c"), &[], 6).unwrap();
+    check_domains(&*Rotation.for_pool(D, C, &pool).unwrap(), &pool);
+
+    let pool = Pool::new(&labels("a,a,a,b,b,b,c,c,c,d,d,d"), &[], 12).unwrap();
+    check_domains(&*Declustered::new(12).for_pool(D, C, &pool).unwrap(), &pool);
+    let weights = [1, 2, 4, 1, 1, 1, 2, 2, 1, 4, 1, 1];
+    let pool = Pool::new(&labels("a,a,a,b,b,b,c,c,c,d,d,d"), &weights, 12).unwrap();
+    check_domains(&*Weighted::new(12).for_pool(D, C, &pool).unwrap(), &pool);
+}
+
+#[test]
+fn pool_refuses_bad_domains_and_weights() {
+    let result = Pool::new(&labels("a,b"), &[], 6);
+    assert_eq!(
+        result,
+        Err(Error::Domains {
+            labels: 2,
+            devices: 6
+        })
+    );
+    let result = Pool::new(&[], &[1, 1, 1], 6);
+    assert_eq!(
+        result,
+        Err(Error::Weights {
+            weights: 3,
+            devices: 6
+        })
+    );
+    let result = Pool::new(&[], &[1, 1, 0, 1, 1, 1], 6);
+    assert_eq!(result, Err(Error::ZeroWeight));
+
+    // three chunks of every slice in one domain
+    let pool = Pool::new(&labels("a,a,a,b,b,b"), &[], 6).unwrap();
+    let result = Rotation.for_pool(D, C, &pool).map(|_| ());
+    assert_eq!(result, Err(Error::DomainsDoNotFit { max: C }));
+    // six chunks of a slice, at most two in each of two domains
+    let pool = Pool::new(&labels("a,a,a,a,a,b,b,b,b"), &[], 9).unwrap();
+    let result = Declustered::new(9).for_pool(D, C, &pool).map(|_| ());
+    assert_eq!(result, Err(Error::DomainsDoNotFit { max: C }));
+
+    let pool = Pool::new(&[], &[1, 1, 2, 1, 1, 1], 6).unwrap();
+    let result = Rotation.for_pool(D, C, &pool).map(|_| ());
+    let layout = "rotation".to_string();
+    assert_eq!(result, Err(Error::Unweighted { layout }));
+}
+
+#[test]
+#[should_panic(expected = "needs the `Controller`")]
+fn checkpoint_refuses_declustered() {
+    let root = common::root("checkpoint_refuses_declustered");
+    let config = Config {
+        layout: Arc::new(Declustered::new(9)),
+        ..Config::default()
+    };
+    Checkpoint::<D, C, 64>::with_config(root, config);
+}
+
+// device of every chunk of the first slices, the data chunks and then the checksums, as md
+// places them on 5 devices with one checksum and on 6 devices with two
+type Table = Vec<Vec<usize>>;
+
+fn md_tables() -> Vec<(Arc<dyn Layout>, Table, Table)> {
+    vec![
+        (
+            Arc::new(Dedicated),
+            vec![vec![0, 1, 2, 3, 4]; 5],
+            vec![vec![0, 1, 2, 3, 4, 5]; 6],
+        ),
+        (
+            Arc::new(LeftSymmetric),
+            vec![
+                vec![0, 1, 2, 3, 4],
+                vec![4, 0, 1, 2, 3],
+                vec![3, 4, 0, 1, 2],
+                vec![2, 3, 4, 0, 1],
+                vec![1, 2, 3, 4, 0],
+            ],
+            vec![
+                vec![1, 2, 3, 4, 5, 0],
+                vec![0, 1, 2, 3, 4, 5],
+                vec![5, 0, 1, 2, 3, 4],
+                vec![4, 5, 0, 1, 2, 3],
+                vec![3, 4, 5, 0, 1, 2],
+                vec![2, 3, 4, 5, 0, 1],
+            ],
+        ),
+        (
+            Arc::new(LeftAsymmetric),
+            vec![
+                vec![0, 1, 2, 3, 4],
+                vec![0, 1, 2, 4, 3],
+                vec![0, 1, 3, 4, 2],
+                vec![0, 2, 3, 4, 1],
+                vec![1, 2, 3, 4, 0],
+            ],
+            vec![
+                vec![1, 2, 3, 4, 5, 0],
+                vec![0, 1, 2, 3, 4, 5],
+                vec![0, 1, 2, 5, 3, 4],
+                vec![0, 1, 4, 5, 2, 3],
+                vec![0, 3, 4, 5, 1, 2],
+                vec![2, 3, 4, 5, 0, 1],
+            ],
+        ),
+        (
+            Arc::new(RightSymmetric),
+            vec![
+                vec![1, 2, 3, 4, 0],
+                vec![2, 3, 4, 0, 1],
+                vec![3, 4, 0, 1, 2],
+                vec![4, 0, 1, 2, 3],
+                vec![0, 1, 2, 3, 4],
+            ],
+            vec![
+                vec![2, 3, 4, 5, 0, 1],
+                vec![3, 4, 5, 0, 1, 2],
+                vec![4, 5, 0, 1, 2, 3],
+                vec![5, 0, 1, 2, 3, 4],
+                vec![0, 1, 2, 3, 4, 5],
+                vec![1, 2, 3, 4, 5, 0],
+            ],
+        ),
+        (
+            Arc::new(RightAsymmetric),
+            vec![
+                vec![1, 2, 3, 4, 0],
+                vec![0, 2, 3, 4, 1],
+                vec![0, 1, 3, 4, 2],
+                vec![0, 1, 2, 4, 3],
+                vec![0, 1, 2, 3, 4],
+            ],
+            vec![
+                vec![2, 3, 4, 5, 0, 1],
+                vec![0, 3, 4, 5, 1, 2],
+                vec![0, 1, 4, 5, 2, 3],
+                vec![0, 1, 2, 5, 3, 4],
+                vec![0, 1, 2, 3, 4, 5],
+                vec![1, 2, 3, 4, 5, 0],
+            ],
+        ),
+    ]
+}
+
+#[test]
+fn md_layouts() {
+    for (layout, raid5, raid6) in md_tables() {
+        for (checks, table) in [(1, raid5), (2, raid6)] {
+            let n = D + checks;
+            // the pattern repeats after n slices
+            for data_slice in 0..3 * n {
+                for (idx, &dev_idx) in table[data_slice % n].iter().enumerate() {
+                    let name = layout.name();
+                    assert_eq!(
+                        layout.device(D, checks, data_slice, idx),
+                        dev_idx,
+                        "{name} {D}+{checks} slice {data_slice} chunk {idx}"
+                    );
+                    assert_eq!(layout.chunk(D, checks, data_slice, dev_idx), Some(idx));
+                }
+            }
+        }
+    }
+}
+
+// the chunk files are where the table puts them and the slices survive two lost devices
+#[test]
+fn md_layouts_round_trip() {
+    const X: usize = 4096;
+    for (layout, _, raid6) in md_tables() {
+        let root = common::root(&format!("md_layouts_round_trip_{}", layout.name()));
+        let config = Config {
+            layout,
+            ..Config::default()
+        };
+        let mut raid = Controller::<D, C, X>::with_config(root.clone(), config.clone());
+        let slices: Vec<[Box<[u8; X]>; D]> =
+            (0..2 * (D + C)).map(|_| common::random_slice()).collect();
+        for (data_slice, slice) in slices.iter().enumerate() {
+            raid.add_data(&common::refs(slice), data_slice);
+        }
+        raid.shutdown();
+        for data_slice in 0..slices.len() {
+            for (idx, &dev_idx) in raid6[data_slice % (D + C)].iter().enumerate() {
+                let name = match idx {
+                    idx if idx < D => format!("{data_slice}_{idx}d.bin"),
+                    idx => format!("{data_slice}_{}c.bin", idx - D),
+                };
+                let path = root.join(format!("device{dev_idx}")).join(name);
+                assert!(path.exists(), "{path:?}");
+            }
+        }
+
+        let raid = Controller::<D, C, X>::open(root, config);
+        raid.destroy_devices(&[1, 4]);
+        for (data_slice, slice) in slices.iter().enumerate() {
+            assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+        }
+        raid.shutdown();
+    }
+}
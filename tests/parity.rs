@@ -0,0 +1,158 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use raid::raid::bitmap::Bitmap;
+use raid::raid::controller::Controller;
+use raid::raid::disk::Durability;
+use raid::raid::layout::{Dedicated, Layout, Rotation};
+use raid::raid::parity::ParityChange;
+use raid::raid::{Config, RAID};
+
+const D: usize = 3;
+const C: usize = 1;
+const C2: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 20;
+
+type Slices = Vec<[Box<[u8; X]>; D]>;
+
+fn config(layout: Arc<dyn Layout>) -> Config {
+    Config {
+        layout,
+        ..Config::default()
+    }
+}
+
+fn create(root: &Path, config: &Config) -> Slices {
+    let mut raid = Controller::<D, C, X>::with_config(root.to_path_buf(), config.clone());
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.shutdown();
+    slices
+}
+
+fn check_reads(change: &ParityChange<D, C, C2, X>, slices: &Slices) {
+    for (data_slice, slice) in slices.iter().enumerate() {
+        for (data_idx, chunk) in slice.iter().enumerate() {
+            assert_eq!(
+                &change.read_data_at(data_slice, data_idx),
+                chunk,
+                "slice {data_slice}"
+            );
+        }
+    }
+}
+
+// every slice survives the loss of any two devices
+fn check(root: &Path, raid: Controller<D, C2, X>, slices: &Slices) {
+    for pair in [[0, 1], [2, 3], [4, 0]] {
+        raid.destroy_devices(&pair);
+        for (data_slice, slice) in slices.iter().enumerate() {
+            assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+        }
+    }
+    raid.shutdown();
+    let bitmap = Bitmap::open(&root.join("bitmap"), Durability::NoSync);
+    assert!(bitmap.set_slices().is_empty());
+}
+
+#[test]
+fn update_while_changing() {
+    let root = common::root("update_while_changing");
+    let config = config(Arc::new(Rotation));
+    let mut slices = create(&root, &config);
+    let change = ParityChange::<D, C, C2, X>::open(root.clone(), config.clone());
+    for _ in 0..5 {
+        change.step();
+    }
+    // slices in the new geometry, the next one and in the old geometry
+    for (data_slice, data_idx) in [(2, 0), (5, 1), (12, 2)] {
+        slices[data_slice][data_idx] = common::random_chunk();
+        change.update_data(&slices[data_slice][data_idx], data_slice, data_idx);
+    }
+    check_reads(&change, &slices);
+    assert_eq!(change.progress().done, 6);
+
+    // a crash after a step
+    drop(change);
+    let change = ParityChange::<D, C, C2, X>::open(root.clone(), config.clone());
+    check_reads(&change, &slices);
+    for _ in 0..3 {
+        change.step();
+    }
+    slices[15][0] = common::random_chunk();
+    change.update_data(&slices[15][0], 15, 0);
+    check_reads(&change, &slices);
+    check(&root, change.finish(), &slices);
+}
+
+// the crash came in the middle of moving slice 4, after the marker of slice 3 and before its
+// old chunks were removed
+#[test]
+fn half_moved_slice_is_moved_again() {
+    let root = common::root("half_moved_slice_is_moved_again");
+    let config = config(Arc::new(Rotation));
+    let mut slices = create(&root, &config);
+    let change = ParityChange::<D, C, C2, X>::open(root.clone(), config.clone());
+    for _ in 0..4 {
+        change.step();
+    }
+    drop(change);
+
+    let device = |checks: usize, data_slice: usize, data_idx: usize| {
+        let dev_idx = config.layout.device(D, checks, data_slice, data_idx);
+        root.join(format!("device{dev_idx}"))
+    };
+    // chunk 1 of slice 4 is already in its new folder
+    fs::write(device(C2, 4, 1).join("4_1d.bin"), slices[4][1].as_slice()).unwrap();
+    // chunk 1 of slice 3 is still in its old folder
+    let stale = device(C, 3, 1).join("3_1d.bin");
+    assert_ne!(device(C2, 3, 1), device(C, 3, 1));
+    fs::write(&stale, slices[3][1].as_slice()).unwrap();
+
+    let change = ParityChange::<D, C, C2, X>::open(root.clone(), config.clone());
+    assert!(!stale.exists());
+    slices[4][0] = common::random_chunk();
+    change.update_data(&slices[4][0], 4, 0);
+    check_reads(&change, &slices);
+    drop(change);
+
+    let change = ParityChange::<D, C, C2, X>::open(root.clone(), config);
+    check_reads(&change, &slices);
+    check(&root, change.finish(), &slices);
+}
+
+// the first checksum is the same with more checksums, only the second one is written
+#[test]
+fn dedicated_keeps_chunks_in_place() {
+    let root = common::root("dedicated_keeps_chunks_in_place");
+    let config = config(Arc::new(Dedicated));
+    let slices = create(&root, &config);
+    let inodes = |dev_idx: usize| -> Vec<u64> {
+        (0..SLICES)
+            .map(|data_slice| {
+                let name = match dev_idx {
+                    dev_idx if dev_idx < D => format!("{data_slice}_{dev_idx}d.bin"),
+                    _ => format!("{data_slice}_0c.bin"),
+                };
+                let path = root.join(format!("device{dev_idx}")).join(name);
+                fs::metadata(path).unwrap().ino()
+            })
+            .collect()
+    };
+    let before: Vec<_> = (0..D + C).map(inodes).collect();
+    let change = ParityChange::<D, C, C2, X>::open(root.clone(), config);
+    change.run();
+    let after: Vec<_> = (0..D + C).map(inodes).collect();
+    assert_eq!(after, before);
+    check(&root, change.finish(), &slices);
+}
@@ -0,0 +1,97 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+
+use raid::galois;
+use raid::galois::Galois;
+use raid::matrix::Matrix;
+use raid::raid::distributed::Msg;
+use raid::raid::Config;
+
+use common::head::Head;
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+// the first checksum of the chunks
+fn checksum(chunks: &[Box<[Galois; X]>]) -> Box<[Galois; X]> {
+    let v = Matrix::<C, D>::reed_solomon();
+    galois::from_fn(|i| {
+        (0..D).fold(Galois::zero(), |sum, data_idx| {
+            sum + v[0][data_idx] * chunks[data_idx][i]
+        })
+    })
+}
+
+// the checksum node restarts in the middle of a write of the whole slice, it continues
+// with the chunks it added before and drops the ones that are sent again
+#[test]
+fn partials_survive_restart() {
+    let root = common::root("partials_survive_restart");
+    let mut head = Head::<X>::bind(27440, D + C);
+    let layout = Config::default().layout;
+    let slot = layout.device(D, C, 0, D);
+    let chunks: Vec<Box<[Galois; X]>> = (0..D)
+        .map(|_| galois::from_bytes(common::random_chunk()))
+        .collect();
+    let send = |head: &Head<X>, data_idx: usize| {
+        let msg = Msg::NewDataChecksum {
+            data_slice: 0,
+            data: chunks[data_idx].clone(),
+            dev_idx: layout.device(D, C, 0, data_idx),
+            version: 1,
+        };
+        head.send(slot, msg);
+    };
+
+    let node = head.spawn(&root, D, C, slot, false);
+    send(&head, 0);
+    send(&head, 1);
+    head.shutdown(slot, node);
+
+    let node = head.spawn(&root, D, C, slot, true);
+    for data_idx in [1, 2, 3] {
+        send(&head, data_idx);
+    }
+    assert_eq!(head.read(slot, 0), checksum(&chunks));
+    head.shutdown(slot, node);
+}
+
+// a partial checksum file that was cut short is dropped, the node starts without it
+#[test]
+fn corrupt_partial_is_dropped() {
+    let root = common::root("corrupt_partial_is_dropped");
+    let mut head = Head::<X>::bind(27450, D + C);
+    let layout = Config::default().layout;
+    let slot = layout.device(D, C, 0, D);
+    let chunks: Vec<Box<[Galois; X]>> = (0..D)
+        .map(|_| galois::from_bytes(common::random_chunk()))
+        .collect();
+    let send = |head: &Head<X>, data_idx: usize, version: u64| {
+        let msg = Msg::NewDataChecksum {
+            data_slice: 0,
+            data: chunks[data_idx].clone(),
+            dev_idx: layout.device(D, C, 0, data_idx),
+            version,
+        };
+        head.send(slot, msg);
+    };
+
+    let node = head.spawn(&root, D, C, slot, false);
+    send(&head, 0, 1);
+    head.shutdown(slot, node);
+    let part = root.join(format!("node{slot}")).join("0_0c.part");
+    let bytes = fs::read(&part).unwrap();
+    fs::write(&part, &bytes[..bytes.len() / 2]).unwrap();
+
+    let node = head.spawn(&root, D, C, slot, true);
+    for data_idx in 0..D {
+        send(&head, data_idx, 2);
+    }
+    assert_eq!(head.read(slot, 0), checksum(&chunks));
+    assert!(!part.exists());
+    head.shutdown(slot, node);
+}
@@ -0,0 +1,129 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use raid::raid::cluster::Cluster;
+use raid::raid::distributed::{Checkpoint, Error};
+use raid::raid::RAID;
+
+const D: usize = 3;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 20;
+
+type Slices = Vec<[Box<[u8; X]>; D]>;
+
+fn cluster_file(root: &Path, port: u16, settings: &str) -> PathBuf {
+    let nodes: Vec<_> = (1..=D + C)
+        .map(|i| format!("127.0.0.1:{}", port + i as u16))
+        .collect();
+    let path = root.join("cluster.conf");
+    let content = format!(
+        "data_devices={D}\nchecksum_devices={C}\nchunk_size={X}\nhead=127.0.0.1:{port}\n\
+         nodes={}\n{settings}",
+        nodes.join(",")
+    );
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn spawn(root: &Path, conf: &Path, slot: usize) -> Child {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_raid-node"));
+    command.arg("--config").arg(conf);
+    command.args(["--slot", &slot.to_string()]);
+    command
+        .arg("--storage")
+        .arg(root.join(format!("node{slot}")));
+    command.spawn().unwrap()
+}
+
+// a stopped node keeps its connections but answers nothing until it goes on
+fn signal(node: &Child, signal: &str) {
+    let status = Command::new("kill")
+        .args([signal, &node.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn create(name: &str, port: u16, settings: &str) -> (Checkpoint<D, C, X>, Vec<Child>, Slices) {
+    let root = common::root(name);
+    let conf = cluster_file(&root, port, settings);
+    let nodes = (0..D + C).map(|i| spawn(&root, &conf, i)).collect();
+    let head = root.join("head");
+    fs::create_dir(&head).unwrap();
+    let config = Cluster::load(&conf).config();
+    let mut raid = Checkpoint::<D, C, X>::with_config(head, config);
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+    // the hedge needs the latencies of 32 reads
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice);
+    }
+    (raid, nodes, slices)
+}
+
+fn shutdown(raid: Checkpoint<D, C, X>, nodes: Vec<Child>) {
+    raid.shutdown();
+    for mut node in nodes {
+        assert!(node.wait().unwrap().success());
+    }
+}
+
+// node 0 has the first data chunk of slice 0, the slice is decoded after the read timeout
+#[test]
+fn slow_node_is_read_around() {
+    let settings = "read_timeout=300\nhedge=none\n";
+    let (raid, nodes, slices) = create("slow_node_is_read_around", 27480, settings);
+    signal(&nodes[0], "-STOP");
+    let start = Instant::now();
+    assert_eq!(raid.read_data(0), slices[0]);
+    assert_eq!(raid.read_data_at(0, 0), slices[0][0]);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(600), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    signal(&nodes[0], "-CONT");
+    shutdown(raid, nodes);
+}
+
+// the other nodes are asked after the usual latency of a read, long before the timeout
+#[test]
+fn hedged_read_does_not_wait_for_timeout() {
+    let settings = "read_timeout=10000\nhedge=0.9\n";
+    let (raid, nodes, slices) = create("hedged_read_does_not_wait_for_timeout", 27490, settings);
+    signal(&nodes[0], "-STOP");
+    let start = Instant::now();
+    assert_eq!(raid.read_data(0), slices[0]);
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    signal(&nodes[0], "-CONT");
+    shutdown(raid, nodes);
+}
+
+// only the two checksum nodes of slice 0 answer
+#[test]
+fn too_few_replies_are_an_error() {
+    let settings = "read_timeout=200\n";
+    let (raid, nodes, slices) = create("too_few_replies_are_an_error", 27500, settings);
+    for node in &nodes[..D] {
+        signal(node, "-STOP");
+    }
+    let result = raid.try_read_data(0);
+    assert!(matches!(result, Err(Error::Lost { data_slice: 0 })));
+    let result = raid.try_read_data_at(0, 1);
+    assert!(matches!(result, Err(Error::Lost { data_slice: 0 })));
+    for node in &nodes[..D] {
+        signal(node, "-CONT");
+    }
+    assert_eq!(raid.read_data(0), slices[0]);
+    shutdown(raid, nodes);
+}
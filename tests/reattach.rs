@@ -0,0 +1,75 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+
+use raid::raid::controller::Controller;
+use raid::raid::device;
+use raid::raid::distributed::Checkpoint;
+use raid::raid::RAID;
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+type Reattach<R> = fn(&R, usize) -> Result<usize, device::Error>;
+
+// a folder that comes back before it is reattached must not serve its stale chunks
+fn returned_folder<R: RAID<D, C, X>>(name: &str, reattach: Reattach<R>) {
+    let root = common::root(name);
+    let mut raid = R::new(root.clone());
+    let mut slices: Vec<[Box<[u8; X]>; D]> = (0..8).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+
+    let folder = root.join("device1");
+    let away = root.join("away");
+    fs::rename(&folder, &away).unwrap();
+    for data_slice in [1, 4, 6] {
+        slices[data_slice] = common::random_slice();
+        raid.add_data(&common::refs(&slices[data_slice]), data_slice);
+    }
+    raid.ping();
+    fs::rename(&away, &folder).unwrap();
+
+    // reads, full writes and updates while the old folder is back
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+    for data_slice in [2, 4] {
+        for (data_idx, chunk) in slices[data_slice].iter_mut().enumerate() {
+            *chunk = common::random_chunk();
+            raid.update_data(chunk, data_slice, data_idx);
+        }
+    }
+    raid.ping();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+
+    assert_eq!(reattach(&raid, 1).unwrap(), 4);
+    // the slices are decoded from the reattached device and the checksums only
+    raid.destroy_devices(&[0, 2]);
+    raid.ping();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+    raid.shutdown();
+}
+
+#[test]
+fn controller_returned_folder() {
+    returned_folder::<Controller<D, C, X>>("controller_returned_folder", |raid, dev_idx| {
+        raid.reattach_device(dev_idx)
+    });
+}
+
+#[test]
+fn checkpoint_returned_folder() {
+    returned_folder::<Checkpoint<D, C, X>>("checkpoint_returned_folder", |raid, dev_idx| {
+        raid.reattach_device(dev_idx)
+    });
+}
@@ -0,0 +1,101 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use raid::raid::controller::Controller;
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 200;
+
+fn create(root: &Path) -> Vec<[Box<[u8; X]>; D]> {
+    let mut raid = Controller::<D, C, X>::new(root.to_path_buf());
+    let slices: Vec<_> = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.shutdown();
+    slices
+}
+
+// a folder without its generation looks like an interrupted rebuild on open
+fn interrupt_rebuild(root: &Path, dev_idx: usize) {
+    fs::remove_file(root.join(format!("device{dev_idx}")).join("generation")).unwrap();
+}
+
+// slow enough that the rebuild is still running when the array is shut down
+fn slow_rebuild() -> Config {
+    Config {
+        rebuild_bandwidth: Some(((D + 1) * X * SLICES / 2) as u64),
+        ..Config::default()
+    }
+}
+
+// the slices can only be read with the two devices
+fn check_devices(raid: &Controller<D, C, X>, keep: [usize; 2], slices: &[[Box<[u8; X]>; D]]) {
+    let lost: Vec<_> = (0..D + C).filter(|i| !keep.contains(i)).take(C).collect();
+    raid.destroy_devices(&lost);
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+}
+
+#[test]
+fn shutdown_waits_for_rebuild() {
+    let root = common::root("shutdown_waits_for_rebuild");
+    create(&root);
+    interrupt_rebuild(&root, 2);
+    let raid = Controller::<D, C, X>::open(root.clone(), slow_rebuild());
+    assert_eq!(raid.rebuild_progress().len(), 1);
+    raid.shutdown();
+    assert!(root.join("device2").join("generation").exists());
+    assert!(!root.join("device2.rebuild").exists());
+}
+
+#[test]
+fn cancel_before_shutdown() {
+    let root = common::root("cancel_before_shutdown");
+    let slices = create(&root);
+    interrupt_rebuild(&root, 2);
+    let raid = Controller::<D, C, X>::open(root.clone(), slow_rebuild());
+    raid.cancel_rebuilds();
+    raid.shutdown();
+    assert!(!root.join("device2").join("generation").exists());
+
+    // the rebuild continues on the next open
+    let raid = Controller::<D, C, X>::open(root, Config::default());
+    raid.wait_for_rebuild();
+    check_devices(&raid, [2, 3], &slices);
+    raid.shutdown();
+}
+
+#[test]
+fn corrupt_checkpoint_restarts() {
+    let root = common::root("corrupt_checkpoint_restarts");
+    let slices = create(&root);
+    interrupt_rebuild(&root, 2);
+    fs::write(root.join("device2.rebuild"), "garbage").unwrap();
+    let raid = Controller::<D, C, X>::open(root, Config::default());
+    raid.wait_for_rebuild();
+    check_devices(&raid, [2, 3], &slices);
+    raid.shutdown();
+}
+
+#[test]
+fn missing_devices_rebuilt_together() {
+    let root = common::root("missing_devices_rebuilt_together");
+    let slices = create(&root);
+    let raid = Controller::<D, C, X>::open(root, Config::default());
+    raid.remove_device(1);
+    raid.remove_device(4);
+    raid.construct_missing_devices();
+    assert!(raid.rebuild_progress().is_empty());
+    check_devices(&raid, [1, 4], &slices);
+    raid.shutdown();
+}
@@ -0,0 +1,84 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use raid::raid::distributed::{Checkpoint, Repair};
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 64;
+
+type Slices = Vec<[Box<[u8; X]>; D]>;
+
+fn create(root: &Path, repair: Repair) -> (Checkpoint<D, C, X>, Slices) {
+    let config = Config {
+        repair,
+        ..Config::default()
+    };
+    let mut raid = Checkpoint::<D, C, X>::with_config(root.to_path_buf(), config);
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+    (raid, slices)
+}
+
+fn check(raid: &Checkpoint<D, C, X>, slices: &Slices) {
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+}
+
+// the node gets more replies than it needs, the late ones must not end up in the next
+// recovery of the same slices
+fn recover_twice(name: &str, repair: Repair) {
+    let root = common::root(name);
+    let (raid, mut slices) = create(&root, repair);
+    raid.destroy_devices(&[0]);
+    for (data_slice, slice) in slices.iter_mut().enumerate() {
+        for (data_idx, chunk) in slice.iter_mut().enumerate() {
+            *chunk = common::random_chunk();
+            raid.update_data(chunk, data_slice, data_idx);
+        }
+    }
+    raid.ping();
+    raid.destroy_devices(&[0]);
+    raid.ping();
+    // node 0 and the checksums of two other nodes
+    raid.destroy_devices(&[1, 2]);
+    raid.ping();
+    check(&raid, &slices);
+    raid.shutdown();
+}
+
+#[test]
+fn recover_twice_direct() {
+    recover_twice("recover_twice_direct", Repair::Direct);
+}
+
+#[test]
+fn recover_twice_pipelined() {
+    recover_twice("recover_twice_pipelined", Repair::Pipelined);
+}
+
+// a node that lost its folder does not answer, the others are enough
+#[test]
+fn silent_node() {
+    let root = common::root("silent_node");
+    let (raid, slices) = create(&root, Repair::Direct);
+    fs::remove_dir_all(root.join("device1")).unwrap();
+    raid.destroy_devices(&[0]);
+    raid.ping();
+    // node 0 and the checksums of the nodes left
+    raid.destroy_devices(&[2]);
+    raid.ping();
+    check(&raid, &slices);
+    raid.shutdown();
+}
@@ -0,0 +1,76 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use raid::raid::distributed::{Checkpoint, Repair};
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 24;
+
+type Slices = Vec<[Box<[u8; X]>; D]>;
+
+fn create(root: &Path, repair: Repair, slices: &Slices) -> Checkpoint<D, C, X> {
+    let config = Config {
+        repair,
+        ..Config::default()
+    };
+    let mut raid = Checkpoint::<D, C, X>::with_config(root.to_path_buf(), config);
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+    raid
+}
+
+// the chunks of the device by file name
+fn chunks(root: &Path, dev_idx: usize) -> BTreeMap<String, Vec<u8>> {
+    let device = root.join(format!("device{dev_idx}"));
+    fs::read_dir(&device)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".bin"))
+        .map(|name| (name.clone(), fs::read(device.join(name)).unwrap()))
+        .collect()
+}
+
+// node 0 has the first data chunk of slice 0, node 4 the first checksum. The helpers sum
+// up the chunks of both with the coefficients of the rebuilt node
+#[test]
+fn pipelined_equals_direct() {
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    let mut rebuilt = vec![];
+    for repair in [Repair::Direct, Repair::Pipelined] {
+        let root = common::root(&format!("pipelined_equals_direct_{repair:?}"));
+        let raid = create(&root, repair, &slices);
+        let lost = [chunks(&root, 0), chunks(&root, D)];
+        raid.destroy_devices(&[0, D]);
+        raid.wait_for_rebuild();
+        assert_eq!([chunks(&root, 0), chunks(&root, D)], lost, "{repair:?}");
+        raid.shutdown();
+        rebuilt.push(lost);
+    }
+    assert_eq!(rebuilt[0], rebuilt[1]);
+}
+
+// a helper that can not read its chunk makes the rebuilt node ask the others directly
+#[test]
+fn repair_failed_falls_back() {
+    let root = common::root("repair_failed_falls_back");
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    let raid = create(&root, Repair::Pipelined, &slices);
+    let lost = chunks(&root, 0);
+    // node 1 has the second data chunk of slice 0, the first helper
+    fs::write(root.join("device1").join("0_1d.bin"), [0]).unwrap();
+    raid.destroy_devices(&[0]);
+    raid.wait_for_rebuild();
+    assert_eq!(chunks(&root, 0), lost);
+    raid.shutdown();
+}
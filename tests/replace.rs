@@ -0,0 +1,97 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use raid::raid::controller::Controller;
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 100;
+
+fn write_slices(raid: &mut Controller<D, C, X>) -> Vec<[Box<[u8; X]>; D]> {
+    let slices: Vec<_> = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    slices
+}
+
+// copy the chunks of the slices before `until` like an interrupted replace did
+fn copy_chunks(from: &Path, to: &Path, until: usize) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let Some((data_slice, _)) = name.split_once('_') else {
+            continue;
+        };
+        if data_slice.parse::<usize>().unwrap() < until {
+            fs::copy(&path, to.join(&name)).unwrap();
+        }
+    }
+}
+
+// the slices can only be read with the replaced device and the checksums
+fn check_replaced(raid: &Controller<D, C, X>, slices: &[[Box<[u8; X]>; D]]) {
+    raid.destroy_devices(&[0, 2]);
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+}
+
+#[test]
+fn crash_while_switching() {
+    let root = common::root("crash_while_switching");
+    let mut raid = Controller::<D, C, X>::new(root.clone());
+    let slices = write_slices(&mut raid);
+    raid.shutdown();
+
+    // the copy is complete, the old folder was moved away but the link not renamed yet
+    let target = root.join("new1");
+    copy_chunks(&root.join("device1"), &target, SLICES);
+    fs::write(
+        root.join("device1.copy"),
+        format!("{SLICES}\n{}\n", target.display()),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(&target, root.join("device1.link")).unwrap();
+    fs::rename(root.join("device1"), root.join("device1.replaced")).unwrap();
+
+    let raid = Controller::<D, C, X>::open(root.clone(), Config::default());
+    assert_eq!(fs::read_link(root.join("device1")).unwrap(), target);
+    assert!(!root.join("device1.link").exists());
+    assert!(!root.join("device1.copy").exists());
+    check_replaced(&raid, &slices);
+    raid.shutdown();
+}
+
+#[test]
+fn continue_interrupted_copy() {
+    let root = common::root("continue_interrupted_copy");
+    let mut raid = Controller::<D, C, X>::new(root.clone());
+    let slices = write_slices(&mut raid);
+    raid.shutdown();
+
+    // half of the slices were copied before the crash
+    let target = root.join("new1");
+    copy_chunks(&root.join("device1"), &target, SLICES / 2);
+    fs::write(
+        root.join("device1.copy"),
+        format!("{}\n{}\n", SLICES / 2, target.display()),
+    )
+    .unwrap();
+    // a copied slice that is copied again would get the broken chunk
+    fs::write(root.join("device1").join("0_1d.bin"), vec![0; X]).unwrap();
+
+    let raid = Controller::<D, C, X>::open(root.clone(), Config::default());
+    raid.replace_device(1, target.clone());
+    assert_eq!(fs::read_link(root.join("device1")).unwrap(), target);
+    check_replaced(&raid, &slices);
+    raid.shutdown();
+}
@@ -0,0 +1,91 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use raid::raid::bitmap::Bitmap;
+use raid::raid::controller::Controller;
+use raid::raid::disk::Durability;
+use raid::raid::reshape::Reshape;
+use raid::raid::{Config, RAID};
+
+const D: usize = 3;
+const D2: usize = 5;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 30;
+
+// the data as one sequence of chunks, see `Reshape`
+fn create(root: &Path) -> Vec<Box<[u8; X]>> {
+    let mut raid = Controller::<D, C, X>::new(root.to_path_buf());
+    let mut chunks = vec![];
+    for data_slice in 0..SLICES {
+        let slice = common::random_slice::<D, X>();
+        raid.add_data(&common::refs(&slice), data_slice);
+        chunks.extend(slice);
+    }
+    raid.shutdown();
+    chunks
+}
+
+fn bitmap(root: &Path) -> Bitmap {
+    Bitmap::open(&root.join("bitmap"), Durability::NoSync)
+}
+
+// every chunk, also when it is decoded from the checksums
+fn check(root: &Path, raid: Controller<D2, C, X>, chunks: &[Box<[u8; X]>]) {
+    for pair in [[0, 1], [2, 3], [4, 5], [6, 0]] {
+        raid.destroy_devices(&pair);
+        for (n, chunk) in chunks.iter().enumerate() {
+            assert_eq!(&raid.read_data_at(n / D2, n % D2), chunk, "chunk {n}");
+        }
+    }
+    raid.shutdown();
+    assert!(bitmap(root).set_slices().is_empty());
+}
+
+#[test]
+fn write_while_reshaping() {
+    let root = common::root("write_while_reshaping");
+    let mut chunks = create(&root);
+    let reshape = Reshape::<D, D2, C, X>::open(root.clone(), Config::default());
+    for _ in 0..5 {
+        reshape.step();
+    }
+    // chunks in the new and in the old geometry
+    for n in [0, 7, 24, 25, 60, 89] {
+        chunks[n] = common::random_chunk();
+        reshape.write_chunk(n, &chunks[n]);
+        assert!(bitmap(&root).set_slices().is_empty());
+    }
+    check(&root, reshape.finish(), &chunks);
+}
+
+// the data chunk is written, the checksums are not
+#[test]
+fn interrupted_write_is_resynced() {
+    let root = common::root("interrupted_write_is_resynced");
+    let mut chunks = create(&root);
+    let reshape = Reshape::<D, D2, C, X>::open(root.clone(), Config::default());
+    for _ in 0..5 {
+        reshape.step();
+    }
+    drop(reshape);
+
+    // chunk 1 of slice 2 in the new geometry
+    let n = 2 * D2 + 1;
+    chunks[n] = common::random_chunk();
+    let file = (0..D2 + C)
+        .map(|i| root.join(format!("device{i}")).join("2_1d.bin"))
+        .find(|file| file.exists())
+        .unwrap();
+    fs::write(file, chunks[n].as_slice()).unwrap();
+    bitmap(&root).set(2);
+
+    let reshape = Reshape::<D, D2, C, X>::open(root.clone(), Config::default());
+    assert!(bitmap(&root).set_slices().is_empty());
+    check(&root, reshape.finish(), &chunks);
+}
@@ -0,0 +1,88 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::path::Path;
+use std::thread::JoinHandle;
+
+use raid::raid::distributed::{Checkpoint, Msg, Node, NodeState, Repair};
+use raid::raid::transport;
+use raid::raid::transport::{Packet, Peers};
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+fn config(port: u16) -> Config {
+    Config {
+        peers: Some(Peers {
+            head: format!("127.0.0.1:{port}").parse().unwrap(),
+            nodes: (1..=D + C)
+                .map(|i| format!("127.0.0.1:{}", port + i as u16).parse().unwrap())
+                .collect(),
+        }),
+        repair: Repair::Pipelined,
+        ..Config::default()
+    }
+}
+
+// the nodes over TCP, each with its own view of the states
+fn serve(root: &Path, config: &Config) -> Vec<JoinHandle<()>> {
+    (0..D + C)
+        .map(|dev_idx| {
+            let path = root.join(format!("node{dev_idx}"));
+            let config = config.clone();
+            std::thread::spawn(move || {
+                Node::<D, C, X>::serve(path, dev_idx, &config, false).unwrap();
+            })
+        })
+        .collect()
+}
+
+// both nodes rebuild at once, each one must only sum up the chunks of the survivors
+#[test]
+fn nodes_learn_states() {
+    let root = common::root("nodes_learn_states");
+    let config = config(27300);
+    let nodes = serve(&root, &config);
+    let head = root.join("head");
+    std::fs::create_dir(&head).unwrap();
+    let mut raid = Checkpoint::<D, C, X>::with_config(head, config);
+    let slices: Vec<[Box<[u8; X]>; D]> = (0..32).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    raid.ping();
+    raid.destroy_devices(&[0, 1]);
+    raid.ping();
+    // only the rebuilt nodes and two others are left
+    raid.destroy_devices(&[2, 3]);
+    raid.ping();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice, "slice {data_slice}");
+    }
+    raid.shutdown();
+    for node in nodes {
+        node.join().unwrap();
+    }
+}
+
+#[test]
+fn state_over_tcp() {
+    for state in [
+        NodeState::Healthy,
+        NodeState::Rebuilding,
+        NodeState::Failed,
+        NodeState::Down,
+    ] {
+        let body = transport::encode::<X>(&Packet::Msg(Msg::State { dev_idx: 3, state }));
+        match transport::decode::<X>(&body).unwrap() {
+            Packet::Msg(Msg::State {
+                dev_idx: 3,
+                state: decoded,
+            }) => assert_eq!(decoded, state),
+            packet => panic!("{packet:?}"),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use raid::raid::controller::Controller;
+use raid::raid::uring::UringController;
+use raid::raid::{Config, RAID};
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+const SLICES: usize = 8;
+
+type Slices = Vec<[Box<[u8; X]>; D]>;
+
+fn chunk_path(root: &Path, data_slice: usize, data_idx: usize) -> PathBuf {
+    let dev_idx = Config::default().layout.device(D, C, data_slice, data_idx);
+    root.join(format!("device{dev_idx}"))
+        .join(format!("{data_slice}_{data_idx}d.bin"))
+}
+
+fn tmp_files(root: &Path) -> usize {
+    (0..D + C)
+        .flat_map(|dev_idx| fs::read_dir(root.join(format!("device{dev_idx}"))).unwrap())
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("tmp".as_ref()))
+        .count()
+}
+
+#[test]
+fn round_trip() {
+    let root = common::root("uring_round_trip");
+    let mut raid = UringController::<D, C, X>::new(root.clone());
+    let slices: Slices = (0..SLICES).map(|_| common::random_slice()).collect();
+    for (data_slice, slice) in slices.iter().enumerate() {
+        raid.add_data(&common::refs(slice), data_slice);
+    }
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice);
+        assert_eq!(&raid.read_data_at(data_slice, 2), &slice[2]);
+    }
+    raid.shutdown();
+    assert_eq!(tmp_files(&root), 0);
+
+    // the same files as the controller, checksums included
+    let raid = Controller::<D, C, X>::open(root, Config::default());
+    raid.destroy_devices(&[0, 1]);
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice);
+    }
+    raid.shutdown();
+}
+
+// a pipe hands out the chunk in two parts, the read of the second one is submitted again
+#[test]
+fn short_read_is_submitted_again() {
+    let root = common::root("uring_short_read_is_submitted_again");
+    let mut raid = UringController::<D, C, X>::new(root.clone());
+    let slice = common::random_slice();
+    raid.add_data(&common::refs(&slice), 0);
+    raid.ping();
+
+    let file_path = chunk_path(&root, 0, 1);
+    fs::remove_file(&file_path).unwrap();
+    let status = Command::new("mkfifo").arg(&file_path).status().unwrap();
+    assert!(status.success());
+    let chunk = slice[1].clone();
+    let writer = thread::spawn(move || {
+        let mut fifo = File::options().write(true).open(file_path).unwrap();
+        fifo.write_all(&chunk[..X / 2]).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        fifo.write_all(&chunk[X / 2..]).unwrap();
+    });
+    // a fallback to the controller would wait for a writer of the pipe forever
+    assert_eq!(raid.read_data(0), slice);
+    writer.join().unwrap();
+    assert!(!root.join("device1.failed").exists());
+}
+
+// a write of the uring fails with ENOSPC, the controller writes the slice again
+#[test]
+fn failed_write_falls_back() {
+    let root = common::root("uring_failed_write_falls_back");
+    let mut raid = UringController::<D, C, X>::new(root.clone());
+    let slices: Slices = (0..2).map(|_| common::random_slice()).collect();
+    raid.add_data(&common::refs(&slices[0]), 0);
+    raid.ping();
+
+    let tmp_path = chunk_path(&root, 1, 0).with_extension("tmp");
+    symlink("/dev/full", &tmp_path).unwrap();
+    raid.add_data(&common::refs(&slices[1]), 1);
+    raid.ping();
+    assert!(fs::symlink_metadata(&tmp_path).is_err());
+    assert_eq!(tmp_files(&root), 0);
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice);
+    }
+    raid.shutdown();
+
+    let raid = Controller::<D, C, X>::open(root, Config::default());
+    raid.destroy_devices(&[2, 3]);
+    for (data_slice, slice) in slices.iter().enumerate() {
+        assert_eq!(&raid.read_data(data_slice), slice);
+    }
+    raid.shutdown();
+}
@@ -0,0 +1,180 @@
+#![feature(generic_const_exprs)]
+
+mod common;
+
+use std::fs;
+
+use raid::galois;
+use raid::galois::Galois;
+use raid::matrix::Matrix;
+use raid::raid::distributed::Msg;
+use raid::raid::Config;
+
+use common::head::Head;
+
+const D: usize = 4;
+const C: usize = 2;
+const X: usize = 4096;
+
+// the node of the first checksum of slice 0 and the nodes of its data chunks
+fn slots() -> (usize, [usize; D]) {
+    let layout = Config::default().layout;
+    let data_slots = core::array::from_fn(|data_idx| layout.device(D, C, 0, data_idx));
+    (layout.device(D, C, 0, D), data_slots)
+}
+
+fn random() -> Box<[Galois; X]> {
+    galois::from_bytes(common::random_chunk())
+}
+
+// the first checksum of the chunks, given by their data idx
+fn checksum(chunks: &[(usize, &[Galois; X])]) -> Box<[Galois; X]> {
+    let v = Matrix::<C, D>::reed_solomon();
+    galois::from_fn(|i| {
+        chunks
+            .iter()
+            .fold(Galois::zero(), |sum, (data_idx, chunk)| {
+                sum + v[0][*data_idx] * chunk[i]
+            })
+    })
+}
+
+// a write and an update that are sent twice, the repeats must not be added again
+#[test]
+fn duplicate_write_is_dropped() {
+    let root = common::root("duplicate_write_is_dropped");
+    let mut head = Head::<X>::bind(27420, D + C);
+    let (slot, data_slots) = slots();
+    let node = head.spawn(&root, D, C, slot, false);
+    let chunks: Vec<_> = (0..D).map(|_| random()).collect();
+    // the checksum would be written once D chunks are counted
+    for data_idx in [0, 1, 0, 2, 1, 3] {
+        let msg = Msg::NewDataChecksum {
+            data_slice: 0,
+            data: chunks[data_idx].clone(),
+            dev_idx: data_slots[data_idx],
+            version: 1,
+        };
+        head.send(slot, msg);
+    }
+    let diff = random();
+    for _ in 0..2 {
+        let msg = Msg::UpdateDataChecksum {
+            data_slice: 0,
+            diff: diff.clone(),
+            dev_idx: data_slots[2],
+            version: 2,
+            epoch: 1,
+        };
+        head.send(slot, msg);
+    }
+    let mut expected: Vec<_> = chunks.iter().map(|chunk| &**chunk).enumerate().collect();
+    expected.push((2, &*diff));
+    assert_eq!(head.read(slot, 0), checksum(&expected));
+    head.shutdown(slot, node);
+}
+
+// an update overtakes the checksum of the aggregated write it builds on, it waits for it
+#[test]
+fn missing_epoch_is_awaited() {
+    let root = common::root("missing_epoch_is_awaited");
+    let mut head = Head::<X>::bind(27430, D + C);
+    let (slot, data_slots) = slots();
+    let node = head.spawn(&root, D, C, slot, false);
+    let diff = random();
+    head.send(
+        slot,
+        Msg::UpdateDataChecksum {
+            data_slice: 0,
+            diff: diff.clone(),
+            dev_idx: data_slots[1],
+            version: 3,
+            epoch: 2,
+        },
+    );
+    let written = random();
+    head.send(
+        slot,
+        Msg::NewChecksum {
+            data_slice: 0,
+            checksum: written.clone(),
+            version: 2,
+        },
+    );
+    let delta = checksum(&[(1, &*diff)]);
+    let expected = galois::from_fn(|i| written[i] + delta[i]);
+    assert_eq!(head.read(slot, 0), expected);
+    head.shutdown(slot, node);
+}
+
+// an update overtakes the chunks of the other nodes of the write it builds on, it is added
+// to the unfinished checksum
+#[test]
+fn update_overtakes_unfinished_write() {
+    let root = common::root("update_overtakes_unfinished_write");
+    let mut head = Head::<X>::bind(27510, D + C);
+    let (slot, data_slots) = slots();
+    let node = head.spawn(&root, D, C, slot, false);
+    let chunks: Vec<_> = (0..D).map(|_| random()).collect();
+    let diff = random();
+    let new_chunk = |data_idx: usize| Msg::NewDataChecksum {
+        data_slice: 0,
+        data: chunks[data_idx].clone(),
+        dev_idx: data_slots[data_idx],
+        version: 2,
+    };
+    head.send(slot, new_chunk(1));
+    head.send(
+        slot,
+        Msg::UpdateDataChecksum {
+            data_slice: 0,
+            diff: diff.clone(),
+            dev_idx: data_slots[1],
+            version: 3,
+            epoch: 2,
+        },
+    );
+    for data_idx in [0, 2, 3] {
+        head.send(slot, new_chunk(data_idx));
+    }
+    let mut expected: Vec<_> = chunks.iter().map(|chunk| &**chunk).enumerate().collect();
+    expected.push((1, &*diff));
+    assert_eq!(head.read(slot, 0), checksum(&expected));
+    head.shutdown(slot, node);
+}
+
+// a torn checksum can not be updated, the node leaves it out instead of stopping. Slice
+// D + C has its chunks on the same nodes as slice 0
+#[test]
+fn torn_checksum_is_left_out() {
+    let root = common::root("torn_checksum_is_left_out");
+    let mut head = Head::<X>::bind(27460, D + C);
+    let (slot, data_slots) = slots();
+    let node = head.spawn(&root, D, C, slot, false);
+    for data_slice in [0, D + C] {
+        for dev_idx in data_slots {
+            let msg = Msg::NewDataChecksum {
+                data_slice,
+                data: random(),
+                dev_idx,
+                version: 1,
+            };
+            head.send(slot, msg);
+        }
+    }
+    let written = head.read(slot, D + C);
+    fs::write(root.join(format!("node{slot}")).join("0_0c.bin"), [0]).unwrap();
+    for data_slice in [0, D + C] {
+        let msg = Msg::UpdateDataChecksum {
+            data_slice,
+            diff: random(),
+            dev_idx: data_slots[0],
+            version: 2,
+            epoch: 1,
+        };
+        head.send(slot, msg);
+    }
+    assert!(head.try_read(slot, 0).is_none());
+    assert_ne!(head.read(slot, D + C), written);
+    head.shutdown(slot, node);
+}